@@ -0,0 +1,302 @@
+//! Minimal AES-128 implementation (FIPS-197) with CBC/PKCS#7 helpers, as used
+//! by `#EXT-X-KEY:METHOD=AES-128` HLS streams.
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+fn inv_sbox() -> [u8; 256] {
+    let mut inv = [0u8; 256];
+    for (i, &v) in SBOX.iter().enumerate() {
+        inv[v as usize] = i as u8;
+    }
+    inv
+}
+
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            p ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    p
+}
+
+/// Expanded AES-128 key schedule.
+pub struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+    inv_sbox: [u8; 256],
+}
+
+impl Aes128 {
+    pub fn new(key: &[u8; 16]) -> Self {
+        let mut w = [[0u8; 4]; 44];
+        for (i, word) in w.iter_mut().take(4).enumerate() {
+            word.copy_from_slice(&key[i * 4..i * 4 + 4]);
+        }
+        for i in 4..44 {
+            let mut temp = w[i - 1];
+            if i % 4 == 0 {
+                temp.rotate_left(1);
+                for b in temp.iter_mut() {
+                    *b = SBOX[*b as usize];
+                }
+                temp[0] ^= RCON[i / 4 - 1];
+            }
+            for j in 0..4 {
+                w[i][j] = w[i - 4][j] ^ temp[j];
+            }
+        }
+        let mut round_keys = [[0u8; 16]; 11];
+        for (r, rk) in round_keys.iter_mut().enumerate() {
+            for c in 0..4 {
+                rk[c * 4..c * 4 + 4].copy_from_slice(&w[r * 4 + c]);
+            }
+        }
+        Self { round_keys, inv_sbox: inv_sbox() }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; 16]) {
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..10 {
+            sub_bytes(block, &SBOX);
+            shift_rows(block);
+            mix_columns(block);
+            add_round_key(block, &self.round_keys[round]);
+        }
+        sub_bytes(block, &SBOX);
+        shift_rows(block);
+        add_round_key(block, &self.round_keys[10]);
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; 16]) {
+        add_round_key(block, &self.round_keys[10]);
+        for round in (1..10).rev() {
+            inv_shift_rows(block);
+            sub_bytes(block, &self.inv_sbox);
+            add_round_key(block, &self.round_keys[round]);
+            inv_mix_columns(block);
+        }
+        inv_shift_rows(block);
+        sub_bytes(block, &self.inv_sbox);
+        add_round_key(block, &self.round_keys[0]);
+    }
+
+    /// Encrypts `data` in CBC mode, appending PKCS#7 padding.
+    pub fn cbc_encrypt(&self, iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+        let pad = 16 - data.len() % 16;
+        let mut out = Vec::with_capacity(data.len() + pad);
+        out.extend_from_slice(data);
+        out.extend(std::iter::repeat_n(pad as u8, pad));
+
+        let mut prev = *iv;
+        for chunk in out.chunks_exact_mut(16) {
+            let mut block = [0u8; 16];
+            for i in 0..16 {
+                block[i] = chunk[i] ^ prev[i];
+            }
+            self.encrypt_block(&mut block);
+            chunk.copy_from_slice(&block);
+            prev = block;
+        }
+        out
+    }
+
+}
+
+/// CBC decryption of data that arrives in pieces, stripping PKCS#7 padding.
+/// The last block received is held back until [`CbcDecryptor::finish`],
+/// since only the end tells it apart.
+pub struct CbcDecryptor {
+    cipher: Arc<Aes128>,
+    /// The ciphertext block before the next one.
+    prev: [u8; 16],
+    /// Received but not decrypted yet: the last block and any partial one.
+    pending: Vec<u8>,
+    received: u64,
+}
+
+impl CbcDecryptor {
+    pub fn new(cipher: Arc<Aes128>, iv: &[u8; 16]) -> Self {
+        Self { cipher, prev: *iv, pending: Vec::new(), received: 0 }
+    }
+
+    /// Takes `data` and returns what can be decrypted so far.
+    pub fn update(&mut self, data: &[u8]) -> Vec<u8> {
+        self.received += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        let ready = self.pending.len().saturating_sub(1) / 16 * 16;
+        let mut out: Vec<u8> = self.pending.drain(..ready).collect();
+        self.decrypt(&mut out);
+        out
+    }
+
+    /// The rest of the plaintext, without the padding.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        if self.received == 0 || self.pending.len() != 16 {
+            return Err(anyhow!("Encrypted segment length {} is not a multiple of the AES block size", self.received));
+        }
+        let mut out = std::mem::take(&mut self.pending);
+        self.decrypt(&mut out);
+        let pad = out[15] as usize;
+        if pad == 0 || pad > 16 || out[16 - pad..].iter().any(|&b| b as usize != pad) {
+            return Err(anyhow!("Invalid PKCS#7 padding (wrong key or IV?)"));
+        }
+        out.truncate(16 - pad);
+        Ok(out)
+    }
+
+    /// Decrypts whole blocks in place.
+    fn decrypt(&mut self, data: &mut [u8]) {
+        for chunk in data.chunks_exact_mut(16) {
+            let mut block = [0u8; 16];
+            block.copy_from_slice(chunk);
+            let cipher = block;
+            self.cipher.decrypt_block(&mut block);
+            for i in 0..16 {
+                chunk[i] = block[i] ^ self.prev[i];
+            }
+            self.prev = cipher;
+        }
+    }
+}
+
+fn add_round_key(state: &mut [u8; 16], key: &[u8; 16]) {
+    for (s, k) in state.iter_mut().zip(key) {
+        *s ^= k;
+    }
+}
+
+fn sub_bytes(state: &mut [u8; 16], table: &[u8; 256]) {
+    for b in state.iter_mut() {
+        *b = table[*b as usize];
+    }
+}
+
+// The state is stored column-major: byte `r + 4c` is row r, column c.
+fn shift_rows(s: &mut [u8; 16]) {
+    let t = *s;
+    for r in 1..4 {
+        for c in 0..4 {
+            s[r + 4 * c] = t[r + 4 * ((c + r) % 4)];
+        }
+    }
+}
+
+fn inv_shift_rows(s: &mut [u8; 16]) {
+    let t = *s;
+    for r in 1..4 {
+        for c in 0..4 {
+            s[r + 4 * ((c + r) % 4)] = t[r + 4 * c];
+        }
+    }
+}
+
+fn mix_columns(s: &mut [u8; 16]) {
+    for c in 0..4 {
+        let col = [s[4 * c], s[4 * c + 1], s[4 * c + 2], s[4 * c + 3]];
+        s[4 * c] = xtime(col[0]) ^ xtime(col[1]) ^ col[1] ^ col[2] ^ col[3];
+        s[4 * c + 1] = col[0] ^ xtime(col[1]) ^ xtime(col[2]) ^ col[2] ^ col[3];
+        s[4 * c + 2] = col[0] ^ col[1] ^ xtime(col[2]) ^ xtime(col[3]) ^ col[3];
+        s[4 * c + 3] = xtime(col[0]) ^ col[0] ^ col[1] ^ col[2] ^ xtime(col[3]);
+    }
+}
+
+fn inv_mix_columns(s: &mut [u8; 16]) {
+    for c in 0..4 {
+        let col = [s[4 * c], s[4 * c + 1], s[4 * c + 2], s[4 * c + 3]];
+        s[4 * c] = gmul(col[0], 14) ^ gmul(col[1], 11) ^ gmul(col[2], 13) ^ gmul(col[3], 9);
+        s[4 * c + 1] = gmul(col[0], 9) ^ gmul(col[1], 14) ^ gmul(col[2], 11) ^ gmul(col[3], 13);
+        s[4 * c + 2] = gmul(col[0], 13) ^ gmul(col[1], 9) ^ gmul(col[2], 14) ^ gmul(col[3], 11);
+        s[4 * c + 3] = gmul(col[0], 11) ^ gmul(col[1], 13) ^ gmul(col[2], 9) ^ gmul(col[3], 14);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn block(s: &str) -> [u8; 16] {
+        hex(s).try_into().unwrap()
+    }
+
+    // NIST SP 800-38A, F.2.1 and F.2.2 (CBC-AES128).
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const IV: &str = "000102030405060708090a0b0c0d0e0f";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+                             30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710";
+    const CIPHERTEXT: &str = "7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2\
+                              73bed6b8e3c1743b7116e69e222295163ff1caa1681fac09120eca307586e1a7";
+
+    #[test]
+    fn sp_800_38a_encrypt() {
+        let cipher = Aes128::new(&block(KEY));
+        let out = cipher.cbc_encrypt(&block(IV), &hex(PLAINTEXT));
+        // The vectors have no padding; ours adds a whole block of it.
+        assert_eq!(out.len(), 80);
+        assert_eq!(out[..64], hex(CIPHERTEXT)[..]);
+    }
+
+    #[test]
+    fn sp_800_38a_decrypt() {
+        let cipher = Arc::new(Aes128::new(&block(KEY)));
+        let encrypted = cipher.cbc_encrypt(&block(IV), &hex(PLAINTEXT));
+        let mut decryptor = CbcDecryptor::new(cipher, &block(IV));
+        let mut out = Vec::new();
+        for piece in encrypted.chunks(7) {
+            out.extend(decryptor.update(piece));
+        }
+        out.extend(decryptor.finish().unwrap());
+        assert_eq!(out, hex(PLAINTEXT));
+    }
+
+    #[test]
+    fn bad_padding_is_an_error() {
+        // The first block alone decrypts to plaintext that ends in 0x2a,
+        // which is not a padding length.
+        let cipher = Arc::new(Aes128::new(&block(KEY)));
+        let mut decryptor = CbcDecryptor::new(cipher, &block(IV));
+        assert!(decryptor.update(&hex(CIPHERTEXT)[..16]).is_empty());
+        let error = decryptor.finish().unwrap_err();
+        assert!(error.to_string().contains("PKCS#7"), "unexpected error: {}", error);
+    }
+
+    #[test]
+    fn partial_block_is_an_error() {
+        let cipher = Arc::new(Aes128::new(&block(KEY)));
+        let mut decryptor = CbcDecryptor::new(cipher, &block(IV));
+        decryptor.update(&hex(CIPHERTEXT)[..20]);
+        assert!(decryptor.finish().is_err());
+    }
+}
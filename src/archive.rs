@@ -0,0 +1,83 @@
+//! `--download-archive FILE`: the lessons and playlists that `batch` and
+//! `course` have downloaded, one per line, so that a later run skips them
+//! even when the outputs have been moved or renamed since. Lessons are keyed
+//! by school and lesson ID (`lesson school.example 123`), other URLs by the
+//! playlist without its query (`playlist cdn.example/2/master.m3u8`), whose
+//! access tokens change between runs.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use url::Url;
+
+pub struct Archive {
+    path: PathBuf,
+    /// Flush each entry to disk as it is added.
+    sync: bool,
+    keys: Mutex<HashSet<String>>,
+}
+
+impl Archive {
+    /// Reads the archive at `path`; a missing file is an empty archive.
+    pub fn open(path: &Path, sync: bool) -> Result<Self> {
+        let keys = match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read the download archive {}", path.display())),
+        };
+        Ok(Self { path: path.to_path_buf(), sync, keys: Mutex::new(keys) })
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.lock().unwrap().contains(key)
+    }
+
+    /// Records `key`, appending it to the file unless it is there already.
+    pub fn add(&self, key: &str) -> Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.contains(key) {
+            return Ok(());
+        }
+        let appended = (|| {
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            writeln!(file, "{}", key)?;
+            if self.sync {
+                file.sync_all()?;
+            }
+            std::io::Result::Ok(())
+        })();
+        appended.with_context(|| format!("Cannot add to the download archive {}", self.path.display()))?;
+        keys.insert(key.to_string());
+        Ok(())
+    }
+}
+
+/// The key of lesson `id` of the school at `url`.
+pub fn lesson_key(url: &Url, id: &str) -> String {
+    format!("lesson {} {}", url.host_str().unwrap_or_default(), id)
+}
+
+/// The key of a URL as given to `batch`: the lesson for a lesson page
+/// (`/lesson/view/id/123` or `/lesson/view?id=123`), else the playlist.
+pub fn url_key(url: &str) -> String {
+    let Ok(parsed) = Url::parse(url) else {
+        return format!("playlist {}", url);
+    };
+    let id = match parsed.path().split_once("/lesson/view/id/") {
+        Some((_, rest)) => Some(rest.to_string()),
+        None if parsed.path().ends_with("/lesson/view") => {
+            parsed.query_pairs().find(|(name, _)| name == "id").map(|(_, id)| id.into_owned())
+        }
+        None => None,
+    };
+    let id = id.map(|id| id.chars().take_while(char::is_ascii_digit).collect::<String>()).filter(|id| !id.is_empty());
+    match id {
+        Some(id) => lesson_key(&parsed, &id),
+        None => format!("playlist {}{}", parsed.host_str().unwrap_or_default(), parsed.path()),
+    }
+}
@@ -0,0 +1,61 @@
+//! Alternate audio renditions advertised by a master playlist with
+//! `#EXT-X-MEDIA:TYPE=AUDIO` (e.g. the original voice and a translation),
+//! as read by [`crate::playlist`].
+
+use anyhow::{anyhow, Result};
+use tracing::warn;
+
+/// An audio rendition, or a subtitle one (`TYPE=SUBTITLES`, see
+/// [`crate::subtitles`]), which is described the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendition {
+    pub name: String,
+    pub language: Option<String>,
+    /// Marked `DEFAULT=YES`.
+    pub default: bool,
+    /// Absolute URL of the rendition's media playlist.
+    pub uri: String,
+}
+
+impl Rendition {
+    /// `NAME (LANGUAGE)`, for messages and track titles.
+    pub fn label(&self) -> String {
+        match &self.language {
+            Some(language) if *language != self.name => format!("{} ({})", self.name, language),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Whether `want` names this rendition: its LANGUAGE or NAME without
+    /// regard to case, or the primary language (`en` matches `en-US`).
+    pub fn matches(&self, want: &str) -> bool {
+        self.name.eq_ignore_ascii_case(want)
+            || self.language.as_ref().is_some_and(|l| {
+                l.eq_ignore_ascii_case(want)
+                    || l.split('-').next().is_some_and(|primary| primary.eq_ignore_ascii_case(want))
+            })
+    }
+}
+
+/// Picks the renditions asked for in `wanted`, in that order, matching each
+/// entry with [`Rendition::matches`]. The first pick
+/// becomes the default track. Entries that match nothing are reported and
+/// skipped, but at least one must match.
+pub fn select(renditions: &[Rendition], wanted: &[String]) -> Result<Vec<Rendition>> {
+    let mut picked: Vec<Rendition> = Vec::new();
+    for want in wanted {
+        match renditions.iter().find(|r| r.matches(want)) {
+            Some(rendition) if !picked.contains(rendition) => picked.push(rendition.clone()),
+            Some(_) => {}
+            None => warn!("No audio track matches {:?}", want),
+        }
+    }
+    if picked.is_empty() {
+        let available: Vec<String> = renditions.iter().map(Rendition::label).collect();
+        return Err(anyhow!("None of the requested audio tracks exist; available: {}", available.join(", ")));
+    }
+    for (i, rendition) in picked.iter_mut().enumerate() {
+        rendition.default = i == 0;
+    }
+    Ok(picked)
+}
@@ -0,0 +1,182 @@
+//! `batch` subcommand: downloads every URL listed in a text or CSV file,
+//! one `url<TAB>output` (or `url,output`) pair per line. A failed item is
+//! reported and the rest still run; the summary at the end lists them all.
+//! With `--jobs N` several items download at once, each in its own work
+//! directory and with its progress lines marked `[i/total]`; they share one
+//! client, and with it the `limit_rate` budget. With a download archive,
+//! the items it lists are skipped and those that finish are added to it.
+//!
+//! ```text
+//! # lessons of the spring course
+//! url,output
+//! https://school.example/pl/teach/control/lesson/view?id=1,lesson-01.mp4
+//! "https://cdn.example/2/master.m3u8?a=1,b=2","Lesson 2, part 1.mp4"
+//! ```
+
+use crate::{
+    archive::{self, Archive},
+    config::Config,
+    download_to,
+    exit::Failure,
+    fetcher,
+    output::Fsync,
+    DownloadOptions, Existing,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::{stream, StreamExt};
+use std::{fs, path::PathBuf};
+use tracing::{info, warn};
+
+const USAGE: &str = "Usage: getcourse-downloader batch <file> [--jobs N]";
+
+struct BatchOptions {
+    file: PathBuf,
+    /// Downloads run at the same time.
+    parallel: usize,
+}
+
+impl BatchOptions {
+    fn from_args(args: &[String]) -> Result<Self> {
+        let mut file = None;
+        let mut parallel = 1;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| iter.next().ok_or_else(|| anyhow!("Missing value for {}", name)).cloned();
+            match arg.as_str() {
+                // `--parallel` is the older spelling.
+                "--jobs" | "--parallel" => {
+                    let value = value(arg)?;
+                    parallel = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                        anyhow!("Invalid {} '{}'; expected a positive number", arg, value)
+                    })?;
+                }
+                other if !other.starts_with('-') && file.is_none() => file = Some(PathBuf::from(other)),
+                other => return Err(Failure::Usage.of(anyhow!("Unknown batch option: {}", other))),
+            }
+        }
+        let file = file.ok_or_else(|| anyhow!(USAGE))?;
+        Ok(Self { file, parallel })
+    }
+}
+
+struct Item {
+    /// Line in the batch file, for messages.
+    line: usize,
+    url: String,
+    output: String,
+}
+
+/// `exec` is the global `--exec` hook, run after every item; `existing` says
+/// what to do about outputs that exist already.
+pub async fn run(config: &Config, exec: Option<&str>, existing: Existing, args: &[String]) -> Result<()> {
+    let opts = BatchOptions::from_args(args)?;
+    let text =
+        fs::read_to_string(&opts.file).with_context(|| format!("Cannot read batch file {}", opts.file.display()))?;
+    let items = parse(&text).with_context(|| format!("Invalid batch file {}", opts.file.display()))?;
+    if items.is_empty() {
+        return Err(anyhow!("{} lists no downloads", opts.file.display()));
+    }
+    let archive = match &config.download_archive {
+        Some(path) => Some(Archive::open(path, config.fsync != Fsync::Off)?),
+        None => None,
+    };
+    let archived = |item: &Item| archive.as_ref().is_some_and(|archive| archive.contains(&archive::url_key(&item.url)));
+    let fetcher = fetcher(config)?;
+
+    let total = items.len();
+    let pending = items.iter().enumerate().filter(|(_, item)| !archived(item));
+    let mut results: Vec<(usize, Result<()>)> = stream::iter(pending)
+        .map(|(i, item)| {
+            let (fetcher, archive) = (&fetcher, &archive);
+            let label = (opts.parallel > 1).then(|| format!("{}/{}", i + 1, total));
+            let download_opts = DownloadOptions { label, existing, ..Default::default() };
+            async move {
+                info!("[{}/{}] {} -> {}", i + 1, total, item.url, item.output);
+                let mut result = download_to(fetcher, config, &download_opts, &item.url, &item.output, exec).await;
+                if let (Ok(()), Some(archive)) = (&result, archive) {
+                    result = archive.add(&archive::url_key(&item.url));
+                }
+                if let Err(e) = &result {
+                    warn!("[{}/{}] {} failed: {:#}", i + 1, total, item.output, e);
+                }
+                (i, result)
+            }
+        })
+        .buffer_unordered(opts.parallel)
+        .collect()
+        .await;
+    results.sort_by_key(|(i, _)| *i);
+
+    info!("\nBatch summary:");
+    let mut failed = 0;
+    let mut results = results.iter().peekable();
+    for (i, item) in items.iter().enumerate() {
+        let Some((_, result)) = results.next_if(|(done, _)| *done == i) else {
+            info!("  skipped {} (in the download archive)", item.output);
+            continue;
+        };
+        match result {
+            Ok(()) => info!("  ok      {}", item.output),
+            Err(e) => {
+                failed += 1;
+                info!("  FAILED  {} (line {}): {:#}", item.output, item.line, e);
+            }
+        }
+    }
+    match failed {
+        0 => {
+            info!("All {} downloads finished.", total);
+            Ok(())
+        }
+        n => Err(anyhow!("{} of {} downloads failed", n, total)),
+    }
+}
+
+/// Reads the whole file up front, so that a typo near the end is reported
+/// before anything is downloaded. Blank lines, `#` comments and a
+/// `url,output` header row are skipped.
+fn parse(text: &str) -> Result<Vec<Item>> {
+    let mut items = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = match line.contains('\t') {
+            true => line.split('\t').map(|field| field.trim().to_string()).collect(),
+            false => csv_fields(line).with_context(|| format!("line {}", i + 1))?,
+        };
+        match fields.as_slice() {
+            [url, _] if items.is_empty() && url.eq_ignore_ascii_case("url") => {}
+            [url, output] if !url.is_empty() && !output.is_empty() => {
+                items.push(Item { line: i + 1, url: url.clone(), output: output.clone() })
+            }
+            _ => return Err(anyhow!("line {}: expected a URL and an output name, separated by a tab or comma", i + 1)),
+        }
+    }
+    Ok(items)
+}
+
+/// Splits a CSV line; fields may be quoted, with `""` for a literal quote.
+fn csv_fields(line: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quote"));
+    }
+    fields.push(field.trim().to_string());
+    Ok(fields)
+}
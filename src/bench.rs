@@ -0,0 +1,131 @@
+//! `bench` subcommand: measures segment download throughput for several
+//! concurrency levels and write strategies, against the built-in fixture
+//! origin or a user-supplied playlist URL.
+
+use crate::{
+    config::Config,
+    download_segments,
+    fixture::{Fixture, FixtureOptions},
+    http::default_fetcher,
+    resolve_segments,
+    tune::Concurrency,
+    Destination, SegmentWrite,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{collections::BTreeSet, net::SocketAddr, time::Instant};
+use tempfile::tempdir;
+
+struct BenchOptions {
+    url: Option<String>,
+    concurrency: Vec<usize>,
+    rounds: usize,
+    segments: usize,
+}
+
+impl BenchOptions {
+    fn from_args(args: &[String]) -> Result<Self> {
+        let mut opts = Self {
+            url: None,
+            concurrency: vec![1, 4, 10, 16],
+            rounds: 3,
+            segments: 40,
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| {
+                iter.next()
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+                    .cloned()
+            };
+            match arg.as_str() {
+                "--concurrency" => {
+                    opts.concurrency = value(arg)?
+                        .split(',')
+                        .map(|n| n.trim().parse().context("Invalid --concurrency"))
+                        .collect::<Result<_>>()?;
+                    if opts.concurrency.contains(&0) {
+                        return Err(anyhow!("--concurrency values must be at least 1"));
+                    }
+                }
+                "--rounds" => opts.rounds = value(arg)?.parse().context("Invalid --rounds")?,
+                "--segments" => opts.segments = value(arg)?.parse().context("Invalid --segments")?,
+                other if !other.starts_with('-') && opts.url.is_none() => {
+                    opts.url = Some(other.to_string())
+                }
+                other => return Err(anyhow!("Unknown bench option: {}", other)),
+            }
+        }
+        opts.rounds = opts.rounds.max(1);
+        Ok(opts)
+    }
+}
+
+pub async fn run(args: &[String]) -> Result<()> {
+    let opts = BenchOptions::from_args(args)?;
+
+    // Keep the fixture alive for the whole run when no URL was given.
+    let (_fixture, url) = match &opts.url {
+        Some(url) => (None, url.clone()),
+        None => {
+            let fixture = Fixture::start(FixtureOptions {
+                addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                segments: opts.segments,
+                segment_size: 188 * 2800,
+                ..FixtureOptions::default()
+            })
+            .await?;
+            let url = fixture.master_url();
+            println!("Benchmarking against local fixture at {}", url);
+            (Some(fixture), url)
+        }
+    };
+
+    let defaults = Config::default();
+    let fetcher = default_fetcher(&defaults.client())?;
+    let urls: Vec<String> = resolve_segments(fetcher.as_ref(), &url, defaults.playlist_retry(), defaults.quality)
+        .await?
+        .into_iter()
+        .map(|s| s.url)
+        .collect();
+    println!("{} segments, {} round(s) per configuration\n", urls.len(), opts.rounds);
+    println!("{:>11}  {:>9}  {:>9}  {:>10}", "concurrency", "strategy", "time", "throughput");
+
+    for write in [SegmentWrite::Buffered, SegmentWrite::Streamed] {
+        for &concurrency in &opts.concurrency {
+            let mut bytes = 0;
+            let mut elapsed = 0.0;
+            for _ in 0..opts.rounds {
+                let dir = tempdir()?;
+                let start = Instant::now();
+                bytes += download_segments(
+                    &fetcher,
+                    &urls,
+                    0,
+                    &BTreeSet::new(),
+                    Destination::Dir(dir.path()),
+                    &mut Concurrency::fixed(concurrency),
+                    defaults.segment_retry(),
+                    write,
+                    false,
+                    None,
+                    None,
+                    |_, _, _| {},
+                )
+                .await?;
+                elapsed += start.elapsed().as_secs_f64();
+            }
+            let strategy = match write {
+                SegmentWrite::Buffered => "buffered",
+                SegmentWrite::Streamed => "streamed",
+            };
+            println!(
+                "{:>11}  {:>9}  {:>8.2}s  {:>6.1} MB/s",
+                concurrency,
+                strategy,
+                elapsed / opts.rounds as f64,
+                bytes as f64 / elapsed.max(f64::EPSILON) / 1_000_000.0
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! `#EXT-X-BYTERANGE` segments: several segments that are slices of one
+//! resource. [`crate::playlist`] names the slice in the fragment of the
+//! segment URL (`media.ts#bytes=1000-1999`), which keeps the URLs of the
+//! slices apart wherever segments are told apart by URL (resuming, keys,
+//! mirrors), and [`ranged`] turns such a URL into a `Range` request for the
+//! slice. Fragments are never sent to the server.
+
+use crate::{
+    http::{HttpFetcher, Response},
+    playlist::ByteRange,
+};
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tracing::info;
+
+const MARK: &str = "#bytes=";
+
+/// `url` naming the slice `range` of it.
+pub fn mark(url: &str, range: ByteRange) -> String {
+    let url = url.split('#').next().unwrap_or(url);
+    format!("{}{}{}-{}", url, MARK, range.offset, range.offset + range.length - 1)
+}
+
+/// The resource and the slice a [`mark`]ed URL names.
+pub fn split(url: &str) -> Option<(&str, ByteRange)> {
+    let (base, range) = url.split_once(MARK)?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+    Some((base, ByteRange { offset: first, length: last.checked_sub(first)? + 1 }))
+}
+
+/// `fetcher`, requesting only the slice for [`mark`]ed URLs. A `Range` header
+/// `bytes=N-` on such a request (the rest of a slice that broke off) is taken
+/// within the slice.
+pub fn ranged(fetcher: &Arc<dyn HttpFetcher>) -> Arc<dyn HttpFetcher> {
+    Arc::new(Ranged { inner: Arc::clone(fetcher), ignored: AtomicBool::new(false) })
+}
+
+struct Ranged {
+    inner: Arc<dyn HttpFetcher>,
+    /// Whether a server sending whole resources was reported.
+    ignored: AtomicBool,
+}
+
+impl HttpFetcher for Ranged {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        let Some((base, range)) = split(url) else {
+            return self.inner.request(url, headers);
+        };
+        Box::pin(async move {
+            let is_range = |name: &str| name.eq_ignore_ascii_case("range");
+            let skip = headers
+                .iter()
+                .find(|(name, _)| is_range(name))
+                .and_then(|(_, value)| value.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<u64>().ok())
+                .unwrap_or(0);
+            let mut headers: Vec<_> = headers.iter().filter(|(name, _)| !is_range(name)).cloned().collect();
+            let last = range.offset + range.length - 1;
+            headers.push(("Range".to_string(), format!("bytes={}-{}", range.offset + skip, last)));
+            let mut resp = self.inner.request(base, &headers).await?;
+            if resp.status == 200 {
+                if !self.ignored.swap(true, Ordering::Relaxed) {
+                    info!("{} ignores Range requests; every byte-range segment fetches all of it", base);
+                }
+                resp.length = resp.length.filter(|&length| length > last).map(|_| range.length);
+                resp.body = slice(resp.body, range);
+            }
+            Ok(resp)
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        let Some((base, range)) = split(url) else {
+            return self.inner.head(url, headers);
+        };
+        Box::pin(async move {
+            let mut resp = self.inner.head(base, headers).await?;
+            resp.length = Some(range.length).filter(|_| resp.is_success());
+            Ok(resp)
+        })
+    }
+}
+
+/// The part of a whole body that `range` covers.
+fn slice(body: BoxStream<'static, Result<Bytes>>, range: ByteRange) -> BoxStream<'static, Result<Bytes>> {
+    let end = range.offset + range.length;
+    body.scan(0, move |position: &mut u64, chunk| {
+        let item = match chunk {
+            Err(e) => Some(Err(e)),
+            Ok(_) if *position >= end => None,
+            Ok(chunk) => {
+                let start = *position;
+                *position += chunk.len() as u64;
+                let len = chunk.len() as u64;
+                let (from, to) = (range.offset.saturating_sub(start).min(len), (end - start).min(len));
+                Some(Ok(chunk.slice(from as usize..to as usize)))
+            }
+        };
+        futures::future::ready(item)
+    })
+    .filter(|chunk| futures::future::ready(!chunk.as_ref().is_ok_and(|chunk| chunk.is_empty())))
+    .boxed()
+}
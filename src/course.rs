@@ -0,0 +1,175 @@
+//! `course` subcommand: downloads every lesson of a training, including the
+//! lessons of its modules, into a directory tree named after the course and
+//! its modules. Lessons already on disk, or in the download archive, are
+//! skipped, so an interrupted run is finished by running it again.
+
+use crate::{
+    archive::{self, Archive},
+    config::Config,
+    download, download_with_retry,
+    exit::Failure,
+    fetcher,
+    getcourse::{self, Lesson},
+    hook,
+    http::HttpFetcher,
+    output::Fsync,
+    placement::{CourseInfo, Placement},
+    remux::Container,
+    sanitize_filename, DownloadOptions,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
+use tracing::{info, warn};
+use url::Url;
+
+/// How deep modules may nest below the training page.
+const MAX_DEPTH: usize = 4;
+
+/// A lesson to download: the directory of its module and its position there.
+struct Item {
+    dir: PathBuf,
+    index: usize,
+    lesson: Lesson,
+}
+
+/// `exec` is the global `--exec` hook, run after every lesson.
+pub async fn run(config: &Config, exec: Option<&str>, args: &[String]) -> Result<()> {
+    let [training] = args else {
+        return Err(Failure::Usage.of(anyhow!("Usage: getcourse-downloader course <training-url> [--output-dir DIR]")));
+    };
+    let training = Url::parse(training).context("Invalid training URL")?;
+    let fetcher = fetcher(config)?;
+
+    let html = download_with_retry(fetcher.as_ref(), training.as_str(), config.playlist_retry())
+        .await
+        .context("Failed to download training page")?;
+    let title = getcourse::page_title(&html).unwrap_or_else(|| "course".to_string());
+    let base = config.output_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let placement =
+        Placement::resolve(&config.rules, CourseInfo::new(&training, &title), base.join(sanitize_filename(&title)));
+
+    let mut items = Vec::new();
+    let mut visited = HashSet::from([training.path().to_string()]);
+    collect(&fetcher, config, &training, html, placement.dir.clone(), 0, &mut visited, &mut items).await?;
+    if items.is_empty() {
+        return Err(anyhow!("No lessons found on {} (log in first?)", training));
+    }
+    info!("\"{}\": {} lesson(s), saving to {}", title, items.len(), placement.dir.display());
+
+    let archive = match &config.download_archive {
+        Some(path) => Some(Archive::open(path, config.fsync != Fsync::Off)?),
+        None => None,
+    };
+    let extension = config.remux.map_or("ts", Container::extension);
+    let (mut downloaded, mut present, mut without_video, mut failed) = (0, 0, 0, 0);
+    for item in &items {
+        let key = archive::lesson_key(&item.lesson.url, &item.lesson.id);
+        if archive.as_ref().is_some_and(|archive| archive.contains(&key)) {
+            present += 1;
+            continue;
+        }
+        let output = item.dir.join(placement.file_name(&item.lesson.title, item.index, extension));
+        if output.exists() {
+            present += 1;
+            if let Some(archive) = &archive {
+                archive.add(&key)?;
+            }
+            continue;
+        }
+        info!("Lesson: {}", item.lesson.title);
+        let started = Instant::now();
+        let result = async {
+            let html = download_with_retry(fetcher.as_ref(), item.lesson.url.as_str(), config.playlist_retry())
+                .await
+                .context("Failed to download lesson page")?;
+            let Some(playlist) =
+                getcourse::lesson_playlist(fetcher.as_ref(), &item.lesson.url, &html, config.playlist_retry()).await?
+            else {
+                return Ok(None);
+            };
+            fs::create_dir_all(&item.dir).with_context(|| format!("Cannot create {}", item.dir.display()))?;
+            let opts = DownloadOptions {
+                title: Some(item.lesson.title.clone()),
+                course: Some(title.clone()),
+                ..Default::default()
+            };
+            download(&fetcher, config, &opts, &playlist, &output).await.map(Some)
+        }
+        .await;
+        let result = match result {
+            Ok(None) => {
+                info!("No video in \"{}\"; skipped", item.lesson.title);
+                without_video += 1;
+                continue;
+            }
+            Ok(Some(report)) => Ok(report),
+            Err(e) => Err(e),
+        };
+        let output = result.as_ref().map_or(output.display(), |report| report.output.display()).to_string();
+        let result = hook::finish(exec, item.lesson.url.as_str(), output, started, result).await;
+        let result = match &archive {
+            Some(archive) => result.and_then(|()| archive.add(&key)),
+            None => result,
+        };
+        match result {
+            Ok(()) => downloaded += 1,
+            Err(e) => {
+                warn!("Failed to download \"{}\": {:#}", item.lesson.title, e);
+                failed += 1;
+            }
+        }
+    }
+
+    info!(
+        "Course done: {} downloaded, {} already present, {} without video, {} failed.",
+        downloaded, present, without_video, failed
+    );
+    match failed {
+        0 => Ok(()),
+        n => Err(anyhow!("{} lesson(s) failed; run the same command again to retry them", n)),
+    }
+}
+
+/// Adds the lessons of the training page `url` (fetched as `html`) to
+/// `items`, in `dir`, then those of its modules in numbered subdirectories.
+#[allow(clippy::too_many_arguments)]
+fn collect<'a>(
+    fetcher: &'a Arc<dyn HttpFetcher>,
+    config: &'a Config,
+    url: &'a Url,
+    html: String,
+    dir: PathBuf,
+    depth: usize,
+    visited: &'a mut HashSet<String>,
+    items: &'a mut Vec<Item>,
+) -> BoxFuture<'a, Result<()>> {
+    Box::pin(async move {
+        for (i, lesson) in getcourse::list_lessons(&html, url).into_iter().enumerate() {
+            items.push(Item { dir: dir.clone(), index: i + 1, lesson });
+        }
+        let modules = getcourse::list_modules(&html, url);
+        if depth == MAX_DEPTH {
+            if !modules.is_empty() {
+                warn!("Warning: modules nested deeper than {} levels in {} are left out", MAX_DEPTH, url);
+            }
+            return Ok(());
+        }
+        // Breadcrumbs and menus link back to pages already walked.
+        let modules: Vec<_> = modules.into_iter().filter(|m| visited.insert(m.url.path().to_string())).collect();
+        for (i, module) in modules.iter().enumerate() {
+            let html = download_with_retry(fetcher.as_ref(), module.url.as_str(), config.playlist_retry())
+                .await
+                .with_context(|| format!("Failed to download module \"{}\"", module.title))?;
+            let dir = dir.join(format!("{:02} {}", i + 1, sanitize_filename(&module.title)));
+            collect(fetcher, config, &module.url, html, dir, depth + 1, visited, items).await?;
+        }
+        Ok(())
+    })
+}
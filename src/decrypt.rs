@@ -0,0 +1,220 @@
+//! Segments encrypted with `#EXT-X-KEY:METHOD=AES-128`. Keys are fetched
+//! once per URI before the download starts, and [`Decrypting`] then hands out
+//! every encrypted segment already decrypted, so the writers, the manifest
+//! and the joining all see plain MPEG-TS.
+
+use crate::{
+    aes::{Aes128, CbcDecryptor},
+    exit::{Categorize, Failure},
+    http::{HttpFetcher, Response},
+    retry::Retry,
+    skip::attribute_list,
+};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream, StreamExt};
+use std::{collections::HashMap, sync::Arc};
+use tracing::info;
+
+/// The key a segment is encrypted with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentKey {
+    pub uri: String,
+    pub iv: [u8; 16],
+}
+
+/// An `#EXT-X-KEY` tag, applying to the segments after it.
+#[derive(Debug, Clone)]
+pub struct KeyTag {
+    uri: String,
+    /// `IV=0x...`; without it the IV is derived from the media sequence number.
+    iv: Option<[u8; 16]>,
+}
+
+impl KeyTag {
+    /// Parses the attributes of an `#EXT-X-KEY` tag; `None` for `METHOD=NONE`.
+    pub fn parse(attributes: &str) -> Result<Option<Self>> {
+        Self::parse_attributes(attributes).category(Failure::Decryption)
+    }
+
+    fn parse_attributes(attributes: &str) -> Result<Option<Self>> {
+        let attributes = attribute_list(attributes);
+        let get = |name: &str| attributes.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str());
+        match get("METHOD") {
+            Some("NONE") => return Ok(None),
+            Some("AES-128") => {}
+            Some(method) => return Err(anyhow!("Segments are encrypted with {}, which is not supported", method)),
+            None => return Err(anyhow!("#EXT-X-KEY without METHOD")),
+        }
+        if let Some(format) = get("KEYFORMAT").filter(|f| *f != "identity") {
+            return Err(anyhow!("Segments are protected with the {:?} DRM key format, which is not supported", format));
+        }
+        let uri = get("URI").ok_or_else(|| anyhow!("#EXT-X-KEY without URI"))?.to_string();
+        let iv = get("IV").map(parse_iv).transpose()?;
+        Ok(Some(Self { uri, iv }))
+    }
+
+    /// The tag with its key URI passed through `resolve`, which makes it
+    /// absolute.
+    pub fn resolve(mut self, resolve: &dyn Fn(&str) -> Result<String>) -> Result<Self> {
+        self.uri = resolve(&self.uri)?;
+        Ok(self)
+    }
+
+    /// The key of the segment with media sequence number `sequence`.
+    pub fn for_segment(&self, sequence: u64) -> SegmentKey {
+        SegmentKey { uri: self.uri.clone(), iv: self.iv.unwrap_or_else(|| sequence_iv(sequence)) }
+    }
+}
+
+/// `0x` followed by 32 hex digits.
+fn parse_iv(s: &str) -> Result<[u8; 16]> {
+    let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    let mut iv = [0u8; 16];
+    if hex.len() != 32 {
+        return Err(anyhow!("Invalid IV {:?}", s));
+    }
+    for (i, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| anyhow!("Invalid IV {:?}", s))?;
+    }
+    Ok(iv)
+}
+
+/// IV used when a key tag has no IV attribute: the media sequence number as a
+/// 128-bit big-endian integer.
+pub fn sequence_iv(sequence: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[8..].copy_from_slice(&sequence.to_be_bytes());
+    iv
+}
+
+/// `fetcher`, decrypting the segments in `segments` (URL and key) that are
+/// encrypted. Fetches each distinct key first.
+pub async fn decrypting<'a>(
+    fetcher: &Arc<dyn HttpFetcher>,
+    segments: impl IntoIterator<Item = (&'a str, Option<&'a SegmentKey>)>,
+    retry: Retry,
+) -> Result<Arc<dyn HttpFetcher>> {
+    let mut keys = KeyRing::default();
+    let fetcher = keys.decrypting(fetcher, segments, retry).await?;
+    if !keys.is_empty() {
+        info!("Segments are encrypted with AES-128; decrypting them");
+    }
+    Ok(fetcher)
+}
+
+/// Decryption keys by URI, each fetched once however many batches of
+/// segments use it.
+#[derive(Default)]
+pub struct KeyRing(HashMap<String, Arc<Aes128>>);
+
+impl KeyRing {
+    /// Like [`decrypting`], fetching only keys not seen before.
+    pub async fn decrypting<'a>(
+        &mut self,
+        fetcher: &Arc<dyn HttpFetcher>,
+        segments: impl IntoIterator<Item = (&'a str, Option<&'a SegmentKey>)>,
+        retry: Retry,
+    ) -> Result<Arc<dyn HttpFetcher>> {
+        let mut segment_keys = HashMap::new();
+        for (url, key) in segments {
+            let Some(key) = key else { continue };
+            if !self.0.contains_key(&key.uri) {
+                let cipher = Arc::new(Aes128::new(&fetch_key(fetcher.as_ref(), &key.uri, retry).await?));
+                self.0.insert(key.uri.clone(), cipher);
+            }
+            segment_keys.insert(url.to_string(), (Arc::clone(&self.0[&key.uri]), key.iv));
+        }
+        if segment_keys.is_empty() {
+            return Ok(Arc::clone(fetcher));
+        }
+        Ok(Arc::new(Decrypting { inner: Arc::clone(fetcher), keys: segment_keys }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+async fn fetch_key(fetcher: &dyn HttpFetcher, uri: &str, retry: Retry) -> Result<[u8; 16]> {
+    let mut last_error = None;
+    for attempt in 0..=retry.retries {
+        if attempt > 0 {
+            retry.wait(attempt - 1).await;
+        }
+        match fetcher.get(uri).await {
+            Ok(response) if response.is_success() => {
+                let key = response.bytes().await?;
+                return key.as_ref().try_into().map_err(|_| {
+                    Failure::Decryption.of(anyhow!("The decryption key {} is {} bytes, not 16", uri, key.len()))
+                });
+            }
+            Ok(response) => last_error = Some(Failure::Network.of(anyhow!("HTTP status: {}", response.status))),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("Unknown error")))
+        .with_context(|| format!("Failed to fetch the decryption key {}", uri))
+}
+
+/// Decrypts the bodies of known encrypted segments as they arrive and passes
+/// everything else through. Decrypted sizes are not known up front, so their
+/// responses, and HEAD requests for those segments, report no length.
+struct Decrypting {
+    inner: Arc<dyn HttpFetcher>,
+    /// Segment URL to its key and IV.
+    keys: HashMap<String, (Arc<Aes128>, [u8; 16])>,
+}
+
+impl HttpFetcher for Decrypting {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        let Some((cipher, iv)) = self.keys.get(url).cloned() else {
+            return self.inner.request(url, headers);
+        };
+        Box::pin(async move {
+            // CBC needs the segment from its start; the whole of it is sent on.
+            let headers: Vec<_> =
+                headers.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("range")).cloned().collect();
+            let mut response = self.inner.request(url, &headers).await?;
+            if !response.is_success() {
+                return Ok(response);
+            }
+            let (status, final_url, content_type) = (response.status, response.url.take(), response.content_type.take());
+            let decryptor = CbcDecryptor::new(cipher, &iv);
+            let url = url.to_string();
+            let body = stream::unfold(Some((response.body, decryptor)), move |state| {
+                let url = url.clone();
+                async move {
+                    let (mut body, mut decryptor) = state?;
+                    let plain = match body.next().await {
+                        Some(Ok(chunk)) => {
+                            let plain = Bytes::from(decryptor.update(&chunk));
+                            return Some((Ok(plain), Some((body, decryptor))));
+                        }
+                        Some(Err(e)) => Err(e),
+                        None => {
+                            let last = decryptor.finish().with_context(|| format!("Failed to decrypt {}", url));
+                            last.map(Bytes::from).category(Failure::Decryption)
+                        }
+                    };
+                    // The last piece, or the error that ends the body.
+                    Some((plain, None))
+                }
+            })
+            .filter(|chunk| futures::future::ready(!chunk.as_ref().is_ok_and(|chunk| chunk.is_empty())))
+            .boxed();
+            Ok(Response { status, length: None, url: final_url, content_type, cookies: Vec::new(), body })
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        let encrypted = self.keys.contains_key(url);
+        Box::pin(async move {
+            let mut response = self.inner.head(url, headers).await?;
+            if encrypted {
+                response.length = None;
+            }
+            Ok(response)
+        })
+    }
+}
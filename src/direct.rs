@@ -0,0 +1,106 @@
+//! Direct I/O for the final output. Archiving tens of gigabytes through the
+//! page cache evicts everything else a busy server had cached; with
+//! `O_DIRECT` (Linux only) the output bypasses the cache instead.
+
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+use tracing::info;
+
+/// Size of each direct write. `O_DIRECT` needs the buffer address, the length
+/// and the file offset aligned to the device's logical block size; 4 KiB
+/// covers every common device and whole chunks keep the offset aligned.
+const ALIGN: usize = 4096;
+const CHUNK: usize = 1 << 20;
+
+/// A file written with direct I/O where the platform and filesystem allow it,
+/// and normally otherwise. [`finish`](Self::finish) must be called to write
+/// the last, partial chunk.
+pub struct DirectFile {
+    file: File,
+    /// Staging buffer, over-allocated so that an aligned chunk fits in it.
+    /// Empty when writing normally.
+    buffer: Vec<u8>,
+    /// Offset of the aligned chunk within `buffer`.
+    start: usize,
+    /// Bytes staged in the chunk.
+    len: usize,
+}
+
+impl DirectFile {
+    #[cfg(target_os = "linux")]
+    pub fn create(path: &Path) -> Result<Self> {
+        use std::{fs::OpenOptions, os::unix::fs::OpenOptionsExt};
+        let direct = OpenOptions::new().write(true).create(true).truncate(true).custom_flags(libc::O_DIRECT).open(path);
+        match direct {
+            Ok(file) => {
+                let buffer = vec![0; CHUNK + ALIGN];
+                let start = buffer.as_ptr().align_offset(ALIGN);
+                Ok(Self { file, buffer, start, len: 0 })
+            }
+            // tmpfs and some network filesystems refuse O_DIRECT.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                info!("Direct I/O is not supported for {}; writing it normally", path.display());
+                Self::plain(path)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn create(path: &Path) -> Result<Self> {
+        info!("Direct I/O is only available on Linux; writing {} normally", path.display());
+        Self::plain(path)
+    }
+
+    /// Creates `path` for normal writes, for callers that want direct I/O
+    /// only some of the time.
+    pub fn plain(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self { file, buffer: Vec::new(), start: 0, len: 0 })
+    }
+
+    /// Writes what is left in the buffer and returns the file.
+    pub fn finish(mut self) -> io::Result<File> {
+        if self.len > 0 {
+            // The tail is not a whole block, so it goes through the page cache.
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::unix::io::AsRawFd;
+                let fd = self.file.as_raw_fd();
+                // SAFETY: plain fcntl calls on a descriptor owned by `self.file`.
+                let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+                if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            self.file.write_all(&self.buffer[self.start..self.start + self.len])?;
+        }
+        Ok(self.file)
+    }
+}
+
+impl Write for DirectFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            return self.file.write(data);
+        }
+        let n = data.len().min(CHUNK - self.len);
+        let at = self.start + self.len;
+        self.buffer[at..at + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        if self.len == CHUNK {
+            self.file.write_all(&self.buffer[self.start..self.start + CHUNK])?;
+            self.len = 0;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Partial chunks cannot be written directly; they wait for `finish`.
+        self.file.flush()
+    }
+}
@@ -0,0 +1,122 @@
+//! Exit codes by what went wrong, so that wrapper scripts can tell a typo in
+//! the arguments from a server that is down or a full disk. Errors are
+//! [`Failure::of`] a kind where it is known, and otherwise classified by
+//! their causes: HTTP client errors are network failures, and the I/O errors
+//! of a full, read-only or forbidden filesystem are disk failures. Anything
+//! else exits with 1.
+
+use anyhow::Result;
+use std::{error::Error, fmt, io};
+
+/// Exit code of a failure that fits none of the kinds.
+pub const OTHER: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Invalid arguments or settings.
+    Usage,
+    /// Unreachable server, HTTP errors, timeouts, broken connections.
+    Network,
+    /// Not a playlist, or one that cannot be read.
+    Playlist,
+    /// Unsupported encryption, or a key that does not fit the segments.
+    Decryption,
+    /// Not enough space, or no permission to write.
+    Disk,
+    /// Stopped by Ctrl+C or SIGTERM.
+    Interrupted,
+    /// Stopped at the `deadline` setting, with the code of `timeout(1)`.
+    Deadline,
+}
+
+impl Failure {
+    pub fn code(self) -> i32 {
+        match self {
+            Failure::Usage => 2,
+            Failure::Network => 3,
+            Failure::Playlist => 4,
+            Failure::Decryption => 5,
+            Failure::Disk => 6,
+            Failure::Interrupted => 130,
+            Failure::Deadline => 124,
+        }
+    }
+
+    /// `error` as a failure of this kind, unless it is of a kind already:
+    /// the cause knows best.
+    pub fn of(self, error: anyhow::Error) -> anyhow::Error {
+        match failure(&error) {
+            Some(_) => error,
+            None => anyhow::Error::new(Tagged { failure: self, error }),
+        }
+    }
+}
+
+/// [`Failure::of`] for results.
+pub trait Categorize<T> {
+    fn category(self, failure: Failure) -> Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> Categorize<T> for std::result::Result<T, E> {
+    fn category(self, failure: Failure) -> Result<T> {
+        self.map_err(|e| failure.of(e.into()))
+    }
+}
+
+/// The exit code for `error`.
+pub fn code(error: &anyhow::Error) -> i32 {
+    failure(error).map_or(OTHER, Failure::code)
+}
+
+/// The kind of `error`, if it can be told.
+pub fn failure(error: &anyhow::Error) -> Option<Failure> {
+    tagged(error.chain()).or_else(|| error.chain().find_map(caused))
+}
+
+fn tagged<'a>(mut chain: impl Iterator<Item = &'a (dyn Error + 'static)>) -> Option<Failure> {
+    chain.find_map(|cause| match cause.downcast_ref::<Tagged>() {
+        Some(tagged) => Some(tagged.failure),
+        // What broke a segment off is not part of the chain.
+        None => cause.downcast_ref::<crate::Interrupted>().and_then(|interrupted| failure(&interrupted.0)),
+    })
+}
+
+/// The kind of failure the error `cause` is, by its type.
+fn caused(cause: &(dyn Error + 'static)) -> Option<Failure> {
+    #[cfg(feature = "reqwest-backend")]
+    if cause.is::<reqwest::Error>() {
+        return Some(Failure::Network);
+    }
+    #[cfg(feature = "hyper-backend")]
+    if cause.is::<hyper::Error>() {
+        return Some(Failure::Network);
+    }
+    use io::ErrorKind::*;
+    let kind = cause.downcast_ref::<io::Error>()?.kind();
+    matches!(kind, StorageFull | QuotaExceeded | FileTooLarge | ReadOnlyFilesystem | PermissionDenied)
+        .then_some(Failure::Disk)
+}
+
+/// An error of a known kind. It shows as the error itself.
+struct Tagged {
+    failure: Failure,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl fmt::Debug for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.error)
+    }
+}
+
+impl Error for Tagged {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
@@ -0,0 +1,274 @@
+//! Extractors turn the URL given on the command line into the playlist to
+//! download (plus any headers the CDN insists on). Built-in extractors cover
+//! GetCourse lesson pages, the LMS platforms in [`lms::PLATFORMS`] and direct
+//! playlist links; support for other players
+//! can be added without rebuilding by dropping an executable into the
+//! `extractors` directory next to the config file.
+//!
+//! `page:URL` skips the matching and scrapes URL as a GetCourse lesson page,
+//! for schools on their own domain or with pages laid out differently.
+//!
+//! Plugin protocol: `plugin --matches URL` exits 0 if the plugin handles the
+//! URL; `plugin URL` prints the playlist URL and optional `Name: value` header
+//! lines (the same format as the request hook).
+
+use crate::{
+    config, getcourse,
+    http::{HttpFetcher, WithHeaders},
+    lms::{self, Platform},
+    request_hook,
+    retry::Retry,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+use tracing::info;
+use url::Url;
+
+/// Marks a URL as a lesson page to scrape, whatever it looks like.
+const PAGE_PREFIX: &str = "page:";
+
+/// Where to get the video from.
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pub playlist: String,
+    /// Sent with the playlist and every segment request.
+    pub headers: Vec<(String, String)>,
+    /// The lesson's title, when the video was found on a page; it names the
+    /// output when none is given.
+    pub title: Option<String>,
+}
+
+pub trait Extractor: Send + Sync {
+    fn name(&self) -> &str;
+    fn matches(&self, url: &Url) -> bool;
+    fn extract<'a>(&'a self, fetcher: &'a dyn HttpFetcher, url: &'a Url, retry: Retry)
+        -> BoxFuture<'a, Result<Extraction>>;
+}
+
+/// Extractors in priority order; the first one that matches wins.
+#[derive(Default)]
+pub struct Registry {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl Registry {
+    /// Plugins first, so they can take over URLs a built-in would claim, then
+    /// GetCourse and the other LMS platforms, then the catch-all for direct
+    /// playlist links.
+    pub fn load() -> Result<Self> {
+        let mut registry = Self::default();
+        if let Some(dir) = plugin_dir() {
+            for plugin in Plugin::discover(&dir)? {
+                registry.register(Box::new(plugin));
+            }
+        }
+        registry.register(Box::new(GetCourse));
+        for platform in lms::PLATFORMS {
+            registry.register(Box::new(Lms(platform)));
+        }
+        registry.register(Box::new(Playlist));
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, extractor: Box<dyn Extractor>) {
+        self.extractors.push(extractor);
+    }
+
+    pub fn find(&self, url: &Url) -> Option<&dyn Extractor> {
+        self.extractors.iter().find(|e| e.matches(url)).map(|e| e.as_ref())
+    }
+}
+
+/// Resolves `url` to a playlist with the matching extractor. The returned
+/// fetcher sends the extractor's headers, if it asked for any.
+pub async fn resolve(
+    fetcher: &Arc<dyn HttpFetcher>,
+    url: &str,
+    retry: Retry,
+) -> Result<(Extraction, Arc<dyn HttpFetcher>)> {
+    let mut extraction = extract(fetcher.as_ref(), url, retry, true).await?;
+    let headers = std::mem::take(&mut extraction.headers);
+    Ok((extraction, with_headers(fetcher, headers)))
+}
+
+/// Runs the extractor matching `url`, or for `page:URL` the GetCourse one;
+/// with `announce`, says which one unless it is the plain playlist link.
+pub async fn extract(fetcher: &dyn HttpFetcher, url: &str, retry: Retry, announce: bool) -> Result<Extraction> {
+    let (url, forced) = match url.strip_prefix(PAGE_PREFIX) {
+        Some(url) => (url, true),
+        None => (url, false),
+    };
+    let page = Url::parse(url).with_context(|| format!("Invalid URL: {}", url))?;
+    let registry = Registry::load()?;
+    let extractor = match forced {
+        true => &GetCourse,
+        false => registry.find(&page).ok_or_else(|| anyhow!("No extractor supports {}", page))?,
+    };
+    if announce && extractor.name() != Playlist.name() {
+        info!("Using the {} extractor", extractor.name());
+    }
+    extractor
+        .extract(fetcher, &page, retry)
+        .await
+        .with_context(|| format!("The {} extractor failed", extractor.name()))
+}
+
+/// `fetcher`, sending `headers` with every request if there are any.
+pub fn with_headers(fetcher: &Arc<dyn HttpFetcher>, headers: Vec<(String, String)>) -> Arc<dyn HttpFetcher> {
+    match headers {
+        headers if headers.is_empty() => Arc::clone(fetcher),
+        headers => Arc::new(WithHeaders::new(Arc::clone(fetcher), headers)),
+    }
+}
+
+/// `<config dir>/extractors`.
+fn plugin_dir() -> Option<PathBuf> {
+    Some(config::default_path()?.parent()?.join("extractors"))
+}
+
+/// A playlist link as found in the `data-master` attribute; used as is.
+struct Playlist;
+
+impl Extractor for Playlist {
+    fn name(&self) -> &str {
+        "playlist"
+    }
+
+    /// `file` URLs are local playlists served by [`crate::local`].
+    fn matches(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https" | "file")
+    }
+
+    fn extract<'a>(&'a self, _: &'a dyn HttpFetcher, url: &'a Url, _: Retry) -> BoxFuture<'a, Result<Extraction>> {
+        Box::pin(async move { Ok(Extraction { playlist: url.to_string(), headers: Vec::new(), title: None }) })
+    }
+}
+
+/// A GetCourse lesson page (`.../lesson/view/id/N`).
+struct GetCourse;
+
+impl Extractor for GetCourse {
+    fn name(&self) -> &str {
+        "getcourse"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        url.path().contains("/lesson/view")
+    }
+
+    fn extract<'a>(
+        &'a self,
+        fetcher: &'a dyn HttpFetcher,
+        url: &'a Url,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<Extraction>> {
+        Box::pin(async move {
+            let video = getcourse::resolve_lesson_playlist(fetcher, url, retry).await?;
+            Ok(Extraction { playlist: video.playlist, headers: Vec::new(), title: video.title })
+        })
+    }
+}
+
+/// A lesson page of one of the [`lms::PLATFORMS`].
+struct Lms(&'static Platform);
+
+impl Extractor for Lms {
+    fn name(&self) -> &str {
+        self.0.name
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        self.0.matches(url) && !url.path().ends_with(".m3u8")
+    }
+
+    fn extract<'a>(
+        &'a self,
+        fetcher: &'a dyn HttpFetcher,
+        url: &'a Url,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<Extraction>> {
+        Box::pin(async move {
+            let video = lms::find_page_playlist(fetcher, url, retry, |_| true).await?;
+            // Embedded players commonly check that they are loaded from the school's site.
+            let headers = vec![("Referer".to_string(), url.to_string())];
+            Ok(Extraction { playlist: video.playlist, headers, title: video.title })
+        })
+    }
+}
+
+/// An external extractor executable.
+struct Plugin {
+    name: String,
+    path: PathBuf,
+}
+
+impl Plugin {
+    fn discover(dir: &Path) -> Result<Vec<Self>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read {}", dir.display())),
+        };
+        let mut plugins: Vec<Self> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| is_executable(path))
+            .map(|path| Self {
+                name: path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+                path,
+            })
+            .collect();
+        plugins.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(plugins)
+    }
+}
+
+impl Extractor for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        Command::new(&self.path)
+            .arg("--matches")
+            .arg(url.as_str())
+            .stdout(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    fn extract<'a>(&'a self, _: &'a dyn HttpFetcher, url: &'a Url, _: Retry) -> BoxFuture<'a, Result<Extraction>> {
+        Box::pin(async move {
+            let path = self.path.clone();
+            let url = url.to_string();
+            let output = tokio::task::spawn_blocking(move || Command::new(path).arg(url).output())
+                .await
+                .context("Extractor plugin panicked")?
+                .context("Failed to run extractor plugin")?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow!("exited with {}: {}", output.status, stderr.trim()));
+            }
+            let rewrite = request_hook::parse(&String::from_utf8_lossy(&output.stdout))?;
+            let playlist = rewrite.url.ok_or_else(|| anyhow!("printed no playlist URL"))?;
+            Ok(Extraction { playlist, headers: rewrite.headers, title: None })
+        })
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "exe" || ext == "bat" || ext == "cmd")
+}
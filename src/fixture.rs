@@ -0,0 +1,826 @@
+//! Synthetic HLS origin served by the hidden `serve-fixture` subcommand.
+//!
+//! Everything is generated on the fly: a master playlist with two variants,
+//! their media playlists and MPEG-TS segments made of valid 188-byte packets.
+//! Optional knobs reproduce the awkward parts of real CDNs (AES-128 keys,
+//! periodic 429s, redirects, live playlists) and of schools (a login wall)
+//! so the pipeline can be exercised offline.
+
+use crate::{aes::Aes128, decrypt::sequence_iv};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+pub const FIXTURE_KEY: [u8; 16] = *b"getcou-rs-fixtur";
+const TS_PACKET: usize = 188;
+pub const INTRO_CLASS: &str = "com.getcou-rs.fixture.intro";
+const VARIANTS: [(u32, &str); 2] = [(800_000, "640x360"), (2_500_000, "1280x720")];
+/// Session cookie of a successful `--login`, and the guest one before it.
+const SESSION_COOKIE: &str = "PHPSESSID5=fixture-session";
+const GUEST_COOKIE: &str = "PHPSESSID5=fixture-guest";
+const LOGIN_FORM_ID: &str = "99_1_1_1";
+const LOGIN_SIGN: &str = "fixture-sign";
+/// The lesson of the fixture course that has no video.
+const TEXT_LESSON: &str = "5";
+
+#[derive(Debug, Clone)]
+pub struct FixtureOptions {
+    pub addr: SocketAddr,
+    pub segments: usize,
+    pub segment_size: usize,
+    pub segment_duration: f64,
+    pub aes: bool,
+    pub fail_every: Option<usize>,
+    /// Answer every N-th segment request with `200` and a damaged body,
+    /// alternately an HTML error page and a segment cut short.
+    pub damage_every: Option<usize>,
+    /// Close the connection halfway through the body of every N-th segment
+    /// response.
+    pub cut_every: Option<usize>,
+    /// Ignore `Range` headers, answering them with the whole segment.
+    pub no_ranges: bool,
+    /// Serve each variant as one file, `all.ts`, that the media playlists
+    /// list in slices with `#EXT-X-BYTERANGE`.
+    pub byterange: bool,
+    pub redirect: bool,
+    /// List relative URIs in the playlists instead of absolute URLs.
+    pub relative: bool,
+    /// Serve fragmented MP4: an `#EXT-X-MAP` init segment and `.m4s`
+    /// fragments instead of MPEG-TS segments.
+    pub fmp4: bool,
+    /// Segment index at which timestamps restart, announced with
+    /// `#EXT-X-DISCONTINUITY`. The segments before it form a spliced-in intro,
+    /// marked with an `#EXT-X-DATERANGE` of class [`INTRO_CLASS`].
+    pub discontinuity: Option<usize>,
+    /// Languages of alternate audio renditions (`#EXT-X-MEDIA:TYPE=AUDIO`),
+    /// served as `/a<N>/index.m3u8`; the first one is the default.
+    pub audio: Vec<String>,
+    /// Languages of subtitle renditions (`#EXT-X-MEDIA:TYPE=SUBTITLES`),
+    /// served as `/s<N>/index.m3u8` with one WebVTT segment per segment.
+    pub subs: Vec<String>,
+    /// Email and password of the only account. Everything but the login page
+    /// then needs the session cookie it hands out.
+    pub login: Option<(String, String)>,
+    /// Publish the segments live, one per segment duration, listing the
+    /// latest this many without `#EXT-X-ENDLIST` until the last is out.
+    pub live: Option<usize>,
+    /// Serve MPEG-TS segments as GetCourse-style `.bin` files in disguise.
+    pub bin: Option<Disguise>,
+}
+
+/// How `--bin` segments are disguised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disguise {
+    /// Behind the header of a PNG image.
+    Image,
+    /// Every byte XORed with [`BIN_XOR_KEY`].
+    Xor,
+}
+
+pub const BIN_XOR_KEY: u8 = 0x5a;
+
+/// What `--bin image` puts in front of each segment: a PNG signature and the
+/// `IHDR` chunk of a 1x1 image.
+const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0\x1f\x15\xc4\x89";
+
+impl Default for FixtureOptions {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            segments: 10,
+            segment_size: TS_PACKET * 1000,
+            segment_duration: 4.0,
+            aes: false,
+            fail_every: None,
+            damage_every: None,
+            cut_every: None,
+            no_ranges: false,
+            byterange: false,
+            redirect: false,
+            relative: false,
+            fmp4: false,
+            discontinuity: None,
+            audio: Vec::new(),
+            subs: Vec::new(),
+            login: None,
+            live: None,
+            bin: None,
+        }
+    }
+}
+
+impl FixtureOptions {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut opts = Self::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| {
+                iter.next()
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+                    .cloned()
+            };
+            match arg.as_str() {
+                "--addr" => opts.addr = value(arg)?.parse().context("Invalid --addr")?,
+                "--segments" => opts.segments = value(arg)?.parse().context("Invalid --segments")?,
+                "--segment-size" => {
+                    opts.segment_size = value(arg)?.parse().context("Invalid --segment-size")?
+                }
+                "--segment-duration" => {
+                    opts.segment_duration =
+                        value(arg)?.parse().context("Invalid --segment-duration")?
+                }
+                "--aes" => opts.aes = true,
+                "--fail-every" => {
+                    opts.fail_every = Some(value(arg)?.parse().context("Invalid --fail-every")?)
+                }
+                "--damage-every" => {
+                    opts.damage_every = Some(value(arg)?.parse().context("Invalid --damage-every")?)
+                }
+                "--cut-every" => opts.cut_every = Some(value(arg)?.parse().context("Invalid --cut-every")?),
+                "--no-ranges" => opts.no_ranges = true,
+                "--byterange" => opts.byterange = true,
+                "--redirect" => opts.redirect = true,
+                "--relative" => opts.relative = true,
+                "--fmp4" => opts.fmp4 = true,
+                "--discontinuity" => {
+                    opts.discontinuity = Some(value(arg)?.parse().context("Invalid --discontinuity")?)
+                }
+                "--audio" => opts.audio = value(arg)?.split(',').map(String::from).collect(),
+                "--subtitles" => opts.subs = value(arg)?.split(',').map(String::from).collect(),
+                "--login" => {
+                    let account = value(arg)?;
+                    let (email, password) =
+                        account.split_once(':').ok_or_else(|| anyhow!("--login takes EMAIL:PASSWORD"))?;
+                    opts.login = Some((email.to_string(), password.to_string()));
+                }
+                "--live" => opts.live = Some(value(arg)?.parse().context("Invalid --live")?),
+                "--bin" => {
+                    opts.bin = Some(match value(arg)?.as_str() {
+                        "image" => Disguise::Image,
+                        "xor" => Disguise::Xor,
+                        other => return Err(anyhow!("Invalid --bin '{}'; expected image or xor", other)),
+                    })
+                }
+                other => return Err(anyhow!("Unknown serve-fixture option: {}", other)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// A running fixture server.
+pub struct Fixture {
+    pub addr: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Fixture {
+    /// Binds the listener and starts serving in the background. Port 0 picks a
+    /// free port; the actual address is available in `addr`.
+    pub async fn start(opts: FixtureOptions) -> Result<Self> {
+        let listener = TcpListener::bind(opts.addr)
+            .await
+            .with_context(|| format!("Failed to bind {}", opts.addr))?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State {
+            opts,
+            addr,
+            requests: AtomicUsize::new(0),
+            segment_requests: AtomicUsize::new(0),
+            started: Instant::now(),
+        });
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, &state).await;
+                });
+            }
+        });
+        Ok(Self { addr, task })
+    }
+
+    pub fn master_url(&self) -> String {
+        format!("http://{}/master.m3u8", self.addr)
+    }
+
+    pub async fn wait(mut self) -> Result<()> {
+        (&mut self.task).await?;
+        Ok(())
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub async fn serve(args: &[String]) -> Result<()> {
+    let fixture = Fixture::start(FixtureOptions::from_args(args)?).await?;
+    println!("Serving fixture playlist at {}", fixture.master_url());
+    fixture.wait().await
+}
+
+struct State {
+    opts: FixtureOptions,
+    addr: SocketAddr,
+    requests: AtomicUsize,
+    segment_requests: AtomicUsize,
+    started: Instant,
+}
+
+impl State {
+    /// The segments published so far: all of them, or with `--live` the
+    /// latest window of those out by now.
+    fn published(&self) -> std::ops::Range<usize> {
+        let opts = &self.opts;
+        let Some(window) = opts.live else {
+            return 0..opts.segments;
+        };
+        let out = 1 + (self.started.elapsed().as_secs_f64() / opts.segment_duration) as usize;
+        let end = out.min(opts.segments);
+        end.saturating_sub(window)..end
+    }
+}
+
+struct Reply {
+    status: &'static str,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+    /// Close the connection after half of the body.
+    cut: bool,
+}
+
+impl Reply {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self { status: "200 OK", content_type, headers: Vec::new(), body, cut: false }
+    }
+
+    fn status(status: &'static str) -> Self {
+        Self { status, content_type: "text/plain", headers: Vec::new(), body: status.as_bytes().to_vec(), cut: false }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, state: &State) -> Result<()> {
+    loop {
+        let Some(head) = read_head(&mut stream).await? else {
+            return Ok(());
+        };
+        let mut lines = head.lines();
+        let request_line = lines.next().unwrap_or_default();
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or("/").to_string();
+        let mut close = false;
+        let mut range = None;
+        let mut cookie = String::new();
+        let mut length = 0;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else { continue };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "connection" => close |= value.trim().eq_ignore_ascii_case("close"),
+                "range" => {
+                    range = value.trim().strip_prefix("bytes=").and_then(|r| {
+                        let (start, end) = r.split_once('-')?;
+                        Some((start.parse().ok()?, end.parse().ok()))
+                    })
+                }
+                "cookie" => cookie = value.trim().to_string(),
+                "content-length" => length = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await?;
+
+        let reply = match login_route(state, &method, &path, &cookie, &body) {
+            Some(reply) => reply,
+            None => route(state, &path, method == "HEAD", range),
+        };
+        let mut out = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            reply.status,
+            reply.content_type,
+            reply.body.len()
+        );
+        for (name, value) in &reply.headers {
+            out.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        out.push_str("\r\n");
+        stream.write_all(out.as_bytes()).await?;
+        if reply.cut {
+            stream.write_all(&reply.body[..reply.body.len() / 2]).await?;
+            return Ok(());
+        }
+        if method != "HEAD" {
+            stream.write_all(&reply.body).await?;
+        }
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+async fn read_head(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut buf = Vec::new();
+    let mut byte = [0u8; 1];
+    while !buf.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 {
+            return Ok(None);
+        }
+        buf.push(byte[0]);
+    }
+    Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+}
+
+/// With `--login`, the replies of the login page and form, and `403` to
+/// requests without the session cookie; `None` lets [`route`] answer.
+fn login_route(state: &State, method: &str, path: &str, cookie: &str, body: &[u8]) -> Option<Reply> {
+    let (email, password) = state.opts.login.as_ref()?;
+    let has = |wanted: &str| cookie.split(';').any(|pair| pair.trim() == wanted);
+    if path.split('?').next() != Some("/cms/system/login") {
+        return (!has(SESSION_COOKIE)).then(|| Reply::status("403 Forbidden"));
+    }
+    if method != "POST" {
+        let page = format!(
+            "<html><head><script>window.requestSimpleSign = \"{}\";</script></head><body>\n\
+             <div class=\"xdget-block\" data-xdget-id=\"{}\"><form>\n\
+             <input type=\"text\" name=\"email\"><input type=\"password\" name=\"password\">\n\
+             </form></div></body></html>\n",
+            LOGIN_SIGN, LOGIN_FORM_ID
+        );
+        let mut reply = Reply::ok("text/html; charset=utf-8", page.into_bytes());
+        reply.headers.push(("Set-Cookie", format!("{}; path=/; HttpOnly", GUEST_COOKIE)));
+        return Some(reply);
+    }
+    let form: Vec<(String, String)> = url::form_urlencoded::parse(body).into_owned().collect();
+    let field = |name: &str| form.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+    let accepted = has(GUEST_COOKIE)
+        && field("action") == Some("processXdget")
+        && field("xdgetId") == Some(LOGIN_FORM_ID)
+        && field("requestSimpleSign") == Some(LOGIN_SIGN)
+        && field("params[email]") == Some(email)
+        && field("params[password]") == Some(password);
+    let answer = match accepted {
+        true => r#"{"success":true,"data":{}}"#,
+        false => r#"{"success":false,"errorMessage":"Wrong email or password"}"#,
+    };
+    let mut reply = Reply::ok("application/json", answer.as_bytes().to_vec());
+    if accepted {
+        reply.headers.push(("Set-Cookie", format!("{}; path=/; HttpOnly", SESSION_COOKIE)));
+    }
+    Some(reply)
+}
+
+/// The reply to a GET of `path`, or with `head`, of a HEAD; `range` is the
+/// first and last byte of a `Range` (`bytes=N-` or `bytes=N-M`), honoured
+/// for segments.
+fn route(state: &State, path: &str, head: bool, range: Option<(usize, Option<usize>)>) -> Reply {
+    let opts = &state.opts;
+    let n = state.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    if opts.fail_every.is_some_and(|every| every > 0 && n.is_multiple_of(every)) {
+        let mut reply = Reply::status("429 Too Many Requests");
+        reply.headers.push(("Retry-After", "1".into()));
+        return reply;
+    }
+
+    let path = path.split('?').next().unwrap_or(path);
+    if path == "/master.m3u8" {
+        return Reply::ok("application/vnd.apple.mpegurl", master_playlist(state).into_bytes());
+    }
+    if path == "/key.bin" && opts.aes {
+        return Reply::ok("application/octet-stream", FIXTURE_KEY.to_vec());
+    }
+    if let Some(id) = path.strip_prefix("/teach/control/lesson/view/id/") {
+        let page = match id {
+            TEXT_LESSON => "<html><body><h1>Reading list</h1><p>No video here.</p></body></html>\n".to_string(),
+            _ => format!(
+                "<html><body><h1>Lesson {}: what is HLS?</h1>\n\
+                 <video id=\"vgc-player_html5_api\" data-master=\"http://{}/master.m3u8\"></video></body></html>\n",
+                id, state.addr
+            ),
+        };
+        return Reply::ok("text/html; charset=utf-8", page.into_bytes());
+    }
+    if let Some(page) = path.strip_prefix("/teach/control/stream/view/id/").and_then(training_page) {
+        return Reply::ok("text/html; charset=utf-8", page.into_bytes());
+    }
+
+    if let Some(reply) = subtitle_route(state, path) {
+        return reply;
+    }
+
+    let (redirected, rest) = match path.strip_prefix("/cdn") {
+        Some(rest) => (true, rest),
+        None => (false, path),
+    };
+    let Some((dir, variant, file)) = parse_variant_path(rest, opts.audio.len()) else {
+        return Reply::status("404 Not Found");
+    };
+    if opts.redirect && !redirected && file == "index.m3u8" {
+        let mut reply = Reply::status("302 Found");
+        reply.headers.push(("Location", format!("/cdn{}", path)));
+        return reply;
+    }
+    if file == "init.mp4" && opts.fmp4 {
+        return Reply::ok("video/mp4", init_segment());
+    }
+    if file == "index.m3u8" {
+        return Reply::ok(
+            "application/vnd.apple.mpegurl",
+            media_playlist(state, dir, variant).into_bytes(),
+        );
+    }
+    let body = match opts.byterange && file == "all.ts" {
+        true => Some((0..state.published().end).flat_map(|i| segment_body(opts, variant, i)).collect()),
+        false => file
+            .strip_prefix("seg")
+            .and_then(|f| f.strip_suffix(segment_extension(opts)))
+            .and_then(|i| i.parse::<usize>().ok())
+            .filter(|&i| i < state.published().end)
+            .map(|index| segment_body(opts, variant, index)),
+    };
+    let Some(mut body) = body else {
+        return Reply::status("404 Not Found");
+    };
+    if opts.redirect && !redirected {
+        let mut reply = Reply::status("302 Found");
+        reply.headers.push(("Location", format!("/cdn{}", path)));
+        return reply;
+    }
+
+    // Damage is only noticed in bodies.
+    let n = if head { 0 } else { state.segment_requests.fetch_add(1, Ordering::Relaxed) + 1 };
+    if let Some(every) = opts.damage_every.filter(|&every| every > 0 && n > 0 && n.is_multiple_of(every)) {
+        if (n / every) % 2 == 1 {
+            return Reply::ok("text/html", b"<html><body>502 Bad Gateway</body></html>".to_vec());
+        }
+        body.truncate(body.len() - 100);
+    }
+    let total = body.len();
+    let range = range.filter(|_| !opts.no_ranges);
+    let range = range.map(|(start, end)| (start, end.map_or(total.saturating_sub(1), |end| end.min(total - 1))));
+    if let Some((start, end)) = range {
+        if start >= total {
+            let mut reply = Reply::status("416 Range Not Satisfiable");
+            reply.headers.push(("Content-Range", format!("bytes */{}", total)));
+            return reply;
+        }
+        body.truncate(end + 1);
+        body.drain(..start);
+    }
+    let mut reply = Reply::ok(if opts.fmp4 { "video/iso.segment" } else { "video/mp2t" }, body);
+    if let Some((start, end)) = range {
+        reply.status = "206 Partial Content";
+        reply.headers.push(("Content-Range", format!("bytes {}-{}/{}", start, end, total)));
+    }
+    reply.cut = opts.cut_every.is_some_and(|every| every > 0 && n > 0 && n.is_multiple_of(every));
+    reply
+}
+
+fn segment_extension(opts: &FixtureOptions) -> &'static str {
+    match (opts.fmp4, opts.bin) {
+        (true, _) => ".m4s",
+        (false, Some(_)) => ".bin",
+        (false, None) => ".ts",
+    }
+}
+
+/// Segment `index` of variant (or audio rendition) `variant`, as served.
+fn segment_body(opts: &FixtureOptions, variant: usize, index: usize) -> Vec<u8> {
+    let body = match opts.fmp4 {
+        true => fragment_bytes(variant, index, opts.segment_size),
+        false => segment_bytes(variant, index, opts.segment_size, segment_pts(opts, index)),
+    };
+    let body = match opts.bin.filter(|_| !opts.fmp4) {
+        Some(Disguise::Image) => [PNG_HEADER, &body].concat(),
+        Some(Disguise::Xor) => body.into_iter().map(|b| b ^ BIN_XOR_KEY).collect(),
+        None => body,
+    };
+    match opts.aes {
+        true => Aes128::new(&FIXTURE_KEY).cbc_encrypt(&sequence_iv(index as u64), &body),
+        false => body,
+    }
+}
+
+/// With `--subtitles`, the playlists and WebVTT segments under `/s<N>/`.
+fn subtitle_route(state: &State, path: &str) -> Option<Reply> {
+    let (dir, file) = path.strip_prefix("/s")?.split_once('/')?;
+    let language = state.opts.subs.get(dir.parse::<usize>().ok()?)?;
+    if file == "index.m3u8" {
+        let duration = state.opts.segment_duration;
+        let mut out = format!("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n", duration.ceil() as u64);
+        for i in 0..state.opts.segments {
+            let uri = segment_uri(state, &format!("s{}", dir), &format!("seg{:05}.vtt", i));
+            out.push_str(&format!("#EXTINF:{:.3},\n{}\n", duration, uri));
+        }
+        out.push_str("#EXT-X-ENDLIST\n");
+        return Some(Reply::ok("application/vnd.apple.mpegurl", out.into_bytes()));
+    }
+    let index: usize = file.strip_prefix("seg")?.strip_suffix(".vtt")?.parse().ok()?;
+    (index < state.opts.segments).then(|| Reply::ok("text/vtt", subtitle_segment(&state.opts, language, index)))
+}
+
+/// WebVTT segment `index`, mapped to the timestamps of the video: a line of
+/// its own, and one running into the next segment, which repeats it.
+fn subtitle_segment(opts: &FixtureOptions, language: &str, index: usize) -> Vec<u8> {
+    let clock = |seconds: f64| {
+        let ms = (seconds * 1000.0).round() as u64;
+        format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+    };
+    let quarter = opts.segment_duration / 4.0;
+    let start = index as f64 * opts.segment_duration;
+    let mut out = String::from("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\n\n");
+    let mut cue = |from: f64, to: f64, text: String| {
+        out.push_str(&format!("{} --> {}\n{} ({})\n\n", clock(from), clock(to), text, language));
+    };
+    if index > 0 {
+        cue(start - quarter, start + quarter, format!("Across {}", index - 1));
+    }
+    cue(start + quarter, start + 2.0 * quarter, format!("<i>Line {}</i>", index));
+    if index + 1 < opts.segments {
+        cue(start + 3.0 * quarter, start + 5.0 * quarter, format!("Across {}", index));
+    }
+    out.into_bytes()
+}
+
+/// Training pages of a small course: lesson 1 and two modules, the second
+/// of which ends with a lesson without a video.
+fn training_page(id: &str) -> Option<String> {
+    type Links = &'static [(&'static str, &'static str)];
+    let (title, modules, lessons): (&str, Links, Links) = match id {
+        "1" => ("Fixture course", &[("2", "Basics"), ("3", "Advanced: part 1/2")], &[("1", "Welcome")]),
+        "2" => ("Basics", &[("1", "Fixture course")], &[("2", "First steps"), ("3", "Second steps")]),
+        "3" => ("Advanced: part 1/2", &[("1", "Fixture course")], &[("4", "Deep dive"), (TEXT_LESSON, "Reading list")]),
+        _ => return None,
+    };
+    let mut page = format!("<html><head><title>{} - Fixture school</title></head><body><h1>{}</h1>\n", title, title);
+    for (id, title) in modules {
+        page.push_str(&format!("<a href=\"/teach/control/stream/view/id/{}\">{}</a>\n", id, title));
+    }
+    for (id, title) in lessons {
+        page.push_str(&format!(
+            "<a class=\"lesson\" href=\"/teach/control/lesson/view/id/{}\"><div class=\"title\">{}</div></a>\n",
+            id, title
+        ));
+    }
+    page.push_str("</body></html>\n");
+    Some(page)
+}
+
+/// `/v<N>/file` for a video variant, `/a<N>/file` for an audio rendition.
+/// Returns the directory, a number that seeds the segment data, and the file.
+fn parse_variant_path(path: &str, audio: usize) -> Option<(&str, usize, &str)> {
+    let (dir, file) = path.strip_prefix('/')?.split_once('/')?;
+    let (kind, number) = dir.split_at_checked(1)?;
+    let number: usize = number.parse().ok()?;
+    match kind {
+        "v" if number < VARIANTS.len() => Some((dir, number, file)),
+        "a" if number < audio => Some((dir, VARIANTS.len() + number, file)),
+        _ => None,
+    }
+}
+
+/// `http://<addr>`, or nothing with `--relative`, so that URIs are resolved
+/// against the playlist.
+fn origin(state: &State) -> String {
+    match state.opts.relative {
+        true => String::new(),
+        false => format!("http://{}", state.addr),
+    }
+}
+
+/// The URI of `file` in variant directory `dir`, as a media playlist lists it.
+fn segment_uri(state: &State, dir: &str, file: &str) -> String {
+    match state.opts.relative {
+        true => file.to_string(),
+        false => format!("http://{}/{}/{}", state.addr, dir, file),
+    }
+}
+
+fn master_playlist(state: &State) -> String {
+    let mut out = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    for (i, language) in state.opts.audio.iter().enumerate() {
+        out.push_str(&format!(
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"{}\",LANGUAGE=\"{}\",DEFAULT={},AUTOSELECT=YES,URI=\"{}/a{}/index.m3u8\"\n",
+            language.to_uppercase(),
+            language,
+            if i == 0 { "YES" } else { "NO" },
+            origin(state),
+            i
+        ));
+    }
+    for (i, language) in state.opts.subs.iter().enumerate() {
+        out.push_str(&format!(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"{}\",LANGUAGE=\"{}\",URI=\"{}/s{}/index.m3u8\"\n",
+            language.to_uppercase(),
+            language,
+            origin(state),
+            i
+        ));
+    }
+    let mut group = if state.opts.audio.is_empty() { "" } else { ",AUDIO=\"audio\"" }.to_string();
+    if !state.opts.subs.is_empty() {
+        group.push_str(",SUBTITLES=\"subs\"");
+    }
+    for (i, (bandwidth, resolution)) in VARIANTS.iter().enumerate() {
+        out.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}{}\n{}/v{}/index.m3u8\n",
+            bandwidth, resolution, group, origin(state), i
+        ));
+    }
+    out
+}
+
+fn media_playlist(state: &State, dir: &str, variant: usize) -> String {
+    let opts = &state.opts;
+    let published = state.published();
+    let mut out = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+        opts.segment_duration.ceil() as u64,
+        published.start
+    );
+    // Before the key, so the init segment is not encrypted.
+    if opts.fmp4 {
+        out.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", segment_uri(state, dir, "init.mp4")));
+    }
+    if opts.aes {
+        out.push_str(&format!(
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"{}/key.bin\"\n",
+            origin(state)
+        ));
+    }
+    if let Some(intro) = opts.discontinuity {
+        out.push_str("#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:00.000Z\n");
+        out.push_str(&format!(
+            "#EXT-X-DATERANGE:ID=\"intro\",CLASS=\"{}\",START-DATE=\"2024-01-01T00:00:00.000Z\",DURATION={:.3}\n",
+            INTRO_CLASS,
+            intro as f64 * opts.segment_duration
+        ));
+    }
+    let size = |i| segment_body(opts, variant, i).len();
+    let mut offset: usize = (0..published.start).map(size).sum();
+    for i in published.clone() {
+        if opts.discontinuity == Some(i) {
+            out.push_str("#EXT-X-DISCONTINUITY\n");
+        }
+        let file = match opts.byterange {
+            true => {
+                // The offset is left out where the slice follows the one before.
+                let at = if i == published.start { format!("@{}", offset) } else { String::new() };
+                out.push_str(&format!("#EXT-X-BYTERANGE:{}{}\n", size(i), at));
+                offset += size(i);
+                "all.ts".to_string()
+            }
+            false => format!("seg{:05}{}", i, segment_extension(opts)),
+        };
+        out.push_str(&format!("#EXTINF:{:.3},\n{}\n", opts.segment_duration, segment_uri(state, dir, &file)));
+    }
+    if published.end == opts.segments {
+        out.push_str("#EXT-X-ENDLIST\n");
+    }
+    out
+}
+
+/// Presentation time of a segment's first frame (90 kHz). Streams start at 10s,
+/// as packagers commonly do, and restart there after the discontinuity.
+fn segment_pts(opts: &FixtureOptions, index: usize) -> u64 {
+    let position = match opts.discontinuity {
+        Some(d) if index >= d => index - d,
+        _ => index,
+    };
+    900_000 + (position as f64 * opts.segment_duration * 90_000.0) as u64
+}
+
+/// Deterministic MPEG-TS segment: a PAT and a PMT (H.264 on PID 0x100, AAC on
+/// 0x101) followed by payload packets on PID 0x100 with a running continuity
+/// counter, so sync-byte validation and stream probing pass. The first payload
+/// packet carries a PCR and a PES header with PTS/DTS `pts`.
+pub fn segment_bytes(variant: usize, index: usize, size: usize, pts: u64) -> Vec<u8> {
+    let packets = size.div_ceil(TS_PACKET).max(3);
+    let mut seed = (variant as u32).wrapping_mul(0x9E37_79B9) ^ (index as u32).wrapping_add(1);
+    let mut out = Vec::with_capacity(packets * TS_PACKET);
+    // PAT: program 1 -> PMT on PID 0x1000.
+    psi_packet(&mut out, 0x0000, &[0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01, 0xf0, 0x00]);
+    // PMT: PCR on 0x100, H.264 (0x1b) on 0x100, AAC (0x0f) on 0x101.
+    psi_packet(
+        &mut out,
+        0x1000,
+        &[
+            0x02, 0xb0, 0x17, 0x00, 0x01, 0xc1, 0x00, 0x00, 0xe1, 0x00, 0xf0, 0x00, //
+            0x1b, 0xe1, 0x00, 0xf0, 0x00, //
+            0x0f, 0xe1, 0x01, 0xf0, 0x00,
+        ],
+    );
+    for p in 0..packets - 2 {
+        let start = out.len();
+        if p == 0 {
+            let dts = pts.saturating_sub(3003);
+            out.extend_from_slice(&[0x47, 0x41, 0x00, 0x30, 0x07, 0x10]);
+            out.extend_from_slice(&encode_pcr(dts));
+            out.extend_from_slice(&[0x00, 0x00, 0x01, 0xe0, 0x00, 0x00, 0x80, 0xc0, 0x0a]);
+            out.extend_from_slice(&encode_timestamp(0x30, pts));
+            out.extend_from_slice(&encode_timestamp(0x10, dts));
+        } else {
+            out.extend_from_slice(&[0x47, 0x01, 0x00, 0x10 | (p as u8 & 0x0f)]);
+        }
+        for _ in out.len() - start..TS_PACKET {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            out.push(seed as u8);
+        }
+    }
+    out
+}
+
+/// An fMP4 init segment: `ftyp` and a `moov` with an H.264 and an AAC track,
+/// enough for stream probing.
+fn init_segment() -> Vec<u8> {
+    let track = |entry: &[u8; 4]| {
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(mp4_box(entry, &[0; 8]));
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl)))
+    };
+    let mut out = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso6mp41");
+    out.extend(mp4_box(b"moov", &[track(b"avc1"), track(b"mp4a")].concat()));
+    out
+}
+
+/// An fMP4 fragment of about `size` bytes: a `moof` with the sequence number
+/// and an `mdat` of noise seeded like [`segment_bytes`].
+fn fragment_bytes(variant: usize, index: usize, size: usize) -> Vec<u8> {
+    let mut seed = (variant as u32).wrapping_mul(0x9E37_79B9) ^ (index as u32).wrapping_add(1);
+    let mut mfhd = vec![0, 0, 0, 0];
+    mfhd.extend_from_slice(&(index as u32 + 1).to_be_bytes());
+    let mut out = mp4_box(b"moof", &mp4_box(b"mfhd", &mfhd));
+    let data: Vec<u8> = (0..size.saturating_sub(out.len() + 8))
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect();
+    out.extend(mp4_box(b"mdat", &data));
+    out
+}
+
+/// An ISO BMFF box.
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// PES timestamp field: 4-bit `prefix` and a 33-bit value with marker bits.
+fn encode_timestamp(prefix: u8, ts: u64) -> [u8; 5] {
+    [
+        prefix | ((ts >> 29) as u8 & 0x0e) | 1,
+        (ts >> 22) as u8,
+        ((ts >> 14) as u8 & 0xfe) | 1,
+        (ts >> 7) as u8,
+        ((ts << 1) as u8 & 0xfe) | 1,
+    ]
+}
+
+/// Adaptation-field PCR with a zero extension.
+fn encode_pcr(base: u64) -> [u8; 6] {
+    [(base >> 25) as u8, (base >> 17) as u8, (base >> 9) as u8, (base >> 1) as u8, ((base & 1) << 7) as u8 | 0x7e, 0]
+}
+
+/// Appends a TS packet carrying one PSI `section` (without its CRC) on `pid`.
+fn psi_packet(out: &mut Vec<u8>, pid: u16, section: &[u8]) {
+    let start = out.len();
+    out.extend_from_slice(&[0x47, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0x00]);
+    out.extend_from_slice(section);
+    out.extend_from_slice(&crc32_mpeg(section).to_be_bytes());
+    out.resize(start + TS_PACKET, 0xff);
+}
+
+/// CRC-32/MPEG-2, as used by PSI sections.
+fn crc32_mpeg(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,223 @@
+//! Scraping of GetCourse pages: lesson and module lists on training pages
+//! and the `data-master` playlist link of the lesson player.
+
+use crate::{
+    http::HttpFetcher,
+    lms::{self, PageVideo},
+    retry::Retry,
+};
+use anyhow::Result;
+use url::Url;
+
+/// A lesson link found on a training page.
+#[derive(Debug, Clone)]
+pub struct Lesson {
+    pub id: String,
+    pub title: String,
+    pub url: Url,
+}
+
+/// A module (nested training) linked from a training page.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub title: String,
+    pub url: Url,
+}
+
+/// Lists the lessons linked from a training page, in page order.
+pub fn list_lessons(html: &str, base: &Url) -> Vec<Lesson> {
+    links(html, base, "/lesson/view/id/", "Lesson")
+        .into_iter()
+        .map(|(id, title, url)| Lesson { id, title, url })
+        .collect()
+}
+
+/// Lists the modules linked from a training page, in page order, leaving out
+/// links to the page itself.
+pub fn list_modules(html: &str, base: &Url) -> Vec<Module> {
+    let own = id_after(base.path(), "/stream/view/id/");
+    links(html, base, "/stream/view/id/", "Module")
+        .into_iter()
+        .filter(|(id, _, _)| own.as_ref() != Some(id))
+        .map(|(_, title, url)| Module { title, url })
+        .collect()
+}
+
+/// Links whose path contains `marker` followed by an ID, as (ID, title, URL),
+/// once per ID. Untitled ones are named `<kind> <ID>`.
+fn links(html: &str, base: &Url, marker: &str, kind: &str) -> Vec<(String, String, Url)> {
+    let mut found: Vec<(String, String, Url)> = Vec::new();
+    for (tag, inner) in anchors(html) {
+        let Some(href) = attr(tag, "href") else {
+            continue;
+        };
+        let Some(id) = id_after(&href, marker) else {
+            continue;
+        };
+        if found.iter().any(|(other, _, _)| *other == id) {
+            continue;
+        }
+        let Ok(url) = base.join(&href) else {
+            continue;
+        };
+        let title = text(inner);
+        let title = if title.is_empty() { format!("{} {}", kind, id) } else { title };
+        found.push((id, title, url));
+    }
+    found
+}
+
+/// Title of a training page: its first `<h1>`, else the `<title>`.
+pub fn page_title(html: &str) -> Option<String> {
+    ["h1", "title"].iter().find_map(|name| {
+        let start = html.find(&format!("<{}", name))?;
+        let body = &html[start + html[start..].find('>')? + 1..];
+        let title = text(&body[..body.find(&format!("</{}>", name))?]);
+        (!title.is_empty()).then_some(title)
+    })
+}
+
+/// Fetches a lesson page and returns the master playlist URL of its video,
+/// following the player iframe when the link is not on the page itself.
+pub async fn resolve_lesson_playlist(fetcher: &dyn HttpFetcher, lesson_url: &Url, retry: Retry) -> Result<PageVideo> {
+    lms::find_page_playlist(fetcher, lesson_url, retry, is_player).await
+}
+
+/// The master playlist of a lesson page already fetched as `html`, or `None`
+/// for a lesson without a video.
+pub async fn lesson_playlist(
+    fetcher: &dyn HttpFetcher,
+    lesson_url: &Url,
+    html: &str,
+    retry: Retry,
+) -> Result<Option<String>> {
+    lms::page_playlist(fetcher, lesson_url, html, retry, is_player).await
+}
+
+fn is_player(src: &str) -> bool {
+    src.contains("player")
+}
+
+/// The ID after `marker` in `href`, e.g. `123` in
+/// `/teach/control/lesson/view/id/123` (optionally with a query string).
+fn id_after(href: &str, marker: &str) -> Option<String> {
+    let rest = &href[href.find(marker)? + marker.len()..];
+    let id: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    (!id.is_empty()).then_some(id)
+}
+
+/// Opening tags named `name`, without the surrounding `<` `>`.
+pub fn tags<'a>(html: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}", name);
+    html.match_indices(open.as_str())
+        .filter_map(|(i, _)| {
+            let rest = &html[i + open.len()..];
+            if !rest.starts_with(|c: char| c.is_whitespace()) {
+                return None;
+            }
+            rest.find('>').map(|end| &rest[..end])
+        })
+        .collect()
+}
+
+/// `<a ...>inner</a>` pairs as (attributes, inner HTML).
+fn anchors(html: &str) -> Vec<(&str, &str)> {
+    let mut out = Vec::new();
+    let mut rest = html;
+    while let Some(i) = rest.find("<a") {
+        rest = &rest[i + 2..];
+        if !rest.starts_with(|c: char| c.is_whitespace()) {
+            continue;
+        }
+        let Some(tag_end) = rest.find('>') else { break };
+        let tag = &rest[..tag_end];
+        let body = &rest[tag_end + 1..];
+        let inner_end = body.find("</a>").unwrap_or(body.len());
+        out.push((tag, &body[..inner_end]));
+        rest = &body[inner_end..];
+    }
+    out
+}
+
+/// Value of attribute `name` inside a tag's attribute string, entity-decoded.
+pub fn attr(tag: &str, name: &str) -> Option<String> {
+    let mut search = 0;
+    while let Some(pos) = tag[search..].find(name) {
+        let start = search + pos;
+        search = start + name.len();
+        let preceded_ok = tag[..start].ends_with(|c: char| c.is_whitespace());
+        let rest = tag[search..].trim_start();
+        let Some(rest) = rest.strip_prefix('=').filter(|_| preceded_ok) else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let value = match rest.chars().next()? {
+            q @ ('"' | '\'') => &rest[1..1 + rest[1..].find(q)?],
+            _ => rest.split(|c: char| c.is_whitespace() || c == '>').next()?,
+        };
+        return Some(decode_entities(value));
+    }
+    None
+}
+
+/// Visible text of an HTML fragment with whitespace collapsed.
+pub fn text(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                out.push(' ');
+            }
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    decode_entities(&out).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.find(';').filter(|&end| end <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
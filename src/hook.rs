@@ -0,0 +1,132 @@
+//! Post-processing hook (`--exec`): a shell command run once a download has
+//! finished, e.g. to remux with ffmpeg or to send a notification.
+//!
+//! Placeholders in the command are replaced with shell-quoted values:
+//! `{}` the output path, `{json}` the metadata JSON file, `{status}` the exit
+//! status of the download (0 on success). The same values are exported as
+//! `GETCOURSE_OUTPUT`, `GETCOURSE_METADATA` and `GETCOURSE_EXIT_STATUS`, along
+//! with `GETCOURSE_URL`.
+
+use crate::{exit, Report};
+use anyhow::{anyhow, Context, Result};
+use serde_json::json;
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// What the hook gets to know about a finished download.
+pub struct Outcome<'a> {
+    pub url: &'a str,
+    /// Local path or remote target the file was delivered to.
+    pub output: String,
+    pub segments: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// `None` on success.
+    pub error: Option<&'a anyhow::Error>,
+}
+
+impl Outcome<'_> {
+    /// What the command line would exit with (see [`crate::exit`]).
+    pub fn exit_status(&self) -> i32 {
+        self.error.map_or(0, exit::code)
+    }
+
+    fn metadata(&self) -> serde_json::Value {
+        json!({
+            "url": self.url,
+            "output": self.output,
+            "exit_status": self.exit_status(),
+            "error": self.error.map(|e| format!("{:#}", e)),
+            "segments": self.segments,
+            "bytes": self.bytes,
+            "elapsed_seconds": self.elapsed.as_secs_f64(),
+        })
+    }
+}
+
+/// Runs the `--exec` command (if any) for a finished download and passes the
+/// download's own result through. A failing hook fails a successful download;
+/// after a failed download it is only reported.
+pub async fn finish(
+    command: Option<&str>,
+    url: &str,
+    output: String,
+    started: Instant,
+    result: Result<Report>,
+) -> Result<()> {
+    let Some(command) = command else {
+        return result.map(drop);
+    };
+    let report = result.as_ref().ok().cloned().unwrap_or_default();
+    let outcome = Outcome {
+        url,
+        output,
+        segments: report.segments,
+        bytes: report.bytes,
+        elapsed: started.elapsed(),
+        error: result.as_ref().err(),
+    };
+    let hook = run(command, &outcome).await;
+    match (result, hook) {
+        (Ok(_), hook) => hook.context("Post-processing hook failed"),
+        (Err(e), Ok(())) => Err(e),
+        (Err(e), Err(hook)) => {
+            warn!("Post-processing hook failed: {:#}", hook);
+            Err(e)
+        }
+    }
+}
+
+/// Runs `command` through the platform shell and fails if it exits non-zero.
+/// The runtime goes on with other downloads meanwhile.
+pub async fn run(command: &str, outcome: &Outcome<'_>) -> Result<()> {
+    let mut metadata = tempfile::Builder::new()
+        .prefix("getcou-rs-")
+        .suffix(".json")
+        .tempfile()
+        .context("Cannot create metadata file for --exec")?;
+    serde_json::to_writer_pretty(&mut metadata, &outcome.metadata())?;
+    metadata.flush()?;
+    let metadata_path = metadata.path().to_string_lossy().into_owned();
+    let status = outcome.exit_status().to_string();
+
+    let expanded = command
+        .replace("{json}", &quote(&metadata_path))
+        .replace("{status}", &status)
+        .replace("{}", &quote(&outcome.output));
+    info!("Running: {}", expanded);
+
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(&expanded);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(&expanded);
+        shell
+    };
+    let exit = shell
+        .env("GETCOURSE_URL", outcome.url)
+        .env("GETCOURSE_OUTPUT", &outcome.output)
+        .env("GETCOURSE_METADATA", &metadata_path)
+        .env("GETCOURSE_EXIT_STATUS", &status)
+        .status()
+        .await
+        .context("Failed to start --exec command")?;
+    if !exit.success() {
+        return Err(anyhow!("--exec command exited with {}", exit));
+    }
+    Ok(())
+}
+
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
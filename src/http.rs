@@ -0,0 +1,744 @@
+use crate::{exit::Failure, progress};
+use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug, trace};
+#[cfg(feature = "hyper-backend")]
+use tracing::warn;
+
+#[cfg(not(any(feature = "reqwest-backend", feature = "hyper-backend")))]
+compile_error!("enable at least one HTTP backend: `reqwest-backend` or `hyper-backend`");
+
+/// Sent with every request unless [`ClientOptions::user_agent`] says
+/// otherwise: that of a current desktop browser, since some CDNs turn away
+/// anything else, and some APIs (GitHub among them) requests without one.
+pub const USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/140.0.0.0 Safari/537.36";
+
+/// A minimal response as seen by the download pipeline, independent of the
+/// HTTP library that produced it.
+pub struct Response {
+    pub status: u16,
+    /// `Content-Length`, when the server sent one.
+    pub length: Option<u64>,
+    /// The URL the response came from after redirects, when the backend
+    /// knows it. Relative URIs in a playlist are resolved against it.
+    pub url: Option<String>,
+    /// `Content-Type`, when the server sent one.
+    pub content_type: Option<String>,
+    /// The `name=value` part of every `Set-Cookie` header.
+    pub cookies: Vec<String>,
+    pub body: BoxStream<'static, Result<Bytes>>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub async fn bytes(mut self) -> Result<Bytes> {
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = self.body.next().await {
+            buf.extend_from_slice(&chunk?);
+        }
+        Ok(buf.freeze())
+    }
+
+    pub async fn text(self) -> Result<String> {
+        let bytes = self.bytes().await?;
+        String::from_utf8(bytes.to_vec()).context("Response body is not valid UTF-8")
+    }
+}
+
+/// Backend-agnostic HTTP client used for playlists and segments.
+pub trait HttpFetcher: Send + Sync {
+    /// GET `url`, sending `headers` in addition to the defaults.
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>>;
+
+    /// HEAD `url`: the status and length of a GET without the body.
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>>;
+
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Response>> {
+        self.request(url, &[])
+    }
+
+    /// POST `form` to `url` as `application/x-www-form-urlencoded`. Only the
+    /// network backends can; downloads never need it, logging in does.
+    fn post_form<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a [(String, String)],
+        form: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Response>> {
+        let _ = (headers, form);
+        Box::pin(async move { Err(anyhow!("Cannot POST to {} through this client", url)) })
+    }
+}
+
+/// `form` encoded as an `application/x-www-form-urlencoded` body.
+fn form_body(form: &[(String, String)]) -> String {
+    url::form_urlencoded::Serializer::new(String::new()).extend_pairs(form).finish()
+}
+
+/// The `name=value` part of a `Set-Cookie` header.
+fn cookie_pair(set_cookie: &str) -> Option<String> {
+    let pair = set_cookie.split(';').next()?.trim();
+    pair.contains('=').then(|| pair.to_string())
+}
+
+/// Sends fixed extra headers (e.g. those required by an extractor) with every
+/// request of `inner`. They replace headers of the same name given with the
+/// request, so `--header Referer: ...` wins over an extractor's guess.
+pub struct WithHeaders {
+    inner: Arc<dyn HttpFetcher>,
+    headers: Vec<(String, String)>,
+}
+
+impl WithHeaders {
+    pub fn new(inner: Arc<dyn HttpFetcher>, headers: Vec<(String, String)>) -> Self {
+        Self { inner, headers }
+    }
+
+    fn merged(&self, headers: &[(String, String)]) -> Vec<(String, String)> {
+        let mut all: Vec<_> = headers
+            .iter()
+            .filter(|(name, _)| !self.headers.iter().any(|(own, _)| own.eq_ignore_ascii_case(name)))
+            .cloned()
+            .collect();
+        all.extend_from_slice(&self.headers);
+        all
+    }
+}
+
+impl HttpFetcher for WithHeaders {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let all = self.merged(headers);
+            self.inner.request(url, &all).await
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let all = self.merged(headers);
+            self.inner.head(url, &all).await
+        })
+    }
+}
+
+/// Counts the response bytes of `inner` and fails requests and bodies once
+/// more than `cap` bytes in total have been received, so a download over a
+/// metered connection stops instead of running up the bill.
+pub struct Metered {
+    inner: Arc<dyn HttpFetcher>,
+    cap: u64,
+    used: Arc<AtomicU64>,
+}
+
+impl Metered {
+    pub fn new(inner: Arc<dyn HttpFetcher>, cap: u64) -> Self {
+        Self { inner, cap, used: Arc::new(AtomicU64::new(0)) }
+    }
+}
+
+fn cap_reached(cap: u64) -> anyhow::Error {
+    anyhow!("Data cap of {} reached (max_total_bytes); stopping", progress::size(cap))
+}
+
+impl HttpFetcher for Metered {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            if self.used.load(Ordering::Relaxed) >= self.cap {
+                return Err(cap_reached(self.cap));
+            }
+            let mut resp = self.inner.request(url, headers).await?;
+            let (used, cap) = (Arc::clone(&self.used), self.cap);
+            resp.body = resp
+                .body
+                .map(move |chunk| {
+                    let chunk = chunk?;
+                    let len = chunk.len() as u64;
+                    if used.fetch_add(len, Ordering::Relaxed) + len > cap {
+                        return Err(cap_reached(cap));
+                    }
+                    Ok(chunk)
+                })
+                .boxed();
+            Ok(resp)
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            if self.used.load(Ordering::Relaxed) >= self.cap {
+                return Err(cap_reached(self.cap));
+            }
+            self.inner.head(url, headers).await
+        })
+    }
+}
+
+/// Limits the combined download rate of all requests of `inner` to `rate`
+/// bytes per second: a token bucket shared by every response body, allowing
+/// bursts of up to one second's worth.
+pub struct Throttled {
+    inner: Arc<dyn HttpFetcher>,
+    bucket: RateLimit,
+    /// Every request gets a bucket of its own, as full as `bucket` was.
+    each: bool,
+}
+
+/// A download rate limit that several fetchers can share, so that
+/// downloads running side by side stay within it together.
+#[derive(Clone)]
+pub struct RateLimit(Arc<Mutex<Bucket>>);
+
+impl RateLimit {
+    /// `rate` bytes per second.
+    pub fn new(rate: u64) -> Self {
+        Self(Arc::new(Mutex::new(Bucket { rate: rate as f64, tokens: rate as f64, updated: Instant::now() })))
+    }
+}
+
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes `bytes` out of the bucket and returns how long to wait before
+    /// using them. The bucket may go negative, which queues later callers
+    /// behind this one.
+    fn reserve(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * self.rate).min(self.rate);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+impl Throttled {
+    pub fn new(inner: Arc<dyn HttpFetcher>, rate: u64) -> Self {
+        Self::sharing(inner, RateLimit::new(rate))
+    }
+
+    /// Throttles `inner` within `limit`, together with the other fetchers
+    /// sharing it.
+    pub fn sharing(inner: Arc<dyn HttpFetcher>, limit: RateLimit) -> Self {
+        Self { inner, bucket: limit, each: false }
+    }
+
+    /// Limits every request of `inner` to `rate` bytes per second on its
+    /// own, however many run at once.
+    pub fn each(inner: Arc<dyn HttpFetcher>, rate: u64) -> Self {
+        Self { inner, bucket: RateLimit::new(rate), each: true }
+    }
+}
+
+impl HttpFetcher for Throttled {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let mut resp = self.inner.request(url, headers).await?;
+            let bucket = match self.each {
+                true => RateLimit::new(self.bucket.0.lock().unwrap_or_else(|e| e.into_inner()).rate as u64).0,
+                false => Arc::clone(&self.bucket.0),
+            };
+            resp.body = resp
+                .body
+                .then(move |chunk| {
+                    let wait = match &chunk {
+                        Ok(chunk) => bucket.lock().unwrap_or_else(|e| e.into_inner()).reserve(chunk.len()),
+                        Err(_) => Duration::ZERO,
+                    };
+                    async move {
+                        if !wait.is_zero() {
+                            tokio::time::sleep(wait).await;
+                        }
+                        chunk
+                    }
+                })
+                .boxed();
+            Ok(resp)
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        self.inner.head(url, headers)
+    }
+}
+
+/// How long a connection is kept open for reuse while no request needs it.
+const POOL_IDLE: Duration = Duration::from_secs(90);
+
+/// TCP (and HTTP/2) keep-alive probes on open connections, so that one the
+/// network dropped is noticed before a request waits on it.
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// The address family connections are made over, like curl's `-4`/`-6`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpFamily {
+    /// Whichever the resolver offers, the other one as a fallback.
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    pub const NAMES: &[&str] = &["any", "ipv4", "ipv6"];
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "any" => Ok(IpFamily::Any),
+            "ipv4" | "4" => Ok(IpFamily::Ipv4),
+            "ipv6" | "6" => Ok(IpFamily::Ipv6),
+            other => Err(anyhow!("Unknown IP family {:?}; expected one of {}", other, Self::NAMES.join(", "))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IpFamily::Any => "any",
+            IpFamily::Ipv4 => "ipv4",
+            IpFamily::Ipv6 => "ipv6",
+        }
+    }
+
+    /// The address to bind to: an unspecified one of the family makes the
+    /// backends skip resolved addresses of the other.
+    fn local_address(self) -> Option<IpAddr> {
+        match self {
+            IpFamily::Any => None,
+            IpFamily::Ipv4 => Some(Ipv4Addr::UNSPECIFIED.into()),
+            IpFamily::Ipv6 => Some(Ipv6Addr::UNSPECIFIED.into()),
+        }
+    }
+}
+
+/// How the network backends reach servers and how long they wait for them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// Used instead of the proxy named by the environment.
+    pub proxy: Option<String>,
+    /// PEM file of CA certificates trusted besides the system ones.
+    pub ca_cert: Option<PathBuf>,
+    /// Accepts any certificate, even an expired one or one for another host.
+    pub insecure: bool,
+    /// Gives up on a request once the server has sent nothing for this long,
+    /// be it before the response or in the middle of its body.
+    pub timeout: Option<Duration>,
+    /// Gives up on connecting to a server after this long.
+    pub connect_timeout: Option<Duration>,
+    /// Idle connections kept open per host, which should cover the requests
+    /// made at once; 0 leaves the backend's default.
+    pub pool: usize,
+    /// Sent instead of [`USER_AGENT`].
+    pub user_agent: Option<String>,
+    pub family: IpFamily,
+}
+
+/// Returns the fetcher for the backend selected at build time. When both
+/// backends are compiled in, reqwest wins unless `GETCOURSE_HTTP_BACKEND=hyper`.
+/// Its connections are pooled and kept alive, so one fetcher should serve
+/// every request of a run, wrapped as needed.
+///
+/// Requests go through the proxy of `options` when given, and otherwise
+/// through the proxy named by `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY`, if
+/// any.
+pub fn default_fetcher(options: &ClientOptions) -> Result<Arc<dyn HttpFetcher>> {
+    #[cfg(all(feature = "hyper-backend", feature = "reqwest-backend"))]
+    if std::env::var("GETCOURSE_HTTP_BACKEND").is_ok_and(|v| v == "hyper") {
+        return Ok(Arc::new(HyperFetcher::new(options)?));
+    }
+
+    #[cfg(feature = "reqwest-backend")]
+    return Ok(Arc::new(ReqwestFetcher::new(options)?));
+
+    #[cfg(all(feature = "hyper-backend", not(feature = "reqwest-backend")))]
+    return Ok(Arc::new(HyperFetcher::new(options)?));
+}
+
+fn timed_out(url: &str, limit: Duration) -> anyhow::Error {
+    Failure::Network.of(anyhow!("No answer from {} for {:?} (timeout)", url, limit))
+}
+
+/// Waits for `response`, failing once `timeout` has passed.
+async fn timed<T>(timeout: Option<Duration>, url: &str, response: impl Future<Output = Result<T>>) -> Result<T> {
+    match timeout {
+        Some(limit) => tokio::time::timeout(limit, response).await.map_err(|_| timed_out(url, limit))?,
+        None => response.await,
+    }
+}
+
+/// `body`, failing once `timeout` passes without another chunk: a stalled
+/// transfer is given up, while a slow one that keeps moving is not.
+fn stall_limited(
+    body: BoxStream<'static, Result<Bytes>>,
+    timeout: Option<Duration>,
+    url: &str,
+) -> BoxStream<'static, Result<Bytes>> {
+    let Some(limit) = timeout else {
+        return body;
+    };
+    let url = url.to_string();
+    futures::stream::try_unfold((body, url), move |(mut body, url)| async move {
+        match tokio::time::timeout(limit, body.next()).await {
+            Ok(Some(chunk)) => Ok(Some((chunk?, (body, url)))),
+            Ok(None) => Ok(None),
+            Err(_) => Err(timed_out(&url, limit)),
+        }
+    })
+    .boxed()
+}
+
+/// Proxy variables in the order the HTTP clients consult them.
+const PROXY_VARIABLES: &[&str] =
+    &["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"];
+
+/// The first proxy set in the environment, as the variable and its value.
+fn env_proxy() -> Option<(&'static str, String)> {
+    PROXY_VARIABLES.iter().find_map(|name| Some((*name, std::env::var(name).ok().filter(|v| !v.is_empty())?)))
+}
+
+/// Fails for SOCKS proxies, which no backend can speak yet. Without this
+/// check reqwest would skip such a proxy from the environment silently and
+/// connect directly, which is exactly what its user wanted to avoid.
+#[cfg(feature = "reqwest-backend")]
+fn check_proxy_scheme(proxy: &str, origin: &str) -> Result<()> {
+    if proxy.to_ascii_lowercase().starts_with("socks") {
+        return Err(anyhow!(
+            "{} names a SOCKS proxy ({}), which is not supported yet; use an HTTP proxy",
+            origin,
+            proxy
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "reqwest-backend")]
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+    timeout: Option<Duration>,
+}
+
+#[cfg(feature = "reqwest-backend")]
+impl ReqwestFetcher {
+    pub fn new(options: &ClientOptions) -> Result<Self> {
+        // HTTP/2 is negotiated where the server offers it.
+        let mut builder = reqwest::Client::builder()
+            .user_agent(options.user_agent.as_deref().unwrap_or(USER_AGENT))
+            .pool_idle_timeout(POOL_IDLE)
+            .tcp_keepalive(KEEPALIVE)
+            .http2_keep_alive_interval(KEEPALIVE)
+            .http2_adaptive_window(true);
+        if options.pool > 0 {
+            builder = builder.pool_max_idle_per_host(options.pool);
+        }
+        if let Some(limit) = options.connect_timeout {
+            builder = builder.connect_timeout(limit);
+        }
+        builder = builder.local_address(options.family.local_address());
+        match options.proxy.as_deref() {
+            Some(proxy) => {
+                check_proxy_scheme(proxy, "The proxy setting")?;
+                let proxy = reqwest::Proxy::all(proxy).map_err(|_| anyhow!("Invalid proxy URL {:?}", proxy))?;
+                builder = builder.proxy(proxy);
+            }
+            // reqwest picks these up by itself.
+            None => {
+                if let Some((name, value)) = env_proxy() {
+                    check_proxy_scheme(&value, name)?;
+                }
+            }
+        }
+        builder = tls(builder, options)?;
+        Ok(Self { client: builder.build()?, timeout: options.timeout })
+    }
+}
+
+/// `builder` trusting the CA certificates of `options`, or any certificate
+/// at all when it is insecure.
+#[cfg(all(feature = "reqwest-backend", feature = "native-tls"))]
+fn tls(mut builder: reqwest::ClientBuilder, options: &ClientOptions) -> Result<reqwest::ClientBuilder> {
+    if let Some(path) = &options.ca_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Cannot read the CA certificate {}", path.display()))
+            .map_err(|e| Failure::Usage.of(e))?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem)
+            .ok()
+            .filter(|certs| !certs.is_empty())
+            .ok_or_else(|| Failure::Usage.of(anyhow!("{} holds no PEM certificate", path.display())))?;
+        debug!("Trusting {} CA certificate(s) from {}", certs.len(), path.display());
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if options.insecure {
+        warn_insecure();
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+/// Without TLS there are no certificates to check.
+#[cfg(all(feature = "reqwest-backend", not(feature = "native-tls")))]
+fn tls(builder: reqwest::ClientBuilder, options: &ClientOptions) -> Result<reqwest::ClientBuilder> {
+    if options.ca_cert.is_some() || options.insecure {
+        return Err(Failure::Usage.of(anyhow!("This build has no TLS support; --ca-cert and --insecure do not apply")));
+    }
+    Ok(builder)
+}
+
+/// Said once per run, however many clients are built.
+#[cfg(all(feature = "reqwest-backend", feature = "native-tls"))]
+fn warn_insecure() {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| {
+        tracing::warn!(
+            "Warning: --insecure: TLS certificates are not checked, so anyone on the network path can read and \
+             alter the traffic, session cookies included; prefer --ca-cert"
+        )
+    });
+}
+
+#[cfg(feature = "reqwest-backend")]
+impl ReqwestFetcher {
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        headers: &[(String, String)],
+        form: Option<&[(String, String)]>,
+    ) -> Result<Response> {
+        debug!("{} {}", method, url);
+        let mut req = self.client.request(method.clone(), url);
+        for (name, value) in headers {
+            // Names only; values carry cookies and tokens.
+            trace!("  with header {}", name);
+            req = req.header(name.as_str(), value.as_str());
+        }
+        if let Some(form) = form {
+            req = req
+                .header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(form_body(form));
+        }
+        let resp = timed(self.timeout, url, async { Ok(req.send().await?) }).await?;
+        let status = resp.status().as_u16();
+        if resp.url().as_str() != url {
+            debug!("{} {} was redirected to {}", method, url, resp.url());
+        }
+        let final_url = Some(resp.url().to_string());
+        let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE).and_then(|v| Some(v.to_str().ok()?.into()));
+        let length = resp
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse().ok());
+        let cookies = resp
+            .headers()
+            .get_all(reqwest::header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| cookie_pair(v.to_str().ok()?))
+            .collect();
+        debug!("{} {} -> {} ({})", method, url, status, length.map_or("no length".into(), progress::size));
+        let body = futures::stream::try_unfold(resp, |mut resp| async move {
+            Ok(resp.chunk().await?.map(|chunk| (chunk, resp)))
+        });
+        let body = stall_limited(body.boxed(), self.timeout, url);
+        Ok(Response { status, length, url: final_url, content_type, cookies, body })
+    }
+}
+
+#[cfg(feature = "reqwest-backend")]
+impl HttpFetcher for ReqwestFetcher {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(self.send(reqwest::Method::GET, url, headers, None))
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(self.send(reqwest::Method::HEAD, url, headers, None))
+    }
+
+    fn post_form<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a [(String, String)],
+        form: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(self.send(reqwest::Method::POST, url, headers, Some(form)))
+    }
+}
+
+#[cfg(feature = "hyper-backend")]
+pub struct HyperFetcher {
+    #[cfg(feature = "hyper-tls")]
+    client: hyper::Client<hyper_tls::HttpsConnector<hyper::client::HttpConnector>>,
+    #[cfg(not(feature = "hyper-tls"))]
+    client: hyper::Client<hyper::client::HttpConnector>,
+    timeout: Option<Duration>,
+    user_agent: String,
+}
+
+#[cfg(feature = "hyper-backend")]
+impl HyperFetcher {
+    const MAX_REDIRECTS: usize = 10;
+
+    /// Plain hyper has no proxy support, so an explicit `proxy` is refused
+    /// and one from the environment is ignored with a warning. The TLS
+    /// options are refused as well.
+    pub fn new(options: &ClientOptions) -> Result<Self> {
+        if let Some(proxy) = &options.proxy {
+            return Err(anyhow!("The hyper backend does not support proxies ({}); use the reqwest one", proxy));
+        }
+        if options.ca_cert.is_some() || options.insecure {
+            return Err(anyhow!("The hyper backend does not support --ca-cert or --insecure; use the reqwest one"));
+        }
+        if let Some((name, _)) = env_proxy() {
+            warn!("Warning: {} is set, but the hyper backend does not use proxies; connecting directly", name);
+        }
+        let mut http = hyper::client::HttpConnector::new();
+        http.set_connect_timeout(options.connect_timeout);
+        http.set_keepalive(Some(KEEPALIVE));
+        http.set_local_address(options.family.local_address());
+        let mut builder = hyper::Client::builder();
+        builder.pool_idle_timeout(POOL_IDLE);
+        if options.pool > 0 {
+            builder.pool_max_idle_per_host(options.pool);
+        }
+        #[cfg(feature = "hyper-tls")]
+        let client = {
+            http.enforce_http(false);
+            builder.build(hyper_tls::HttpsConnector::new_with_connector(http))
+        };
+        #[cfg(not(feature = "hyper-tls"))]
+        let client = builder.build(http);
+        let user_agent = options.user_agent.clone().unwrap_or_else(|| USER_AGENT.to_string());
+        Ok(Self { client, timeout: options.timeout, user_agent })
+    }
+}
+
+#[cfg(feature = "hyper-backend")]
+impl HyperFetcher {
+    async fn send(
+        &self,
+        mut method: hyper::Method,
+        url: &str,
+        headers: &[(String, String)],
+        mut form: Option<&[(String, String)]>,
+    ) -> Result<Response> {
+        // hyper does not follow redirects on its own, and CDNs use them a lot.
+        let mut uri: hyper::Uri = url.parse().context("Invalid URL")?;
+        // Until a redirect leads to another host.
+        let mut same_host = true;
+        for _ in 0..=Self::MAX_REDIRECTS {
+            debug!("{} {}", method, uri);
+            let mut req = hyper::Request::builder().method(method.clone()).uri(uri.clone());
+            // One given with the request wins, as with reqwest.
+            if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("user-agent")) {
+                req = req.header(hyper::header::USER_AGENT, self.user_agent.as_str());
+            }
+            for (name, value) in headers {
+                if !same_host && SENSITIVE_HEADERS.iter().any(|sensitive| name.eq_ignore_ascii_case(sensitive)) {
+                    trace!("  without header {} on another host", name);
+                    continue;
+                }
+                // Names only; values carry cookies and tokens.
+                trace!("  with header {}", name);
+                req = req.header(name.as_str(), value.as_str());
+            }
+            let req = match form {
+                Some(form) => req
+                    .header(hyper::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(hyper::Body::from(form_body(form)))?,
+                None => req.body(hyper::Body::empty())?,
+            };
+            let resp = timed(self.timeout, url, async { Ok(self.client.request(req).await?) }).await?;
+            if resp.status().is_redirection()
+                && let Some(location) = resp.headers().get(hyper::header::LOCATION)
+            {
+                let next = resolve_location(&uri, location.to_str()?)?;
+                same_host &= next.host() == uri.host() && port(&next) == port(&uri);
+                uri = next;
+                debug!("{} redirected to {}", resp.status().as_u16(), uri);
+                // As browsers do, a form is not sent again.
+                if form.take().is_some() {
+                    method = hyper::Method::GET;
+                }
+                continue;
+            }
+            let status = resp.status().as_u16();
+            let length = resp
+                .headers()
+                .get(hyper::header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()?.parse().ok());
+            let content_type =
+                resp.headers().get(hyper::header::CONTENT_TYPE).and_then(|v| Some(v.to_str().ok()?.into()));
+            let cookies = resp
+                .headers()
+                .get_all(hyper::header::SET_COOKIE)
+                .iter()
+                .filter_map(|v| cookie_pair(v.to_str().ok()?))
+                .collect();
+            debug!("{} {} -> {} ({})", method, uri, status, length.map_or("no length".into(), progress::size));
+            let body = stall_limited(resp.into_body().map(|r| r.map_err(Into::into)).boxed(), self.timeout, url);
+            return Ok(Response { status, length, url: Some(uri.to_string()), content_type, cookies, body });
+        }
+        Err(anyhow::anyhow!("Too many redirects"))
+    }
+}
+
+#[cfg(feature = "hyper-backend")]
+impl HttpFetcher for HyperFetcher {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(self.send(hyper::Method::GET, url, headers, None))
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(self.send(hyper::Method::HEAD, url, headers, None))
+    }
+
+    fn post_form<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a [(String, String)],
+        form: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(self.send(hyper::Method::POST, url, headers, Some(form)))
+    }
+}
+
+#[cfg(feature = "hyper-backend")]
+fn resolve_location(base: &hyper::Uri, location: &str) -> Result<hyper::Uri> {
+    let base = url::Url::parse(&base.to_string()).context("Redirect from an invalid URL")?;
+    let mut url = base.join(location).with_context(|| format!("Invalid redirect location '{}'", location))?;
+    url.set_fragment(None);
+    url.as_str().parse().context("Invalid redirect location")
+}
+
+/// The port `uri` connects to, given or the default of its scheme.
+#[cfg(feature = "hyper-backend")]
+fn port(uri: &hyper::Uri) -> u16 {
+    uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    })
+}
+
+/// Headers that are not sent on to another host when a request is
+/// redirected there, as reqwest's redirect policy does too.
+#[cfg(feature = "hyper-backend")]
+const SENSITIVE_HEADERS: &[&str] = &["cookie", "authorization", "proxy-authorization"];
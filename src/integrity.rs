@@ -0,0 +1,92 @@
+//! Sanity checks on segment responses before they are stored. A CDN that
+//! answers `200` with an HTML error page, or cuts a body short, would
+//! otherwise leave garbage in the middle of the output; a [`Damaged`] segment
+//! is fetched again instead.
+
+use crate::http::Response;
+use std::fmt;
+
+const TS_PACKET: usize = 188;
+const TS_SYNC: u8 = 0x47;
+
+/// A segment response that cannot be what the playlist promised.
+#[derive(Debug)]
+pub struct Damaged(String);
+
+impl fmt::Display for Damaged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Damaged {}
+
+/// Checks one segment body as it arrives.
+pub struct SegmentCheck {
+    /// `Content-Length`, if the server sent one.
+    expected: Option<u64>,
+    received: u64,
+    /// Whether the body is MPEG-TS, known after the first byte.
+    ts: Option<bool>,
+}
+
+impl SegmentCheck {
+    /// Starts checking `response`, refusing it outright when it says it is
+    /// an HTML page.
+    pub fn new(response: &Response) -> Result<Self, Damaged> {
+        if let Some(content_type) = &response.content_type
+            && content_type.trim_start().to_ascii_lowercase().starts_with("text/html")
+        {
+            return Err(Damaged("the server sent an HTML page instead of a segment".into()));
+        }
+        Ok(Self { expected: response.length, received: 0, ts: None })
+    }
+
+    /// Like [`new`](Self::new), for a response that continues after
+    /// `prefix`, received earlier.
+    pub fn continuing(response: &Response, prefix: &[u8]) -> Result<Self, Damaged> {
+        let mut check = Self::new(response)?;
+        check.expected = check.expected.map(|length| length + prefix.len() as u64);
+        check.update(prefix)?;
+        Ok(check)
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), Damaged> {
+        if self.ts.is_none()
+            && let Some(&first) = chunk.iter().find(|b| !b.is_ascii_whitespace())
+        {
+            // MPEG-TS starts with a sync byte and (f)MP4 with a box size;
+            // markup is an error page whatever the content type claimed.
+            if first == b'<' {
+                return Err(Damaged("the server sent an HTML page instead of a segment".into()));
+            }
+            self.ts = Some(chunk[0] == TS_SYNC);
+        }
+        if self.ts == Some(true) {
+            // Offsets of packet starts within this chunk.
+            let skip = (TS_PACKET - self.received as usize % TS_PACKET) % TS_PACKET;
+            if let Some(i) = (skip..chunk.len()).step_by(TS_PACKET).find(|&i| chunk[i] != TS_SYNC) {
+                return Err(Damaged(format!("no MPEG-TS sync byte at offset {}", self.received + i as u64)));
+            }
+        }
+        self.received += chunk.len() as u64;
+        if let Some(expected) = self.expected.filter(|&expected| self.received > expected) {
+            return Err(Damaged(format!("more data than the announced {} bytes", expected)));
+        }
+        Ok(())
+    }
+
+    /// Checks the complete body.
+    pub fn finish(&self) -> Result<(), Damaged> {
+        if let Some(expected) = self.expected.filter(|&expected| self.received != expected) {
+            return Err(Damaged(format!("{} of the announced {} bytes arrived", self.received, expected)));
+        }
+        if self.received == 0 {
+            return Err(Damaged("the segment is empty".into()));
+        }
+        if self.ts == Some(true) && !self.received.is_multiple_of(TS_PACKET as u64) {
+            return Err(Damaged(format!("{} bytes is not a whole number of MPEG-TS packets", self.received)));
+        }
+        Ok(())
+    }
+}
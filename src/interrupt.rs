@@ -0,0 +1,49 @@
+//! Ctrl+C and SIGTERM. [`crate::within_deadline`] stops the job at either:
+//! the segment requests in flight are dropped, the work directory saves its
+//! state file for the next run, and the unfinished output is removed. A live
+//! recording and `--serve` wait for the signal themselves to wrap up what
+//! they have, and [`handle`] it meanwhile.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many [`Handling`] guards are alive.
+static HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Completes at Ctrl+C or SIGTERM, naming the one that came.
+pub async fn signalled() -> &'static str {
+    #[cfg(unix)]
+    if let Ok(mut term) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        tokio::select! {
+            _ = ctrl_c() => return "Ctrl+C",
+            _ = term.recv() => return "SIGTERM",
+        }
+    }
+    ctrl_c().await;
+    "Ctrl+C"
+}
+
+/// Never completes when the handler cannot be installed.
+async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// While it is alive, a signal is left to its holder instead of stopping the job.
+pub struct Handling(());
+
+pub fn handle() -> Handling {
+    HANDLERS.fetch_add(1, Ordering::SeqCst);
+    Handling(())
+}
+
+/// Whether a signal is being waited for by a [`Handling`] holder.
+pub fn handled() -> bool {
+    HANDLERS.load(Ordering::SeqCst) > 0
+}
+
+impl Drop for Handling {
+    fn drop(&mut self) {
+        HANDLERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
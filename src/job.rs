@@ -0,0 +1,221 @@
+//! Job files (`--job FILE`): several downloads in one JSON document, each
+//! with its own output, settings, headers and hook. YAML is not supported; a
+//! `.yaml` or `.yml` file is refused rather than misread as JSON. Jobs run one after
+//! another, or `--jobs N` at a time; a failed job is reported and the rest
+//! still run.
+//!
+//! ```json
+//! {
+//!   "settings": { "concurrency": 8 },
+//!   "output": "lesson-{n}.mp4",
+//!   "jobs": [
+//!     { "url": "https://example.com/1/master.m3u8" },
+//!     { "url": "https://example.com/2/master.m3u8", "output": "intro.mkv",
+//!       "settings": { "all_audio": true }, "headers": { "Referer": "https://example.com/" },
+//!       "exec": "notify-send {}", "start": "1:00", "end": "10:00" }
+//!   ]
+//! }
+//! ```
+
+use crate::{
+    config::Config,
+    download_to,
+    exit::Failure,
+    extractor, fetcher_on,
+    http::{default_fetcher, ClientOptions, HttpFetcher, RateLimit, Throttled},
+    parse_duration, parse_preview,
+    toml::Value,
+    DownloadOptions, Existing,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::{stream, StreamExt};
+use serde_json::{Map, Value as Json};
+use std::sync::Arc;
+use std::{fs, path::Path};
+use tracing::{info, warn};
+
+struct Job {
+    url: String,
+    output: String,
+    config: Config,
+    headers: Vec<(String, String)>,
+    exec: Option<String>,
+    opts: DownloadOptions,
+}
+
+/// Runs every job in `path`, `parallel` at a time. `exec` is the command
+/// line's `--exec`, used by jobs that name no hook of their own, and
+/// `existing` says what to do about outputs that exist already.
+pub async fn run(path: &Path, config: &Config, exec: Option<&str>, existing: Existing, parallel: usize) -> Result<()> {
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml")) {
+        let error = anyhow!("YAML is not supported in job files ({}); write the jobs as JSON", path.display());
+        return Err(Failure::Usage.of(error));
+    }
+    let text = fs::read_to_string(path).with_context(|| format!("Cannot read job file {}", path.display()))?;
+    let jobs = parse(&text, config, exec).with_context(|| format!("Invalid job file {}", path.display()))?;
+    // Jobs that reach servers the same way share a client, and with it its
+    // connections; the command line's rate limit holds for all of them
+    // together.
+    let mut made: Vec<(ClientOptions, Arc<dyn HttpFetcher>)> = Vec::new();
+    let mut clients = Vec::new();
+    for job in &jobs {
+        let mut options = job.config.client();
+        options.pool *= parallel;
+        let client = match made.iter().find(|(other, _)| *other == options) {
+            Some((_, client)) => Arc::clone(client),
+            None => {
+                let client = default_fetcher(&options)?;
+                made.push((options, Arc::clone(&client)));
+                client
+            }
+        };
+        clients.push(client);
+    }
+    let shared = (config.limit_rate > 0 && parallel > 1).then(|| RateLimit::new(config.limit_rate));
+
+    let total = jobs.len();
+    let mut failed: Vec<usize> = stream::iter(jobs.iter().zip(clients).enumerate())
+        .map(|(i, (job, client))| {
+            let shared = shared.clone();
+            async move {
+                info!("Job {}/{}: {} -> {}", i + 1, total, job.url, job.output);
+                let result = async {
+                    let mut fetcher = extractor::with_headers(&fetcher_on(client, &job.config), job.headers.clone());
+                    if let Some(limit) = shared {
+                        fetcher = Arc::new(Throttled::sharing(fetcher, limit));
+                    }
+                    let mut opts = job.opts.clone();
+                    opts.label = (parallel > 1).then(|| format!("job {}", i + 1));
+                    opts.existing = existing;
+                    download_to(&fetcher, &job.config, &opts, &job.url, &job.output, job.exec.as_deref()).await
+                }
+                .await;
+                match result {
+                    Ok(()) => None,
+                    Err(e) => {
+                        warn!("Job {} failed: {:#}", i + 1, e);
+                        Some(i + 1)
+                    }
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .filter_map(|failed| async move { failed })
+        .collect()
+        .await;
+    failed.sort_unstable();
+    match failed.len() {
+        0 => Ok(()),
+        n => Err(anyhow!(
+            "{} of {} jobs failed: {}",
+            n,
+            total,
+            failed.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// Checks the whole file up front, so that a typo in the last job does not
+/// surface only after hours of downloading the others.
+fn parse(text: &str, config: &Config, exec: Option<&str>) -> Result<Vec<Job>> {
+    let root = serde_json::from_str::<Json>(text)?;
+    let root = object(&root, "the top level")?;
+    let mut base = config.clone();
+    let mut template = None;
+    let mut list = None;
+    for (key, value) in root {
+        match key.as_str() {
+            "settings" => apply_settings(&mut base, value)?,
+            "output" => template = Some(string(value, "output")?),
+            "jobs" => list = Some(value.as_array().ok_or_else(|| anyhow!("jobs must be an array"))?),
+            other => return Err(anyhow!("Unknown key {:?}; expected settings, output or jobs", other)),
+        }
+    }
+    let list = list.ok_or_else(|| anyhow!("No jobs listed"))?;
+
+    let width = list.len().to_string().len();
+    let mut jobs = Vec::new();
+    for (i, value) in list.iter().enumerate() {
+        let number = format!("{:0width$}", i + 1, width = width);
+        let job =
+            parse_job(value, &base, template.as_deref(), &number, exec).with_context(|| format!("job {}", i + 1))?;
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+fn parse_job(value: &Json, base: &Config, template: Option<&str>, number: &str, exec: Option<&str>) -> Result<Job> {
+    let mut job = Job {
+        url: String::new(),
+        output: String::new(),
+        config: base.clone(),
+        headers: Vec::new(),
+        exec: exec.map(String::from),
+        opts: DownloadOptions::default(),
+    };
+    let mut output = None;
+    for (key, value) in object(value, "a job")? {
+        match key.as_str() {
+            "url" => job.url = string(value, key)?,
+            "output" => output = Some(string(value, key)?),
+            "settings" => apply_settings(&mut job.config, value)?,
+            "headers" => {
+                for (name, value) in object(value, "headers")? {
+                    job.headers.push((name.clone(), string(value, name)?));
+                }
+            }
+            "exec" => job.exec = Some(string(value, key)?).filter(|s| !s.is_empty()),
+            "preview" => job.opts.preview = Some(parse_preview(&string(value, key)?)?),
+            "start" => job.opts.start = Some(parse_duration(&string(value, key)?)?),
+            "end" => job.opts.end = Some(parse_duration(&string(value, key)?)?),
+            other => {
+                return Err(anyhow!(
+                    "Unknown key {:?}; expected url, output, settings, headers, exec, preview, start or end",
+                    other
+                ));
+            }
+        }
+    }
+    if job.url.is_empty() {
+        return Err(anyhow!("No url given"));
+    }
+    if let (Some(start), Some(end)) = (job.opts.start, job.opts.end)
+        && end <= start
+    {
+        return Err(anyhow!("end must be after start"));
+    }
+    job.output = output
+        .or_else(|| template.map(String::from))
+        .ok_or_else(|| anyhow!("No output given, and the file has no output template"))?
+        .replace("{n}", number);
+    Ok(job)
+}
+
+/// Applies a `settings` object; keys and values are those of the config file.
+fn apply_settings(config: &mut Config, value: &Json) -> Result<()> {
+    for (key, value) in object(value, "settings")? {
+        config.set_cli(key, setting(value).with_context(|| format!("settings.{}", key))?)?;
+    }
+    Ok(())
+}
+
+fn setting(value: &Json) -> Result<Value> {
+    Ok(match value {
+        Json::String(s) => Value::String(s.clone()),
+        Json::Bool(b) => Value::Boolean(*b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        Json::Array(items) => Value::Array(items.iter().map(setting).collect::<Result<_>>()?),
+        Json::Null | Json::Object(_) => return Err(anyhow!("expected a string, number, boolean or array")),
+    })
+}
+
+fn object<'a>(value: &'a Json, what: &str) -> Result<&'a Map<String, Json>> {
+    value.as_object().ok_or_else(|| anyhow!("{} must be an object", what))
+}
+
+fn string(value: &Json, key: &str) -> Result<String> {
+    value.as_str().map(String::from).ok_or_else(|| anyhow!("{} must be a string", key))
+}
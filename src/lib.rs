@@ -0,0 +1,2050 @@
+//! Downloads HLS video (GetCourse lessons and plain M3U8 playlists) into a
+//! single file. The `getcourse-downloader` binary is a thin command-line
+//! front end over this crate; programs can use [`Downloader`] directly:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use getcourse_downloader::Downloader;
+//!
+//! let report = Downloader::new("https://example.com/master.m3u8").output("lesson.mp4").concurrency(8).run().await?;
+//! println!("{} segments, {} bytes", report.segments, report.bytes);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Settings not covered by the builder are taken from a [`Config`], which
+//! can be loaded from the same file the command line reads.
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use config::Config;
+use exit::{Categorize, Failure};
+use output::{Fsync, OutputTarget};
+use playlist::{Master, Media, Playlist, Segment};
+use futures::{stream::FuturesUnordered, StreamExt};
+use http::{default_fetcher, HttpFetcher, Metered, Response, Throttled};
+use integrity::{Damaged, SegmentCheck};
+use media::Format;
+use manifest::Manifest;
+use progress::Progress;
+use reorder::{Reorder, ReorderThread};
+use resume::WorkDir;
+use retry::Retry;
+use remux::{AudioTrack, Chapter, Container, Metadata, Trim};
+use sha256::Sha256;
+use serve::{Server, Source};
+use space::SpaceGuard;
+use tune::Concurrency;
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    future::Future,
+    io,
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+use variant::{Quality, Variant};
+
+/// The output name that writes the video to stdout.
+const STDOUT: &str = "-";
+
+mod aes;
+mod archive;
+mod audio;
+mod byterange;
+#[doc(hidden)]
+pub mod batch;
+#[doc(hidden)]
+pub mod bench;
+pub mod config;
+#[doc(hidden)]
+pub mod course;
+mod decrypt;
+mod direct;
+pub mod exit;
+mod extractor;
+#[doc(hidden)]
+pub mod fixture;
+mod ftp;
+mod getcourse;
+mod hook;
+pub mod http;
+mod integrity;
+mod interrupt;
+#[doc(hidden)]
+pub mod job;
+mod live;
+mod lms;
+#[doc(hidden)]
+pub mod local;
+#[doc(hidden)]
+pub mod logging;
+mod manifest;
+mod media;
+mod mirror;
+mod obfuscation;
+mod output;
+mod placement;
+mod playlist;
+#[doc(hidden)]
+pub mod plan;
+#[doc(hidden)]
+pub mod probe;
+mod progress;
+#[doc(hidden)]
+pub mod queue;
+mod remux;
+mod reorder;
+mod request_hook;
+mod resume;
+pub mod retry;
+#[doc(hidden)]
+pub mod session;
+mod serve;
+mod sha256;
+mod skip;
+mod space;
+mod subtitles;
+pub mod toml;
+mod tune;
+#[doc(hidden)]
+pub mod update;
+pub mod variant;
+#[doc(hidden)]
+pub mod watch;
+
+/// Runs `job`, stopping it once the `deadline` setting has passed or at
+/// Ctrl+C (or SIGTERM). What was downloaded by then stays in the work
+/// directories for the next run.
+#[doc(hidden)]
+pub async fn within_deadline<T>(config: &Config, job: impl Future<Output = Result<T>>) -> Result<T> {
+    let job = async {
+        tokio::pin!(job);
+        loop {
+            tokio::select! {
+                result = &mut job => return result,
+                signal = interrupt::signalled() => {
+                    if !interrupt::handled() {
+                        let error = anyhow!("Interrupted ({}); run the same command again to resume", signal);
+                        return Err(Failure::Interrupted.of(error));
+                    }
+                }
+            }
+        }
+    };
+    if config.deadline == 0 {
+        return job.await;
+    }
+    let limit = Duration::from_secs(config.deadline);
+    tokio::time::timeout(limit, job).await.unwrap_or_else(|_| {
+        let error = anyhow!(
+            "Stopped at the deadline of {}; run the same command again to resume",
+            progress::clock(limit.as_secs_f64())
+        );
+        Err(Failure::Deadline.of(error))
+    })
+}
+
+/// Downloads `url` to `output` (a local path or a remote target), honouring
+/// `--preview`, and runs the `--exec` hook.
+#[doc(hidden)]
+pub async fn download_to(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    opts: &DownloadOptions,
+    url: &str,
+    output: &str,
+    exec: Option<&str>,
+) -> Result<()> {
+    let mut target = OutputTarget::parse(output)?;
+    if let (Some(dir), OutputTarget::Local(path)) = (&config.output_dir, &target)
+        && path.is_relative()
+        && output != STDOUT
+    {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        target = OutputTarget::Local(dir.join(path));
+    }
+    if let Some(container) = config.remux {
+        if output == STDOUT {
+            return Err(anyhow!("--remux needs an output file; - (stdout) only takes the stream as it is"));
+        }
+        target = remux_target(target, container)?;
+    }
+    if opts.preview.is_some() && output != STDOUT {
+        // A local raw clip next to where the real output would go (or in the
+        // current directory for remote targets); no remux, no upload.
+        let path = match &target {
+            OutputTarget::Local(path) => path.clone(),
+            remote => PathBuf::from(remote.file_name()),
+        };
+        target = OutputTarget::Local(path.with_extension("preview.ts"));
+    }
+    if let OutputTarget::Local(path) = &target
+        && output != STDOUT
+        && opts.preview.is_none()
+        && path.exists()
+    {
+        match opts.existing {
+            Existing::Refuse => {
+                return Err(anyhow!(
+                    "{} already exists; pass --force to overwrite it, or --continue to keep it",
+                    path.display()
+                ));
+            }
+            Existing::Continue => {
+                info!("{} already exists; nothing to do (--continue)", path.display());
+                return Ok(());
+            }
+            Existing::Overwrite => info!("{} already exists; overwriting it (--force)", path.display()),
+        }
+    }
+    let started = Instant::now();
+    let staging = config.tmp_dir.as_deref().unwrap_or(Path::new("."));
+    let client = config.client();
+    let produce = |path: PathBuf| async move { download(fetcher, config, opts, url, &path).await };
+    let result = output::deliver(&target, staging, &client, produce).await;
+    let target = match result.as_ref().ok().and_then(|report| report.output.file_name()) {
+        Some(name) if *name != *target.file_name() => target.sibling(&name.to_string_lossy()),
+        _ => target,
+    };
+    hook::finish(exec, url, target.to_string(), started, result).await
+}
+
+/// Playlist file names that say nothing about the video; the directory
+/// above names it instead.
+const GENERIC_PLAYLISTS: &[&str] = &["master", "index", "playlist", "chunklist", "manifest", "video", "prog_index"];
+
+/// The output name for `url` when none is given: the lesson title when the
+/// video comes from a page, else the playlist's file name, made safe for
+/// any file system and given the extension of `--remux` (or `.ts`).
+#[doc(hidden)]
+pub async fn default_output(fetcher: &Arc<dyn HttpFetcher>, config: &Config, url: &str) -> Result<String> {
+    let extraction = extractor::extract(fetcher.as_ref(), url, config.playlist_retry(), false).await?;
+    let stem = match extraction.title {
+        Some(title) => title,
+        None => playlist_stem(&extraction.playlist),
+    };
+    let name = format!("{}.{}", sanitize_filename(&stem), config.remux.map_or("ts", Container::extension));
+    let path = config.output_dir.as_deref().unwrap_or(Path::new("")).join(&name);
+    info!("Saving as {}", path.display());
+    Ok(name)
+}
+
+/// The name part of a playlist URL: its file name without the extension,
+/// or the directory's name for a generic file name such as `master.m3u8`.
+fn playlist_stem(playlist: &str) -> String {
+    let path = url::Url::parse(playlist).map_or(playlist.to_string(), |url| url.path().to_string());
+    let mut parts = path.rsplit('/').filter(|part| !part.is_empty()).map(|part| {
+        let part = percent_encoding::percent_decode_str(part).decode_utf8_lossy().into_owned();
+        match part.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+            _ => part,
+        }
+    });
+    let first = parts.next().unwrap_or_default();
+    match GENERIC_PLAYLISTS.contains(&first.to_ascii_lowercase().as_str()) {
+        true => parts.next().unwrap_or(first),
+        false => first,
+    }
+}
+
+/// `target` renamed for `--remux`: a `.ts` or extensionless output gets the
+/// container's extension, which then selects the remux as usual.
+fn remux_target(target: OutputTarget, container: Container) -> Result<OutputTarget> {
+    let name = target.file_name();
+    let current = Container::from_path(Path::new(&name));
+    if current == container {
+        return Ok(target);
+    }
+    if current.needs_remux() {
+        return Err(anyhow!("--remux {} contradicts the output name {}", container.extension(), name));
+    }
+    remux::require_ffmpeg("--remux")?;
+    let stem = Path::new(&name).file_stem().map_or(name.clone(), |stem| stem.to_string_lossy().into_owned());
+    let target = target.sibling(&format!("{}.{}", stem, container.extension()));
+    info!("Remuxing to {}: writing {}", container.name(), target);
+    Ok(target)
+}
+
+/// The HTTP client for downloads, going through `proxy`, giving up on
+/// stalled requests after `timeout`, sending `headers` and `cookie` (or else
+/// the saved `login` session of the school), held to `max_total_bytes`,
+/// `limit_rate` and `limit_request_rate`, and wrapped in the request hook if one is set.
+pub fn fetcher(config: &Config) -> Result<Arc<dyn HttpFetcher>> {
+    Ok(fetcher_on(default_fetcher(&config.client())?, config))
+}
+
+/// Like [`fetcher`], but sending the requests through `client`, made by
+/// [`default_fetcher`] with [`Config::client`], whose connections are then
+/// shared with its other users.
+pub fn fetcher_on(client: Arc<dyn HttpFetcher>, config: &Config) -> Arc<dyn HttpFetcher> {
+    let mut headers = config.headers.clone();
+    if let Some(cookie) = &config.cookie {
+        headers.push(("Cookie".to_string(), cookie.clone()));
+    }
+    let mut fetcher = client;
+    if config.cookie.is_none() {
+        fetcher = session::with_saved(fetcher);
+    }
+    // Inside the configured headers, so that the hook sees them and its own
+    // replace them.
+    if let Some(command) = &config.request_hook {
+        fetcher = Arc::new(request_hook::HookedFetcher::new(fetcher, command));
+    }
+    fetcher = extractor::with_headers(&fetcher, headers);
+    if config.max_total_bytes > 0 {
+        fetcher = Arc::new(Metered::new(fetcher, config.max_total_bytes));
+    }
+    if config.limit_rate > 0 {
+        fetcher = Arc::new(Throttled::new(fetcher, config.limit_rate));
+    }
+    if config.limit_request_rate > 0 {
+        fetcher = Arc::new(Throttled::each(fetcher, config.limit_request_rate));
+    }
+    fetcher
+}
+
+/// What a finished download produced.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Segments fetched.
+    pub segments: usize,
+    /// Segment data downloaded, in bytes.
+    pub bytes: u64,
+    /// The file written: the requested output, or the same name with `.mp4`
+    /// when a fragmented MP4 stream was asked for as `.ts`.
+    pub output: PathBuf,
+}
+
+/// Per-run options of a single download that are not configuration settings.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    /// Only fetch segments covering this much from the start.
+    pub preview: Option<Duration>,
+    /// Clip to this time range of the stream (`--start`/`--end`).
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+    /// Marks the progress lines of a download that runs alongside others.
+    pub label: Option<String>,
+    /// Offer the video to players over HTTP on this address as it downloads
+    /// (`--serve`).
+    pub serve: Option<SocketAddr>,
+    /// Record a playlist without `#EXT-X-ENDLIST` as it grows (`--live`).
+    pub live: bool,
+    /// Move the segments here once the output is complete, instead of
+    /// removing them (`--keep-segments`).
+    pub keep_segments: Option<PathBuf>,
+    /// What to do when the output exists already.
+    pub existing: Existing,
+    /// Title tagged in remuxed outputs; by default the lesson page's.
+    pub title: Option<String>,
+    /// Course tagged in remuxed outputs as the album.
+    pub course: Option<String>,
+}
+
+/// What a download does about an output file that exists already.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Existing {
+    /// Fail, leaving the file alone.
+    #[default]
+    Refuse,
+    /// Replace it, and start over instead of resuming (`--force`).
+    Overwrite,
+    /// Take it as the finished download and do nothing (`--continue`). An
+    /// unfinished one resumes either way.
+    Continue,
+}
+
+/// Builder for one download: the library's entry point.
+pub struct Downloader {
+    url: String,
+    output: PathBuf,
+    config: Config,
+    opts: DownloadOptions,
+    fetcher: Option<Arc<dyn HttpFetcher>>,
+}
+
+impl Downloader {
+    /// A download of the playlist at `url` into `video.ts`, with the default
+    /// configuration.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            output: PathBuf::from("video.ts"),
+            config: Config::default(),
+            opts: DownloadOptions::default(),
+            fetcher: None,
+        }
+    }
+
+    /// The output file; its extension picks the container.
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output = path.into();
+        self
+    }
+
+    /// Replaces the whole configuration, for instance with
+    /// [`Config::load`]. Call it before the setters below.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Number of segments fetched at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Attempts per segment before the download fails.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.config.segment_retries = retries;
+        self
+    }
+
+    /// Wait before the first retry of a request; it doubles with every
+    /// further attempt.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.config.retry_backoff = backoff;
+        self
+    }
+
+    /// Gives up on a request once the server has sent nothing for this
+    /// long; zero waits forever.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Which variant of a master playlist to download.
+    pub fn quality(mut self, quality: Quality) -> Self {
+        self.config.quality = quality;
+        self
+    }
+
+    /// Only downloads the segments covering this much from the start.
+    pub fn preview(mut self, length: Duration) -> Self {
+        self.opts.preview = Some(length);
+        self
+    }
+
+    /// Only downloads this time range of the stream.
+    pub fn range(mut self, start: Option<Duration>, end: Option<Duration>) -> Self {
+        self.opts.start = start;
+        self.opts.end = end;
+        self
+    }
+
+    /// Sends `name: value` with every request, replacing a header of the
+    /// same name.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        self.config.headers.retain(|(other, _)| !other.eq_ignore_ascii_case(&name));
+        self.config.headers.push((name, value.into()));
+        self
+    }
+
+    /// Uses `fetcher` for every request instead of the built-in HTTP client.
+    pub fn fetcher(mut self, fetcher: Arc<dyn HttpFetcher>) -> Self {
+        self.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Runs the download to completion.
+    pub async fn run(self) -> Result<Report> {
+        if let (Some(start), Some(end)) = (self.opts.start, self.opts.end)
+            && end <= start
+        {
+            return Err(anyhow!("The end of the range must be after its start"));
+        }
+        let fetcher = match self.fetcher {
+            Some(fetcher) => fetcher,
+            None => fetcher(&self.config)?,
+        };
+        download(&fetcher, &self.config, &self.opts, &self.url, &self.output).await
+    }
+}
+
+/// Downloads the stream behind playlist `url` into `output_file`.
+async fn download(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    opts: &DownloadOptions,
+    url: &str,
+    output_file: &Path,
+) -> Result<Report> {
+    // A preview is for checking the source, so it is never re-encoded.
+    let recode = config.recode.filter(|_| opts.preview.is_none());
+    let all_audio = config.all_audio && opts.preview.is_none();
+    let languages = if opts.preview.is_none() { config.audio_languages.as_slice() } else { &[] };
+    let container = remux::check_output(output_file, config.allow_raw, recode)?;
+    if all_audio && languages.is_empty() && container != Container::Mkv {
+        return Err(anyhow!("--all-audio needs an .mkv output to hold several audio tracks"));
+    }
+    if !languages.is_empty() {
+        remux::require_ffmpeg("--audio-lang")?;
+    }
+    let captions = config.captions && opts.preview.is_none();
+    if captions {
+        remux::require_ffmpeg("--captions")?;
+    }
+    let subs = if opts.preview.is_none() { config.subtitles.as_slice() } else { &[] };
+    // Stdout takes the segments as they arrive, in order; anything that works
+    // on the whole file needs one.
+    let to_stdout = output_file == Path::new(STDOUT);
+    if to_stdout {
+        let needs_file = [
+            (recode.is_some(), "--recode"),
+            (all_audio, "--all-audio"),
+            (!languages.is_empty(), "--audio-lang"),
+            (captions, "--captions"),
+            (!subs.is_empty(), "--subs"),
+            (config.hash_manifest || config.verify_manifest, "A hash manifest"),
+            (opts.serve.is_some(), "--serve"),
+            (opts.keep_segments.is_some(), "--keep-segments"),
+        ];
+        if let Some((_, what)) = needs_file.iter().find(|(needed, _)| *needed) {
+            return Err(anyhow!("{} needs an output file; it cannot be used with - (stdout)", what));
+        }
+    }
+    if let Some(dir) = &opts.keep_segments
+        && fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(anyhow!("--keep-segments {}: the directory is not empty", dir.display()));
+    }
+    let (extraction, fetcher) = extractor::resolve(fetcher, url, config.playlist_retry()).await?;
+    let (playlist, title) = (extraction.playlist, opts.title.clone().or(extraction.title));
+    let fetcher = &byterange::ranged(&fetcher);
+
+    let (master, media) = fetch_playlists(fetcher.as_ref(), &playlist, config.playlist_retry(), config.quality).await?;
+    match (media.ended, opts.live) {
+        (false, true) => return live::record(fetcher, config, opts, media, output_file, container).await,
+        (false, false) => info!(
+            "Note: the playlist has no #EXT-X-ENDLIST and may still be growing; downloading what it lists now \
+             (--live records it as it grows)"
+        ),
+        (true, true) => info!("The playlist has #EXT-X-ENDLIST, so the stream is complete; downloading it as usual"),
+        (true, false) => {}
+    }
+    let segments = media.segments;
+    info!("Found {} video segments", segments.len());
+    let fragmented = segments.first().is_some_and(|s| s.map.is_some());
+    let (segments, trim) = select_segments(segments, config, opts)?;
+    // The init segment and the fragments after it make an MP4 file, which a
+    // .ts name would only hide.
+    let renamed;
+    let output_file = match fragmented && container == Container::Ts && remux::ts_name(output_file) && !to_stdout {
+        true => {
+            renamed = output_file.with_extension("mp4");
+            info!("The stream is fragmented MP4 (#EXT-X-MAP); writing {} instead", renamed.display());
+            renamed.as_path()
+        }
+        false => output_file,
+    };
+    let duration: f64 = segments.iter().map(|s| s.duration).sum();
+    if config.max_duration > 0 && duration > config.max_duration as f64 {
+        return Err(anyhow!(
+            "The stream runs {}, longer than max_duration ({}); refusing to download it",
+            progress::clock(duration),
+            progress::clock(config.max_duration as f64)
+        ));
+    }
+    let subtitles = match subs.is_empty() {
+        true => Vec::new(),
+        false => subtitles::select(master.as_ref().map_or(&[][..], |(master, _)| &master.subtitles), subs),
+    };
+    let mut renditions = Vec::new();
+    if all_audio || !languages.is_empty() {
+        renditions = master.map(|(master, _)| master.renditions).unwrap_or_default();
+        if renditions.is_empty() {
+            info!("The playlist has no alternate audio tracks; keeping the audio of the video stream");
+        } else if !languages.is_empty() {
+            renditions = audio::select(&renditions, languages)?;
+        }
+    }
+    if renditions.len() > 1 && container != Container::Mkv {
+        return Err(anyhow!("{} audio tracks were selected; that needs an .mkv output", renditions.len()));
+    }
+    let keys = segments.iter().map(|s| (s.url.as_str(), s.key.as_ref()));
+    // Other tracks have segments and keys of their own.
+    let tracks_fetcher = fetcher;
+    let fetcher = &mirror::with_mirrors(fetcher, &config.mirrors)?;
+    let fetcher = &decrypt::decrypting(fetcher, keys, config.playlist_retry()).await?;
+    let fetcher = &obfuscation::unmasking(fetcher, segments.iter().map(|s| s.url.as_str()));
+    let mut concurrency = Concurrency::of(config);
+    let fetcher = &concurrency.observe(fetcher);
+    let segment_urls: Vec<String> = segments.iter().map(|s| s.url.clone()).collect();
+    let remuxed = container.needs_remux() || recode.is_some() || !renditions.is_empty();
+    // Created now to find out early whether the output can be written at all.
+    let partial = match to_stdout {
+        true => None,
+        false => Some(Partial::create(output_file)?),
+    };
+    let write_to = partial.as_ref().map_or(output_file, |partial| partial.path.as_path());
+
+    // Streamed segments are rebased on the way out; in place there is no way out.
+    let keep = opts.keep_segments.is_some();
+    let in_place = config.in_place && !to_stdout && !keep && join_needed(&segments, remuxed, false).is_none();
+    let streaming = (config.stream_output || to_stdout) && !keep && join_needed(&segments, remuxed, true).is_none();
+    if !in_place
+        && !streaming
+        && let Some(reason) = join_needed(&segments, remuxed, config.stream_output)
+            .or(keep.then_some("--keep-segments keeps them"))
+            .filter(|_| config.in_place || config.stream_output)
+    {
+        info!("Joining the segments afterwards: {}", reason);
+    }
+    let offsets = match in_place {
+        true => in_place_offsets(fetcher.as_ref(), &segments, config).await,
+        false => None,
+    };
+    let stream = streaming && offsets.is_none();
+    let mut work_dir = match &offsets {
+        Some(offsets) => {
+            let (path, len) = (write_to.to_path_buf(), offsets[offsets.len() - 1]);
+            blocking(move || preallocate(&path, len)).await?;
+            None
+        }
+        None if stream => None,
+        None => {
+            let fresh = opts.existing == Existing::Overwrite;
+            Some(WorkDir::open(output_file, config.tmp_dir.as_deref(), &playlist, &segment_urls, fresh)?)
+        }
+    };
+    let work_path = work_dir.as_ref().map(|work| work.path().to_path_buf());
+    let resumed: BTreeSet<usize> = work_dir.iter().flat_map(WorkDir::done).collect();
+    let dest = match (&offsets, &work_path) {
+        (Some(offsets), _) => Destination::Output { path: write_to, offsets },
+        (None, Some(dir)) => Destination::Dir(dir),
+        (None, None) => Destination::Stream,
+    };
+    let space = (config.min_free_space > 0).then(|| {
+        let guard = SpaceGuard::new(config.min_free_space);
+        let guard = match &work_path {
+            Some(dir) => guard.watch(dir, "work directory"),
+            None => guard,
+        };
+        match to_stdout {
+            true => guard,
+            false => guard.watch(output_file.parent().unwrap_or(Path::new(".")), "output directory"),
+        }
+    });
+    // In place, the output is preallocated already; stdout takes no room.
+    if config.space_check && offsets.is_none() && !to_stdout {
+        // The encrypted sizes are close enough, and only they are known in advance.
+        match space::estimate(tracks_fetcher.as_ref(), &segments, config.concurrency).await {
+            Some(sizes) => {
+                let remaining = sizes.iter().enumerate().filter(|(i, _)| !resumed.contains(i)).map(|(_, size)| size);
+                let output_dir = output_file.parent().unwrap_or(Path::new("."));
+                let output = (output_dir, "output", sizes.iter().sum());
+                let needs = match &work_path {
+                    Some(dir) => vec![(dir.as_path(), "segments", remaining.sum()), output],
+                    None => vec![output],
+                };
+                space::check(&needs, config.min_free_space)?;
+            }
+            None => info!("Not checking free space up front: the server does not report segment sizes"),
+        }
+    }
+
+    let server = match opts.serve {
+        Some(addr) => {
+            let source = match dest {
+                Destination::Dir(dir) => Source::Dir(dir.to_path_buf()),
+                Destination::Output { path, offsets } => {
+                    Source::Output { path: path.to_path_buf(), offsets: offsets.to_vec() }
+                }
+                Destination::Stream => Source::Growing(write_to.to_path_buf()),
+            };
+            Some(Server::start(addr, &segments, fragmented, source).await?)
+        }
+        None => None,
+    };
+
+    let total_segments = segment_urls.len();
+    let mut progress = Progress::new(segments.iter().map(|s| s.duration).collect()).with_template(
+        config.progress_template.clone(),
+        Duration::from_secs(config.progress_interval as u64),
+    )
+    .with_format(config.progress)
+    .with_label(opts.label.clone());
+    progress.start();
+    let mut attempts = 1;
+    // Fetch and probe the first segment before the rest, so that a stream the
+    // requested container cannot hold is reported before the long download.
+    let first = match dest {
+        Destination::Stream => {
+            let (data, tries) = fetch_segment(fetcher.as_ref(), &segment_urls[0], config.segment_retry()).await?;
+            attempts = tries;
+            data
+        }
+        dest => {
+            if !resumed.contains(&0) {
+                let (path, range) = dest.slot(0);
+                (_, _, attempts) = download_segment(
+                    fetcher.as_ref(),
+                    &segment_urls[0],
+                    &path,
+                    range,
+                    config.segment_retry(),
+                    SegmentWrite::Streamed,
+                    config.fsync == Fsync::PerSegment,
+                    false,
+                )
+                .await?;
+            }
+            Bytes::from(dest.read_back(0).await?)
+        }
+    };
+    // Segments are hashed as received; the first one is still in memory, and
+    // those of an earlier run are hashed as stored.
+    let mut manifest = (config.hash_manifest || config.verify_manifest).then(Manifest::new);
+    if let Some(manifest) = &mut manifest {
+        manifest.record(0, sha256::digest_hex(&first));
+        for &index in resumed.iter().filter(|&&index| index > 0) {
+            manifest.record(index, sha256::digest_hex(&dest.read_back(index).await?));
+        }
+    }
+    for &index in &resumed {
+        let size = fs::metadata(dest.slot(index).0).map_or(0, |m| m.len());
+        progress.resumed(index, size);
+    }
+    if let Some(server) = &server {
+        resumed.iter().chain([&0]).for_each(|&index| server.ready(index));
+    }
+    let first_bytes = match resumed.contains(&0) {
+        true => 0,
+        false => {
+            progress.done(0, first.len() as u64, attempts);
+            first.len() as u64
+        }
+    };
+    if let Some(work) = &mut work_dir {
+        work.record(0);
+    }
+    progress.report();
+    let info = media::probe(&first);
+    let origin = media::first_pts(&first).map(|pts| pts as f64 / 90_000.0);
+    match &info {
+        Some(info) => info!("Stream: {}", info),
+        None => info!("Stream: unrecognized format (encrypted?)"),
+    }
+    // The video's own audio is replaced when renditions were picked.
+    remux::check_streams(container, recode, info.as_ref().filter(|_| renditions.is_empty()))?;
+
+    let on_done = |index, bytes, attempts| {
+        progress.done(1 + index, bytes, attempts);
+        progress.report();
+        if let Some(server) = &server {
+            server.ready(1 + index);
+        }
+        if let Some(work) = &mut work_dir {
+            work.record(1 + index);
+        }
+    };
+    let mut bytes = first_bytes
+        + match dest {
+            Destination::Stream => {
+                let file = match (to_stdout, config.direct_io) {
+                    (true, _) => Sink::Stdout(io::BufWriter::with_capacity(1 << 20, io::stdout())),
+                    (false, true) => Sink::File(direct::DirectFile::create(write_to)?),
+                    (false, false) => Sink::File(direct::DirectFile::plain(write_to)?),
+                };
+                let mut out = Reorder::new(file, config.reorder_memory, scratch_dir(config, write_to));
+                if Rebase::needed(&segments, &first) {
+                    info!("Rebasing timestamps across discontinuities");
+                    out = out.rebasing(Rebase::new(&segments));
+                }
+                let mut out = out.spawn();
+                out.push(0, first)?;
+                let bytes = stream_segments(
+                    fetcher,
+                    &segment_urls[1..],
+                    1,
+                    &mut out,
+                    &mut concurrency,
+                    config.segment_retry(),
+                    space.as_ref(),
+                    manifest.as_mut(),
+                    on_done,
+                )
+                .await?;
+                let sink = out.finish().await?;
+                blocking(move || sink.finish().context("Failed to write the output")).await?;
+                bytes
+            }
+            dest => {
+                download_segments(
+                    fetcher,
+                    &segment_urls[1..],
+                    1,
+                    &resumed,
+                    dest,
+                    &mut concurrency,
+                    config.segment_retry(),
+                    SegmentWrite::Streamed,
+                    config.fsync == Fsync::PerSegment,
+                    space.as_ref(),
+                    manifest.as_mut(),
+                    on_done,
+                )
+                .await?
+            }
+        };
+    if config.verify_manifest
+        && let Some(manifest) = &mut manifest
+    {
+        bytes += verify_segments(fetcher.as_ref(), &segment_urls, dest, manifest, config).await?;
+    }
+
+    let mut segment_count = total_segments;
+    match dest {
+        // Every segment already sits at its place in the output.
+        Destination::Output { .. } | Destination::Stream => {
+            if captions {
+                save_captions(write_to, output_file, None);
+            }
+        }
+        Destination::Dir(work) => {
+            let (tracks, track_segments, track_bytes) =
+                download_tracks(tracks_fetcher, &renditions, config, opts, work, &mut concurrency, space.as_ref())
+                    .await?;
+            segment_count += track_segments;
+            bytes += track_bytes;
+
+            // Concatenate segments, remuxing if the output extension asks for another container.
+            // Joining writes everything once more.
+            if let Some(space) = &space {
+                space.wait(bytes).await;
+            }
+            // Joining and remuxing are long stretches of blocking disk I/O.
+            let (work, segments, info) = (work.to_path_buf(), segments.clone(), info.clone());
+            let (output_file, write_to) = (output_file.to_path_buf(), write_to.to_path_buf());
+            let metadata =
+                Metadata { title: title.clone(), album: opts.course.clone(), chapters: chapters(&segments, trim) };
+            let direct_io = config.direct_io;
+            blocking(move || {
+                let (work, output_file, write_to) = (work.as_path(), output_file.as_path(), write_to.as_path());
+                if remuxed {
+                    let joined = work.join("joined.tmp");
+                    join_segments(work, &segments, &joined, false)?;
+                    if captions {
+                        save_captions(&joined, output_file, trim);
+                    }
+                    remux::remux(&joined, write_to, container, info.as_ref(), trim, recode, &tracks, &metadata)
+                } else {
+                    if trim.is_some_and(|t| t.start > 0.0 || t.duration.is_some()) {
+                        info!("Note: .ts output is cut at segment boundaries; use .mp4 for an exact cut");
+                    }
+                    join_segments(work, &segments, write_to, direct_io)?;
+                    if captions {
+                        save_captions(write_to, output_file, None);
+                    }
+                    Ok(())
+                }
+            })
+            .await?;
+        }
+    }
+
+    if let Some(partial) = partial {
+        partial.commit(output_file)?;
+    }
+    if !subtitles.is_empty() {
+        // Only remuxed outputs are cut exactly.
+        let trim = trim.filter(|_| remuxed);
+        subtitles::save(tracks_fetcher, config, &subtitles, output_file, origin, trim).await;
+    }
+    if let Some(manifest) = manifest {
+        let (path, output) = (output_file.with_extension("sha256"), output_file.to_path_buf());
+        let written = path.clone();
+        blocking(move || manifest.write(&written, &output)).await?;
+        info!("Segment hashes written to {}", path.display());
+    }
+    if config.fsync != Fsync::Off {
+        let output = output_file.to_path_buf();
+        blocking(move || output::sync_file(&output)).await?;
+    }
+    progress.finish(output_file);
+    match to_stdout {
+        true => info!("Download completed successfully (written to stdout)"),
+        false => info!("Download completed successfully. Output file:\n{}", output_file.display()),
+    }
+    // The segments are served from the work directory until the server stops.
+    if let Some(server) = server {
+        server.finish(output_file);
+        server.linger().await;
+    }
+    match (work_dir, &opts.keep_segments) {
+        (Some(work), Some(dir)) => match work.keep(dir) {
+            Ok(()) => info!("Segments kept in {}", dir.display()),
+            Err(e) => warn!("Warning: {:#}", e),
+        },
+        (Some(work), None) => work.finish(),
+        (None, _) => {}
+    }
+    Ok(Report { segments: segment_count, bytes, output: output_file.to_path_buf() })
+}
+
+/// Downloads the selected alternate audio `renditions` into `work`, one
+/// joined file per track. Returns the tracks and how many segments and bytes
+/// they took.
+async fn download_tracks(
+    fetcher: &Arc<dyn HttpFetcher>,
+    renditions: &[audio::Rendition],
+    config: &Config,
+    opts: &DownloadOptions,
+    work: &Path,
+    concurrency: &mut Concurrency,
+    space: Option<&SpaceGuard>,
+) -> Result<(Vec<AudioTrack>, usize, u64)> {
+    let (mut tracks, mut segment_count, mut bytes) = (Vec::new(), 0, 0);
+    for (k, rendition) in renditions.iter().enumerate() {
+        let (media, base) = download_playlist(fetcher.as_ref(), &rendition.uri, config.playlist_retry())
+            .await
+            .with_context(|| format!("Failed to download the playlist of audio track {}", rendition.label()))?;
+        let (segments, trim) = select_segments(media_playlist(&media, &base)?.segments, config, opts)?;
+        let keys = segments.iter().map(|s| (s.url.as_str(), s.key.as_ref()));
+        let fetcher = &mirror::with_mirrors(fetcher, &config.mirrors)?;
+        let fetcher = &decrypt::decrypting(fetcher, keys, config.playlist_retry()).await?;
+        let fetcher = &obfuscation::unmasking(fetcher, segments.iter().map(|s| s.url.as_str()));
+        let fetcher = &concurrency.observe(fetcher);
+        let urls: Vec<String> = segments.iter().map(|s| s.url.clone()).collect();
+        info!("Downloading audio track {} ({} segments)", rendition.label(), urls.len());
+        let dir = work.join(format!("audio{}", k));
+        fs::create_dir_all(&dir)?;
+        bytes += download_segments(
+            fetcher,
+            &urls,
+            0,
+            &BTreeSet::new(),
+            Destination::Dir(&dir),
+            concurrency,
+            config.segment_retry(),
+            SegmentWrite::Streamed,
+            config.fsync == Fsync::PerSegment,
+            space,
+            None,
+            |_, _, _| {},
+        )
+        .await?;
+        segment_count += urls.len();
+        let path = work.join(format!("audio{}.tmp", k));
+        let (joined, segments) = (path.clone(), segments.clone());
+        blocking(move || join_segments(&dir, &segments, &joined, false)).await?;
+        tracks.push(AudioTrack {
+            path,
+            trim,
+            title: rendition.name.clone(),
+            language: rendition.language.clone(),
+            default: rendition.default,
+        });
+    }
+    Ok((tracks, segment_count, bytes))
+}
+
+/// With `--verify-manifest`: hashes the stored segments again and downloads
+/// those that no longer match what was received. Returns the bytes downloaded
+/// again.
+async fn verify_segments(
+    fetcher: &dyn HttpFetcher,
+    urls: &[String],
+    dest: Destination<'_>,
+    manifest: &mut Manifest,
+    config: &Config,
+) -> Result<u64> {
+    if let Destination::Stream = dest {
+        // Nothing is stored; the segments went straight from memory to the output.
+        return Ok(0);
+    }
+    info!("Verifying {} stored segments against their hashes", urls.len());
+    let slots: Vec<_> = (0..urls.len()).map(|index| dest.slot(index)).collect();
+    let hashed = std::mem::take(manifest);
+    let (hashed, damaged) = blocking(move || {
+        let damaged = hashed.verify(|index| read_slot(&slots[index].0, slots[index].1.clone()));
+        Ok((hashed, damaged))
+    })
+    .await?;
+    *manifest = hashed;
+    let damaged = damaged?;
+    let mut bytes = 0;
+    for index in damaged {
+        warn!("WARNING: segment {} changed on disk since it was downloaded; downloading it again", index + 1);
+        let (path, range) = dest.slot(index);
+        let (size, digest, _) = download_segment(
+            fetcher,
+            &urls[index],
+            &path,
+            range,
+            config.segment_retry(),
+            SegmentWrite::Streamed,
+            config.fsync == Fsync::PerSegment,
+            true,
+        )
+        .await?;
+        manifest.record(index, digest.expect("hashed"));
+        bytes += size;
+    }
+    Ok(bytes)
+}
+
+/// Why the segments cannot go straight into the output (`--in-place`, or
+/// `--stream` when `streaming`, which rebases as it goes) but must be joined
+/// afterwards, if they must.
+fn join_needed(segments: &[Segment], remuxed: bool, streaming: bool) -> Option<&'static str> {
+    if remuxed {
+        Some("the output is remuxed")
+    } else if !streaming && segments.iter().skip(1).any(|s| s.discontinuity) {
+        Some("timestamps may need rebasing across discontinuities")
+    } else {
+        None
+    }
+}
+
+/// With `--in-place`: the byte offset of every segment in the output (plus
+/// the total size at the end), from HEAD requests. `None`, with the reason
+/// printed, when the server does not report every size.
+async fn in_place_offsets(fetcher: &dyn HttpFetcher, segments: &[Segment], config: &Config) -> Option<Vec<u64>> {
+    if segments.iter().any(|s| s.key.is_some()) {
+        info!("Not writing in place: the size of decrypted segments is not known in advance");
+        return None;
+    }
+    info!("Probing the size of {} segments", segments.len());
+    let sizes: Vec<Option<u64>> = futures::stream::iter(segments)
+        .map(|segment| async move {
+            let resp = fetcher.head(&segment.url, &[]).await.ok()?;
+            resp.length.filter(|_| resp.is_success())
+        })
+        .buffered(config.concurrency)
+        .collect()
+        .await;
+    let mut offsets = vec![0];
+    for (i, size) in sizes.into_iter().enumerate() {
+        let Some(size) = size else {
+            info!("Not writing in place: the server did not report the size of segment {}", i + 1);
+            return None;
+        };
+        offsets.push(offsets[i] + size);
+    }
+    info!("Writing segments straight into the output ({})", progress::size(offsets[segments.len()]));
+    Some(offsets)
+}
+
+/// Runs `work`, blocking file I/O, on the blocking thread pool rather than on
+/// an async worker thread. Unlike `block_in_place`, this works on a
+/// current-thread runtime too.
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(anyhow!("A disk task was cancelled: {}", e)),
+    }
+}
+
+/// Creates `path` with `len` bytes reserved on disk, so that running out of
+/// space shows up before the download rather than halfway through it.
+fn preallocate(path: &Path, len: u64) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: plain system call on a descriptor owned by `file`.
+        let err = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) };
+        match err {
+            0 => return Ok(()),
+            // Filesystems without fallocate get a sparse file instead.
+            libc::EOPNOTSUPP => {}
+            err => {
+                return Err(io::Error::from_raw_os_error(err))
+                    .with_context(|| format!("Failed to reserve {} for {}", progress::size(len), path.display()));
+            }
+        }
+    }
+    file.set_len(len).with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// Extracts closed captions from `video` to an `.srt` next to `output_file`.
+/// Failures only warn: the video itself is already complete.
+fn save_captions(video: &Path, output_file: &Path, trim: Option<Trim>) {
+    let srt = output_file.with_extension("srt");
+    match remux::extract_captions(video, &srt, trim) {
+        Ok(true) => info!("Closed captions saved to {}", srt.display()),
+        Ok(false) => info!("The video stream carries no closed captions"),
+        Err(e) => warn!("WARNING: extracting closed captions failed: {:#}", e),
+    }
+}
+
+/// Applies the skip options, `--preview` and `--start`/`--end` to the
+/// segments of one media playlist.
+fn select_segments(
+    segments: Vec<Segment>,
+    config: &Config,
+    opts: &DownloadOptions,
+) -> Result<(Vec<Segment>, Option<Trim>)> {
+    let mut segments = skip::apply(segments, &config.skip_daterange_classes, config.skip_bumpers)?;
+    if let Some(preview) = opts.preview {
+        let mut covered = 0.0;
+        segments.retain(|s| {
+            let keep = covered < preview.as_secs_f64();
+            covered += s.duration;
+            keep
+        });
+        let seconds: f64 = segments.iter().map(|s| s.duration).sum();
+        info!("Preview: first {} segment(s), {:.0}s", segments.len(), seconds);
+    }
+    let trim = match (opts.start, opts.end) {
+        (None, None) => None,
+        (start, end) => Some(select_range(&mut segments, start, end)?),
+    };
+    if segments.is_empty() {
+        return Err(Failure::Usage.of(anyhow!("No segments are left to download after --preview, --start and --end")));
+    }
+    Ok((with_init_segment(segments)?, trim))
+}
+
+/// `segments` of a fragmented MP4 stream with their `#EXT-X-MAP` init
+/// segment in front, as a segment of its own, so that it is fetched, stored
+/// and joined like the rest and ends up at the head of the output.
+fn with_init_segment(mut segments: Vec<Segment>) -> Result<Vec<Segment>> {
+    let Some(map) = segments.first().and_then(|s| s.map.clone()) else {
+        return Ok(segments);
+    };
+    if let Some(i) = segments.iter().position(|s| s.map.as_ref() != Some(&map)) {
+        return Err(anyhow!("The init segment changes at segment {} (#EXT-X-MAP); that is not supported yet", i + 1));
+    }
+    let init = Segment {
+        url: map.url,
+        duration: 0.0,
+        title: None,
+        discontinuity: false,
+        program_date: None,
+        dateranges: Vec::new(),
+        key: map.key,
+        byte_range: map.byte_range,
+        map: None,
+    };
+    segments.insert(0, init);
+    Ok(segments)
+}
+
+/// Keeps the segments overlapping `start..end` and returns where, within
+/// them, the requested range lies.
+fn select_range(segments: &mut Vec<Segment>, start: Option<Duration>, end: Option<Duration>) -> Result<Trim> {
+    let start = start.map_or(0.0, |s| s.as_secs_f64());
+    let end = end.map(|e| e.as_secs_f64());
+    let mut position = 0.0;
+    let mut first = None;
+    let mut kept = Vec::new();
+    for segment in segments.drain(..) {
+        let (from, to) = (position, position + segment.duration);
+        position = to;
+        if to > start && end.is_none_or(|end| from < end) {
+            first.get_or_insert(from);
+            kept.push(segment);
+        }
+    }
+    let first = first.ok_or_else(|| anyhow!("--start is past the end of the stream ({:.0}s)", position))?;
+    info!("Range: {} segment(s) from {:.1}s", kept.len(), first);
+    *segments = kept;
+    Ok(Trim { start: start - first, duration: end.map(|end| end - start) })
+}
+
+/// Chapters where the stream is spliced (`#EXT-X-DISCONTINUITY`) and where
+/// the `#EXTINF` title changes, named by that title or numbered; none when
+/// that makes only one. Times are those of the output, cut to `trim`.
+fn chapters(segments: &[Segment], trim: Option<Trim>) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+    let mut position = 0.0;
+    let mut previous: Option<&Segment> = None;
+    for segment in segments.iter().filter(|s| s.duration > 0.0) {
+        let new = previous.is_none_or(|p| segment.discontinuity || segment.title != p.title);
+        if new {
+            let title = segment.title.clone().unwrap_or_else(|| format!("Part {}", chapters.len() + 1));
+            chapters.push(Chapter { start: position, end: position, title });
+        }
+        position += segment.duration;
+        if let Some(chapter) = chapters.last_mut() {
+            chapter.end = position;
+        }
+        previous = Some(segment);
+    }
+    let (start, duration) = trim.map_or((0.0, None), |trim| (trim.start, trim.duration));
+    let end = duration.map_or(f64::INFINITY, |duration| duration);
+    chapters.retain_mut(|chapter| {
+        chapter.start = (chapter.start - start).max(0.0);
+        chapter.end = (chapter.end - start).min(end);
+        chapter.end > chapter.start
+    });
+    if chapters.len() < 2 {
+        chapters.clear();
+    }
+    chapters
+}
+
+/// Downloads the playlist chain starting at `url` and returns the segments of
+/// the media playlist.
+async fn resolve_segments(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    retry: Retry,
+    quality: Quality,
+) -> Result<Vec<Segment>> {
+    let (_, media) = fetch_playlists(fetcher, url, retry, quality).await?;
+    with_init_segment(media.segments)
+}
+
+/// Downloads the playlist at `url` and, if it is a master playlist, the media
+/// playlist of the variant `quality` picks. Returns both, the master with the
+/// variant picked; it is `None` when `url` is a media playlist.
+async fn fetch_playlists(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    retry: Retry,
+    quality: Quality,
+) -> Result<(Option<(Master, Variant)>, Media)> {
+    let (main_playlist, base) =
+        download_playlist(fetcher, url, retry).await.context("Failed to download main playlist")?;
+    let master = match playlist::parse(&main_playlist, &base).category(Failure::Playlist)? {
+        // Some GetCourse main playlists just name the media playlist, without
+        // #EXT-X-STREAM-INF.
+        Playlist::Media(media) => match media.segments.last().filter(|s| s.url.contains(".m3u8")) {
+            Some(last) => {
+                let (text, base) = download_playlist(fetcher, &last.url, retry)
+                    .await
+                    .context("Failed to download secondary playlist")?;
+                return Ok((None, media_playlist(&text, &base)?));
+            }
+            None => return Ok((None, checked(media)?)),
+        },
+        Playlist::Master(master) => master,
+    };
+    let picked = variant::select(&master.variants, quality)?.clone();
+    let (text, base) =
+        download_playlist(fetcher, &picked.uri, retry).await.context("Failed to download secondary playlist")?;
+    let media = media_playlist(&text, &base)?;
+    Ok((Some((master, picked)), media))
+}
+
+/// Parses `text`, downloaded from `url`, which must be a media playlist.
+fn media_playlist(text: &str, url: &str) -> Result<Media> {
+    match playlist::parse(text, url).category(Failure::Playlist)? {
+        Playlist::Media(media) => checked(media),
+        Playlist::Master(_) => {
+            Err(Failure::Playlist.of(anyhow!("{} is a master playlist where a media playlist was expected", url)))
+        }
+    }
+}
+
+/// `media`, if the downloader can handle it.
+fn checked(media: Media) -> Result<Media> {
+    if media.segments.is_empty() {
+        return Err(Failure::Playlist.of(anyhow!("No video segments found in playlist")));
+    }
+    Ok(media)
+}
+
+/// How a segment body gets from the network to its file. Downloads stream,
+/// so memory stays flat however large the segments; `bench` compares both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SegmentWrite {
+    /// Read the whole body into memory, then write it in one go.
+    Buffered,
+    /// Write chunks to the file as they arrive.
+    Streamed,
+}
+
+/// Where streamed segments are written: the output file, or stdout for `-`.
+enum Sink {
+    File(direct::DirectFile),
+    Stdout(io::BufWriter<io::Stdout>),
+}
+
+impl Sink {
+    /// Writes what is still buffered.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.finish().map(drop),
+            Sink::Stdout(mut stdout) => io::Write::flush(&mut stdout),
+        }
+    }
+}
+
+impl io::Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::File(file) => file.write(data),
+            Sink::Stdout(stdout) => stdout.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.flush(),
+            Sink::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+/// Where downloaded segments are written.
+#[derive(Debug, Clone, Copy)]
+enum Destination<'a> {
+    /// Numbered files in a directory, joined afterwards (see [`segment_file`]).
+    Dir(&'a Path),
+    /// Straight into the preallocated output, segment `i` at bytes
+    /// `offsets[i]..offsets[i + 1]` (`--in-place`).
+    Output { path: &'a Path, offsets: &'a [u64] },
+    /// Appended to the output in order by a [`Reorder`] writer (`--stream`);
+    /// see [`stream_segments`].
+    Stream,
+}
+
+impl Destination<'_> {
+    /// The file segment `index` goes to, and its byte range there if the file
+    /// is shared.
+    fn slot(self, index: usize) -> (PathBuf, Option<Range<u64>>) {
+        match self {
+            Destination::Dir(dir) => (segment_file(dir, index), None),
+            Destination::Output { path, offsets } => (path.to_path_buf(), Some(offsets[index]..offsets[index + 1])),
+            Destination::Stream => unreachable!("streamed segments are not written to files"),
+        }
+    }
+
+    /// Reads back segment `index`.
+    fn read(self, index: usize) -> Result<Vec<u8>> {
+        let (path, range) = self.slot(index);
+        read_slot(&path, range)
+    }
+
+    /// [`Destination::read`], off the async worker threads.
+    async fn read_back(self, index: usize) -> Result<Vec<u8>> {
+        let (path, range) = self.slot(index);
+        blocking(move || read_slot(&path, range)).await
+    }
+}
+
+/// Reads `range` of `path`, or all of it.
+fn read_slot(path: &Path, range: Option<Range<u64>>) -> Result<Vec<u8>> {
+    let Some(range) = range else {
+        return Ok(fs::read(path)?);
+    };
+    let mut data = vec![0; (range.end - range.start) as usize];
+    let mut file = File::open(path)?;
+    io::Seek::seek(&mut file, io::SeekFrom::Start(range.start))?;
+    io::Read::read_exact(&mut file, &mut data)?;
+    Ok(data)
+}
+
+/// Downloads `urls` to `dest` as segments numbered from `first`, leaving out
+/// the numbers in `skip`. Up to `concurrency` requests are in flight; a new
+/// one starts as soon as any finishes, and each finished one is counted in
+/// `concurrency`. `on_done` receives the index (within
+/// `urls`), size and number of attempts of each segment as it completes. With `sync`, each segment is flushed to disk
+/// before it counts as done; with `space`, no new request is started while disk space is short;
+/// with `manifest`, the digest of each segment is recorded there.
+/// Returns the total number of bytes written.
+#[allow(clippy::too_many_arguments)]
+async fn download_segments(
+    fetcher: &Arc<dyn HttpFetcher>,
+    urls: &[String],
+    first: usize,
+    skip: &BTreeSet<usize>,
+    dest: Destination<'_>,
+    concurrency: &mut Concurrency,
+    retry: Retry,
+    write: SegmentWrite,
+    sync: bool,
+    space: Option<&SpaceGuard>,
+    mut manifest: Option<&mut Manifest>,
+    mut on_done: impl FnMut(usize, u64, usize),
+) -> Result<u64> {
+    let hash = manifest.is_some();
+    let mut pending = urls.iter().enumerate().filter(|(i, _)| !skip.contains(&(first + i)));
+    let mut results = FuturesUnordered::new();
+
+    let mut total_bytes = 0;
+    loop {
+        while results.len() < concurrency.limit()
+            && let Some((i, url)) = pending.next()
+        {
+            let (segment_path, range) = dest.slot(first + i);
+            results.push(async move {
+                if let Some(space) = space {
+                    space.wait(0).await;
+                }
+                (i, download_segment(fetcher.as_ref(), url, &segment_path, range, retry, write, sync, hash).await)
+            });
+        }
+        let Some((index, result)) = results.next().await else {
+            break;
+        };
+        let (bytes, digest, attempts) = result.inspect_err(|e| warn!("Failed to download segment: {}", e))?;
+        concurrency.done(bytes);
+        total_bytes += bytes;
+        if let (Some(manifest), Some(digest)) = (manifest.as_deref_mut(), digest) {
+            manifest.record(first + index, digest);
+        }
+        on_done(index, bytes, attempts);
+    }
+    Ok(total_bytes)
+}
+
+/// How many places past the next segment to be written a download may start
+/// in `--stream` mode, per concurrent request. Bounds the segments waiting in
+/// the reorder buffer when one is slow.
+const STREAM_WINDOW: usize = 4;
+
+/// Downloads `urls`, numbered from `first`, and appends them to `out` in
+/// order. At most `concurrency` requests are in flight, and none is started
+/// for a segment too far ahead of the next one to be written, so one stalled
+/// segment cannot make the reorder buffer grow without bound. Returns the
+/// total number of bytes received.
+#[allow(clippy::too_many_arguments)]
+async fn stream_segments<W>(
+    fetcher: &Arc<dyn HttpFetcher>,
+    urls: &[String],
+    first: usize,
+    out: &mut ReorderThread<W>,
+    concurrency: &mut Concurrency,
+    retry: Retry,
+    space: Option<&SpaceGuard>,
+    mut manifest: Option<&mut Manifest>,
+    mut on_done: impl FnMut(usize, u64, usize),
+) -> Result<u64> {
+    let mut futures = FuturesUnordered::new();
+    let mut urls = urls.iter().enumerate().peekable();
+    let mut total_bytes = 0;
+    loop {
+        while futures.len() < concurrency.limit()
+            && let Some(&(i, _)) = urls.peek()
+            && first + i < out.next() + STREAM_WINDOW * concurrency.limit()
+        {
+            if let Some(space) = space {
+                space.wait(0).await;
+            }
+            let (i, url) = urls.next().expect("peeked");
+            let fetcher = Arc::clone(fetcher);
+            futures.push(async move { (i, fetch_segment(fetcher.as_ref(), url, retry).await) });
+        }
+        let Some((index, result)) = futures.next().await else {
+            if urls.peek().is_none() {
+                break;
+            }
+            // Everything in flight is done but not yet written; the window
+            // opens up as the writer catches up.
+            out.written().await?;
+            continue;
+        };
+        let (data, attempts) = result.inspect_err(|e| warn!("Failed to download segment: {}", e))?;
+        concurrency.done(data.len() as u64);
+        total_bytes += data.len() as u64;
+        if let Some(manifest) = manifest.as_deref_mut() {
+            manifest.record(first + index, sha256::digest_hex(&data));
+        }
+        on_done(index, data.len() as u64, attempts);
+        out.push(first + index, data)?;
+    }
+    Ok(total_bytes)
+}
+
+/// Downloads one segment into memory, returning it and the number of
+/// attempts it took. When the body breaks off, the next attempt asks only for
+/// the rest.
+async fn fetch_segment(fetcher: &dyn HttpFetcher, url: &str, retry: Retry) -> Result<(Bytes, usize)> {
+    let mut last_error = None;
+    let mut have = Vec::new();
+
+    for attempt in 0..=retry.retries {
+        match request_rest(fetcher, url, have.len() as u64).await {
+            Ok((resp, continues)) if resp.is_success() => {
+                if !continues {
+                    have.clear();
+                }
+                match receive(resp, &mut have).await {
+                    Ok(()) => return Ok((Bytes::from(have), attempt + 1)),
+                    Err(e) => {
+                        if !e.is::<Interrupted>() {
+                            have.clear();
+                        }
+                        last_error = Some(retry_reason(e, url, attempt < retry.retries)?);
+                    }
+                }
+            }
+            Ok((resp, _)) => {
+                have.clear();
+                last_error = Some(Failure::Network.of(anyhow!("HTTP status: {}", resp.status)));
+            }
+            Err(e) => last_error = Some(e),
+        }
+        if let Some(e) = &last_error {
+            debug!("Attempt {} of segment {} failed: {:#}", attempt + 1, url, e);
+        }
+
+        if attempt < retry.retries {
+            retry.wait(attempt).await;
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("Failed after {} retries", retry.retries)))
+}
+
+/// Appends the body of `resp` to `have`, the part of the segment received
+/// before, checking the whole as it grows.
+async fn receive(resp: Response, have: &mut Vec<u8>) -> Result<()> {
+    let mut check = SegmentCheck::continuing(&resp, have)?;
+    let mut body = resp.body;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(Interrupted)?;
+        check.update(&chunk)?;
+        have.extend_from_slice(&chunk);
+    }
+    Ok(check.finish()?)
+}
+
+/// A segment body that broke off; what arrived is kept for the next attempt.
+#[derive(Debug)]
+struct Interrupted(anyhow::Error);
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.root_cause())
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Requests `url`, or with `have` bytes of it received already, the rest.
+/// Returns whether the response continues those bytes: a server that ignores
+/// the `Range` header sends the whole segment again.
+async fn request_rest(fetcher: &dyn HttpFetcher, url: &str, have: u64) -> Result<(Response, bool)> {
+    if have == 0 {
+        return Ok((fetcher.get(url).await?, false));
+    }
+    debug!("Requesting {} from byte {}", url, have);
+    let range = [("Range".to_string(), format!("bytes={}-", have))];
+    let resp = fetcher.request(url, &range).await?;
+    let continues = resp.status == 206;
+    Ok((resp, continues))
+}
+
+/// For a failed segment attempt that another one may fix (a damaged or
+/// interrupted body), says so and returns the error to report should it be
+/// the last. Other errors are passed on.
+fn retry_reason(e: anyhow::Error, url: &str, again: bool) -> Result<anyhow::Error> {
+    let (what, next) = match e.downcast_ref::<Damaged>() {
+        Some(damaged) => (format!("is damaged ({})", damaged), "fetching it again"),
+        None if e.is::<Interrupted>() => (format!("broke off ({})", e), "requesting the rest"),
+        None => return Err(e),
+    };
+    if again {
+        warn!("Segment {} {}; {}", url, what, next);
+    }
+    // Unless decryption broke it off, the connection or the server is to blame.
+    Ok(exit::failure(&e).unwrap_or(Failure::Network).of(anyhow!("Segment {} {}", url, what)))
+}
+
+/// Where temporary files of a download into `output` go: `tmp_dir`, or else
+/// the directory of the output.
+fn scratch_dir(config: &Config, output: &Path) -> PathBuf {
+    match (&config.tmp_dir, output.parent()) {
+        (Some(dir), _) => dir.clone(),
+        (None, Some(dir)) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        (None, _) => PathBuf::from("."),
+    }
+}
+
+/// `00000.ts`, `00001.ts`, ...: names that sort in playlist order.
+fn segment_file(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{:05}.ts", index))
+}
+
+async fn download_with_retry(fetcher: &dyn HttpFetcher, url: &str, retry: Retry) -> Result<String> {
+    download_playlist(fetcher, url, retry).await.map(|(text, _)| text)
+}
+
+/// Like [`download_with_retry`], but also returns the URL the text was
+/// served from after redirects, which relative URIs in it are resolved
+/// against.
+async fn download_playlist(fetcher: &dyn HttpFetcher, url: &str, retry: Retry) -> Result<(String, String)> {
+    let mut last_error = None;
+
+    for attempt in 0..=retry.retries {
+        match fetcher.get(url).await {
+            Ok(mut resp) if resp.is_success() => {
+                let base = resp.url.take().unwrap_or_else(|| url.to_string());
+                let content_type = resp.content_type.take();
+                let text = resp.text().await.context("Failed to read response body")?;
+                // A page given on purpose is left to the parser, which points to `page:`.
+                let playlist_url = url::Url::parse(url).is_ok_and(|url| url.path().ends_with(".m3u8"));
+                if playlist::is_web_page(&text, content_type.as_deref())
+                    && (playlist_url || playlist::is_login_page(&text))
+                {
+                    return Err(Failure::Playlist.of(anyhow!(
+                        "{} answered with a web page instead of the playlist{}: the session has probably expired \
+                         or the video needs authentication; log in again with `getcourse-downloader login`, or \
+                         pass the browser's cookies with --cookie",
+                        url,
+                        if playlist::is_login_page(&text) { " (a login form)" } else { "" }
+                    )));
+                }
+                return Ok((text, base));
+            }
+            Ok(resp) => last_error = Some(Failure::Network.of(anyhow!("HTTP status: {}", resp.status))),
+            Err(e) => last_error = Some(e),
+        }
+
+        if attempt < retry.retries {
+            let delay = retry.delay(attempt);
+            warn!("Retry {}/{} in {:.1}s...", attempt + 1, retry.retries, delay.as_secs_f64());
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("Unknown error")))
+}
+
+/// Downloads one segment to `path`, or with `range`, into that byte range of
+/// the (shared) file at `path`. Returns its size, with `hash` its SHA-256 as
+/// received, and the number of attempts it took. Without `range`, a body that breaks off is kept in
+/// `<path>.part` and the next attempt (or run) asks only for the rest.
+#[allow(clippy::too_many_arguments)]
+async fn download_segment(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    path: &Path,
+    range: Option<Range<u64>>,
+    retry: Retry,
+    write: SegmentWrite,
+    sync: bool,
+    hash: bool,
+) -> Result<(u64, Option<String>, usize)> {
+    let mut last_error = None;
+    let part = part_file(path);
+
+    for attempt in 0..=retry.retries {
+        let have = match range {
+            None => fs::metadata(&part).map_or(0, |m| m.len()),
+            Some(_) => 0,
+        };
+        match request_rest(fetcher, url, have).await {
+            Ok((resp, continues)) if resp.is_success() => {
+                match store_segment(resp, path, &range, continues, write, sync, hash).await {
+                    Ok((size, digest)) => return Ok((size, digest, attempt + 1)),
+                    Err(e) => {
+                        if !e.is::<Interrupted>() {
+                            let _ = fs::remove_file(&part);
+                        }
+                        last_error = Some(retry_reason(e, url, attempt < retry.retries)?);
+                    }
+                }
+            }
+            Ok((resp, _)) => {
+                // Perhaps the part no longer matches the segment (416).
+                let _ = fs::remove_file(&part);
+                last_error = Some(Failure::Network.of(anyhow!("HTTP status: {}", resp.status)));
+            }
+            Err(e) => last_error = Some(e),
+        }
+        if let Some(e) = &last_error {
+            debug!("Attempt {} of segment {} failed: {:#}", attempt + 1, url, e);
+        }
+
+        if attempt < retry.retries {
+            retry.wait(attempt).await;
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("Failed after {} retries", retry.retries)))
+}
+
+/// Where the received part of the segment stored at `path` waits while it is
+/// incomplete.
+fn part_file(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Writes the body of one segment response to its slot, checking it on the
+/// way; with `continues`, the body is the rest of the segment's part file.
+/// Fails with [`Damaged`] when the segment is not plausible, and with
+/// [`Interrupted`] when the body breaks off, leaving what arrived in the part
+/// file (unless writing into a `range`).
+async fn store_segment(
+    resp: Response,
+    path: &Path,
+    range: &Option<Range<u64>>,
+    continues: bool,
+    write: SegmentWrite,
+    sync: bool,
+    hash: bool,
+) -> Result<(u64, Option<String>)> {
+    let part = part_file(path);
+    let prefix = match continues {
+        true => tokio::fs::read(&part).await.context("Failed to read the partial segment")?,
+        false => Vec::new(),
+    };
+    let mut check = SegmentCheck::continuing(&resp, &prefix)?;
+    let mut body = resp.body;
+    match write {
+        SegmentWrite::Buffered => {
+            let mut bytes = prefix;
+            while let Some(chunk) = body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        if range.is_none() && !bytes.is_empty() {
+                            tokio::fs::write(&part, &bytes).await.context("Failed to save the partial segment")?;
+                        }
+                        return Err(Interrupted(e).into());
+                    }
+                };
+                check.update(&chunk)?;
+                bytes.extend_from_slice(&chunk);
+            }
+            check.finish()?;
+            check_slot(range, bytes.len() as u64, true)?;
+            let mut file = open_slot(path, range).await?;
+            file.write_all(&bytes).await.context("Failed to write file")?;
+            // tokio finishes writes in the background; the segment is read back right away.
+            file.flush().await.context("Failed to write file")?;
+            if sync {
+                file.sync_all().await.context("Failed to sync file")?;
+            }
+            if continues {
+                let _ = tokio::fs::remove_file(&part).await;
+            }
+            Ok((bytes.len() as u64, hash.then(|| sha256::digest_hex(&bytes))))
+        }
+        SegmentWrite::Streamed => {
+            let mut file = match (range, continues) {
+                (Some(_), _) => open_slot(path, range).await?,
+                (None, true) => tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&part)
+                    .await
+                    .context("Failed to open the partial segment")?,
+                (None, false) => tokio::fs::File::create(&part).await.context("Failed to create file")?,
+            };
+            let mut hasher = hash.then(Sha256::new);
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&prefix);
+            }
+            let mut written = prefix.len() as u64;
+            while let Some(chunk) = body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        file.flush().await.context("Failed to write file")?;
+                        return Err(Interrupted(e).into());
+                    }
+                };
+                check.update(&chunk)?;
+                check_slot(range, written + chunk.len() as u64, false)?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&chunk);
+                }
+                file.write_all(&chunk).await.context("Failed to write file")?;
+                written += chunk.len() as u64;
+            }
+            check.finish()?;
+            check_slot(range, written, true)?;
+            file.flush().await.context("Failed to write file")?;
+            if sync {
+                file.sync_all().await.context("Failed to sync file")?;
+            }
+            if range.is_none() {
+                tokio::fs::rename(&part, path).await.context("Failed to write file")?;
+            }
+            Ok((written, hasher.map(|h| sha256::to_hex(&h.finalize()))))
+        }
+    }
+}
+
+/// Opens the file a segment is written to, positioned at its range.
+async fn open_slot(path: &Path, range: &Option<Range<u64>>) -> Result<tokio::fs::File> {
+    let Some(range) = range else {
+        return tokio::fs::File::create(path).await.context("Failed to create file");
+    };
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await.context("Failed to open the output")?;
+    file.seek(io::SeekFrom::Start(range.start)).await.context("Failed to seek in the output")?;
+    Ok(file)
+}
+
+/// Fails when `len` bytes would overflow the segment's range, or with
+/// `complete`, do not fill it exactly: the segment changed since its size was
+/// probed, and writing on would corrupt its neighbours.
+fn check_slot(range: &Option<Range<u64>>, len: u64, complete: bool) -> Result<()> {
+    match range {
+        Some(range) if len > range.end - range.start || (complete && len != range.end - range.start) => Err(anyhow!(
+            "Segment size changed since it was probed ({} bytes instead of {}); run again without --in-place",
+            len,
+            range.end - range.start
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn concatenate_files(temp_dir: &Path, output_file: &mut impl io::Write) -> Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(temp_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "ts"))
+        .collect();
+
+    entries.sort();
+
+    for entry in entries {
+        let mut segment_file = File::open(&entry)?;
+        io::copy(&mut segment_file, output_file)?;
+    }
+
+    Ok(())
+}
+
+/// Joins the downloaded `segments` in `dir` into `output_path`, rebasing the
+/// timestamps if the playlist has discontinuities and the segments are MPEG-TS.
+/// With `direct`, the output is written with direct I/O where possible.
+fn join_segments(dir: &Path, segments: &[Segment], output_path: &Path, direct: bool) -> Result<()> {
+    let rebase = Rebase::needed(segments, &fs::read(segment_file(dir, 0))?);
+    if rebase {
+        info!("Rebasing timestamps across discontinuities");
+    }
+    fn join(dir: &Path, segments: &[Segment], rebase: bool, output: &mut impl io::Write) -> Result<()> {
+        if rebase { concatenate_rebased(dir, segments, output) } else { concatenate_files(dir, output) }
+    }
+    if direct {
+        let mut output_file = direct::DirectFile::create(output_path)?;
+        join(dir, segments, rebase, &mut output_file)?;
+        output_file.finish().context("Failed to write the output")?;
+        Ok(())
+    } else {
+        join(dir, segments, rebase, &mut File::create(output_path)?)
+    }
+}
+
+/// Like [`concatenate_files`], but rebases the timestamps (see [`Rebase`]).
+fn concatenate_rebased(temp_dir: &Path, segments: &[Segment], output_file: &mut impl io::Write) -> Result<()> {
+    let mut rebase = Rebase::new(segments);
+    for i in 0..segments.len() {
+        let mut data = fs::read(segment_file(temp_dir, i))?;
+        rebase.apply(i, &mut data);
+        output_file.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// Shifts the MPEG-TS timestamps after each discontinuity so they continue
+/// where the previous part ended (going by the `#EXTINF` durations), leaving
+/// one seekable timeline. Segments must be passed in order.
+struct Rebase {
+    /// Discontinuity flag and duration of every segment.
+    segments: Vec<(bool, f64)>,
+    base: Option<u64>,
+    elapsed: f64,
+    offset: i64,
+}
+
+impl Rebase {
+    fn new(segments: &[Segment]) -> Self {
+        let segments = segments.iter().map(|s| (s.discontinuity, s.duration)).collect();
+        Self { segments, base: None, elapsed: 0.0, offset: 0 }
+    }
+
+    /// Whether `segments` need rebasing: they have discontinuities, and the
+    /// first one, `first`, is MPEG-TS.
+    fn needed(segments: &[Segment], first: &[u8]) -> bool {
+        segments.iter().skip(1).any(|s| s.discontinuity)
+            && media::probe(first).is_some_and(|i| i.format == Format::Ts)
+    }
+
+    fn apply(&mut self, index: usize, data: &mut [u8]) {
+        let (discontinuity, duration) = self.segments[index];
+        if index == 0 || discontinuity {
+            match (self.base, media::first_pts(data)) {
+                (None, Some(pts)) => self.base = Some(pts),
+                (Some(base), Some(pts)) => {
+                    self.offset = base as i64 + (self.elapsed * 90_000.0) as i64 - pts as i64
+                }
+                (_, None) => {
+                    warn!("Segment {} has no timestamps; leaving it as is", index + 1);
+                    self.offset = 0;
+                }
+            }
+        }
+        if self.offset != 0 {
+            media::shift_timestamps(data, self.offset);
+        }
+        self.elapsed += duration;
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `6h`, `1d` (bare numbers are seconds)
+/// and clock positions like `1:02:03.5` or `12:30`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    if s.contains(':') {
+        let invalid = || anyhow!("Invalid time '{}'", s);
+        let mut seconds = 0.0;
+        for part in s.split(':') {
+            // Digits and a dot only: no sign, `inf` or `NaN`.
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+                return Err(invalid());
+            }
+            let value: f64 = part.parse().map_err(|_| invalid())?;
+            seconds = seconds * 60.0 + value;
+        }
+        return Duration::try_from_secs_f64(seconds).map_err(|_| invalid());
+    }
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| anyhow!("Invalid duration '{}'", s))?;
+    let factor = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(anyhow!("Invalid duration unit in '{}' (use ms, s, m, h or d)", s)),
+    };
+    Duration::try_from_secs_f64(number * factor).map_err(|_| anyhow!("Invalid duration '{}'", s))
+}
+
+/// `--preview`: a [`parse_duration`] that is longer than zero.
+pub fn parse_preview(s: &str) -> Result<Duration> {
+    let preview = parse_duration(s)?;
+    if preview.is_zero() {
+        return Err(anyhow!("Invalid preview '{}'; it must be longer than 0", s));
+    }
+    Ok(preview)
+}
+
+/// Longest file name kept, in bytes: file systems allow 255, and room is left
+/// for the extension and the `.parts` work directory next to it.
+const MAX_NAME: usize = 200;
+
+/// Names Windows reserves for devices, whatever the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Replaces characters that are invalid in file names on Windows, macOS or
+/// Linux and trims the result. Windows device names get a `_` in front, a
+/// leading dot (a hidden file) is dropped and long names are shortened.
+fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut cleaned = cleaned.trim().trim_start_matches('.').trim_end_matches('.').trim().to_string();
+    if cleaned.len() > MAX_NAME {
+        let end = (0..=MAX_NAME).rev().find(|&i| cleaned.is_char_boundary(i)).unwrap_or(0);
+        cleaned.truncate(end);
+        cleaned = cleaned.trim_end().trim_end_matches('.').to_string();
+    }
+    let device = cleaned.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(device)) {
+        cleaned.insert(0, '_');
+    }
+    if cleaned.is_empty() { "video".to_string() } else { cleaned }
+}
+
+/// The output while it is written: `name.part.ext` next to it, so that the
+/// extension still tells ffmpeg the container. It replaces the output only
+/// once complete; a failed run removes it and leaves an earlier file of the
+/// output's name as it was.
+struct Partial {
+    path: PathBuf,
+    committed: bool,
+}
+
+impl Partial {
+    fn create(output: &Path) -> Result<Self> {
+        let mut name = output.file_stem().unwrap_or_default().to_os_string();
+        name.push(".part");
+        if let Some(extension) = output.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        let path = output.with_file_name(name);
+        File::create(&path).with_context(|| format!("Cannot create {}", path.display()))?;
+        Ok(Self { path, committed: false })
+    }
+
+    /// Moves the finished file to `output`, replacing whatever was there.
+    fn commit(mut self, output: &Path) -> Result<()> {
+        fs::rename(&self.path, output)
+            .with_context(|| format!("Failed to rename {} to {}", self.path.display(), output.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("1:02:03").unwrap(), Duration::from_secs(3723));
+        assert_eq!(parse_duration("12:30.5").unwrap(), Duration::from_secs_f64(750.5));
+    }
+
+    #[test]
+    fn bad_durations_are_errors() {
+        for bad in ["", "-1:00", "1:-5", "+1:00", "inf:00", "NaN:00", "1::2", "5x", "-5s", "1e400", "1e999:00"] {
+            assert!(parse_duration(bad).is_err(), "{:?} was accepted", bad);
+        }
+        assert!(parse_duration(&"9".repeat(30)).is_err());
+    }
+
+    #[test]
+    fn zero_preview_is_an_error() {
+        assert!(parse_preview("0").is_err());
+        assert_eq!(parse_preview("0:30").unwrap(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn reserved_windows_names() {
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("nul.ts"), "_nul.ts");
+        assert_eq!(sanitize_filename("Com1 .txt"), "_Com1 .txt");
+        assert_eq!(sanitize_filename("CONSOLE"), "CONSOLE");
+        assert_eq!(sanitize_filename("LPT10"), "LPT10");
+    }
+
+    #[test]
+    fn trailing_and_leading_dots() {
+        assert_eq!(sanitize_filename("Lesson 1..."), "Lesson 1");
+        assert_eq!(sanitize_filename(" .hidden. "), "hidden");
+        assert_eq!(sanitize_filename("..."), "video");
+        assert_eq!(sanitize_filename("a: b/c?"), "a_ b_c_");
+    }
+
+    #[test]
+    fn long_names_are_cut_at_a_character() {
+        let name = sanitize_filename(&"я".repeat(150));
+        assert!(name.len() <= MAX_NAME && name.chars().all(|c| c == 'я'), "{:?}", name);
+    }
+}
@@ -0,0 +1,221 @@
+//! `--live`: recording a stream that is still going. A media playlist
+//! without `#EXT-X-ENDLIST` lists only the latest segments; it is read again
+//! every target duration, and the segments that have appeared since (by
+//! `#EXT-X-MEDIA-SEQUENCE` number) are appended to the output in order. The
+//! recording ends with the stream (`#EXT-X-ENDLIST`), at `max_duration`, or
+//! on Ctrl+C (or SIGTERM), and keeps what was recorded up to then.
+
+use crate::{
+    config::Config,
+    decrypt::KeyRing,
+    download_playlist, fetch_segment,
+    http::HttpFetcher,
+    interrupt, media, media_playlist, mirror, obfuscation,
+    playlist::Media,
+    progress::{clock, size},
+    remux::{self, Container},
+    scratch_dir, DownloadOptions, Partial, Report,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// How long to wait between polls when the playlist does not say.
+const DEFAULT_TARGET: f64 = 6.0;
+
+/// Options of a normal download that a recording cannot honour, as the
+/// message names them.
+fn unsupported(config: &Config, opts: &DownloadOptions) -> Option<&'static str> {
+    [
+        (opts.preview.is_some(), "--preview"),
+        (opts.start.is_some() || opts.end.is_some(), "--start/--end"),
+        (config.recode.is_some(), "--recode"),
+        (config.all_audio || !config.audio_languages.is_empty(), "Alternate audio"),
+        (config.captions, "--captions"),
+        (!config.subtitles.is_empty(), "--subs"),
+        (config.hash_manifest || config.verify_manifest, "A hash manifest"),
+        (opts.serve.is_some(), "--serve"),
+        (opts.keep_segments.is_some(), "--keep-segments"),
+    ]
+    .into_iter()
+    .find_map(|(used, what)| used.then_some(what))
+}
+
+/// Records the live stream whose media playlist was read as `media` into
+/// `output_file` (`-` for stdout), remuxing it into `container` at the end.
+pub async fn record(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    opts: &DownloadOptions,
+    media: Media,
+    output_file: &Path,
+    container: Container,
+) -> Result<Report> {
+    if let Some(what) = unsupported(config, opts) {
+        return Err(anyhow!("{} cannot be used when recording a live stream", what));
+    }
+    if media.segments.iter().any(|s| s.map.is_some()) {
+        return Err(anyhow!("Recording a live fragmented MP4 stream (#EXT-X-MAP) is not supported yet"));
+    }
+    let to_stdout = output_file == Path::new(crate::STDOUT);
+    // Written as received; remuxed outputs are recorded as .ts first.
+    let partial = match to_stdout {
+        true => None,
+        false => Some(Partial::create(output_file)?),
+    };
+    let recording = match container.needs_remux() && !to_stdout {
+        true => {
+            let dir = scratch_dir(config, output_file);
+            fs::create_dir_all(&dir)?;
+            Some(tempfile::Builder::new().suffix(".ts").tempfile_in(dir)?)
+        }
+        false => None,
+    };
+    let mut out: Box<dyn Write> = match (&recording, &partial) {
+        (Some(recording), _) => Box::new(io::BufWriter::new(recording.reopen()?)),
+        (None, Some(partial)) => Box::new(io::BufWriter::new(File::create(&partial.path)?)),
+        (None, None) => Box::new(io::BufWriter::new(io::stdout())),
+    };
+
+    // Playlists are read again from their own host only.
+    let segment_fetcher = mirror::with_mirrors(fetcher, &config.mirrors)?;
+    info!("Recording the live stream; it ends with the stream, or press Ctrl+C to stop");
+    let _handling = interrupt::handle();
+    let interrupted = interrupt::signalled();
+    tokio::pin!(interrupted);
+    let mut tape = Tape::default();
+    let mut media = media;
+    let outcome = loop {
+        let target = media.target_duration.unwrap_or(DEFAULT_TARGET);
+        let before = tape.segments;
+        tokio::select! {
+            result = tape.append(&segment_fetcher, config, &media, out.as_mut()) => {
+                if let Err(e) = result {
+                    break Err(e);
+                }
+            }
+            _ = &mut interrupted => break Ok("interrupted"),
+        }
+        if media.ended {
+            break Ok("the stream ended");
+        }
+        if config.max_duration > 0 && tape.duration >= config.max_duration as f64 {
+            break Ok("max_duration reached");
+        }
+        // An unchanged playlist is read again sooner (RFC 8216, 6.3.4).
+        let wait = if tape.segments > before { target } else { target / 2.0 };
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs_f64(wait)) => {}
+            _ = &mut interrupted => break Ok("interrupted"),
+        }
+        let polled = async {
+            let (text, base) = download_playlist(fetcher.as_ref(), &media.url, config.playlist_retry()).await?;
+            media_playlist(&text, &base)
+        };
+        match polled.await {
+            Ok(next) => media = next,
+            Err(e) => break Err(e.context("Failed to read the live playlist again")),
+        }
+    };
+    out.flush().context("Failed to write the recording")?;
+    drop(out);
+
+    if tape.segments == 0 {
+        return Err(outcome.err().unwrap_or_else(|| anyhow!("Nothing was recorded")));
+    }
+    match &outcome {
+        Ok(reason) => info!("Recording stopped: {}", reason),
+        Err(e) => warn!("Recording stopped: {:#}", e),
+    }
+    if tape.missed > 0 {
+        warn!("{} segment(s) left the playlist before they could be recorded", tape.missed);
+    }
+    info!("Recorded {} segments, {} ({})", tape.segments, clock(tape.duration), size(tape.bytes));
+    if let Some(partial) = partial {
+        if let Some(recording) = &recording {
+            let (info, recorded, path) = (tape.info.clone(), recording.path().to_path_buf(), partial.path.clone());
+            let (title, album) = (opts.title.clone(), opts.course.clone());
+            let metadata = remux::Metadata { title, album, chapters: Vec::new() };
+            crate::blocking(move || {
+                remux::remux(&recorded, &path, container, info.as_ref(), None, None, &[], &metadata)
+            })
+            .await?;
+        }
+        partial.commit(output_file)?;
+        info!("Output file:\n{}", output_file.display());
+    }
+    outcome?;
+    Ok(Report { segments: tape.segments, bytes: tape.bytes, output: output_file.to_path_buf() })
+}
+
+/// What has been recorded so far.
+#[derive(Default)]
+struct Tape {
+    /// Sequence number of the next segment to record.
+    next: Option<u64>,
+    segments: usize,
+    duration: f64,
+    bytes: u64,
+    /// Segments gone from the playlist before they were fetched.
+    missed: u64,
+    /// The stream as probed from its first segment.
+    info: Option<media::StreamInfo>,
+    keys: KeyRing,
+}
+
+impl Tape {
+    /// Appends the segments of `media` not recorded yet to `out`, in order.
+    async fn append(
+        &mut self,
+        fetcher: &Arc<dyn HttpFetcher>,
+        config: &Config,
+        media: &Media,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let next = *self.next.get_or_insert(media.sequence);
+        if media.sequence > next {
+            self.missed += media.sequence - next;
+        }
+        let skip = next.saturating_sub(media.sequence) as usize;
+        let new = media.segments.get(skip..).unwrap_or_default();
+        if new.is_empty() {
+            return Ok(());
+        }
+        let had_keys = !self.keys.is_empty();
+        let keys = new.iter().map(|s| (s.url.as_str(), s.key.as_ref()));
+        let fetcher = self.keys.decrypting(fetcher, keys, config.playlist_retry()).await?;
+        let fetcher = obfuscation::unmasking(&fetcher, new.iter().map(|s| s.url.as_str()));
+        if !had_keys && !self.keys.is_empty() {
+            info!("Segments are encrypted with AES-128; decrypting them");
+        }
+        let retry = config.segment_retry();
+        let mut fetched = futures::stream::iter(new)
+            .map(|segment| {
+                let fetcher = Arc::clone(&fetcher);
+                async move { fetch_segment(fetcher.as_ref(), &segment.url, retry).await }
+            })
+            .buffered(config.concurrency);
+        let mut sequence = media.sequence + skip as u64;
+        for segment in new {
+            let (data, _) = fetched.next().await.expect("one result per segment")?;
+            if self.info.is_none() {
+                self.info = media::probe(&data);
+            }
+            out.write_all(&data).context("Failed to write the recording")?;
+            self.segments += 1;
+            self.duration += segment.duration;
+            self.bytes += data.len() as u64;
+            sequence += 1;
+            self.next = Some(sequence);
+            info!("Recorded segment {} ({}, {})", sequence - 1, clock(self.duration), size(self.bytes));
+        }
+        Ok(())
+    }
+}
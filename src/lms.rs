@@ -0,0 +1,120 @@
+//! Lesson-page scraping shared by the LMS extractors: find the HLS playlist of
+//! the embedded player, following the player iframe when the link is not on
+//! the page itself.
+
+use crate::{
+    download_with_retry,
+    getcourse::{attr, decode_entities, page_title, tags},
+    http::HttpFetcher,
+    retry::Retry,
+};
+use anyhow::{anyhow, Context, Result};
+use url::Url;
+
+/// An LMS whose lesson pages embed a player with an HLS playlist.
+pub struct Platform {
+    pub name: &'static str,
+    /// Host suffixes of the platform's school sites.
+    pub hosts: &'static [&'static str],
+}
+
+/// Platforms handled by the generic lesson-page extractor. GetCourse has its
+/// own extractor because its lesson URLs are recognizable on custom domains.
+pub const PLATFORMS: &[Platform] = &[
+    Platform { name: "zenclass", hosts: &["zenclass.ru", "zenclass.net"] },
+    Platform { name: "prodamus", hosts: &["prodamus.xl", "xl.prodamus.ru"] },
+];
+
+impl Platform {
+    pub fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default();
+        self.hosts
+            .iter()
+            .any(|suffix| host == *suffix || host.ends_with(&format!(".{}", suffix)))
+    }
+}
+
+/// The video of a lesson page.
+pub struct PageVideo {
+    pub playlist: String,
+    /// The page's heading or title, which names the lesson.
+    pub title: Option<String>,
+}
+
+/// Fetches `page` and returns the master playlist of its video. Only iframes
+/// whose `src` satisfies `is_player` are followed.
+pub async fn find_page_playlist(
+    fetcher: &dyn HttpFetcher,
+    page: &Url,
+    retry: Retry,
+    is_player: fn(&str) -> bool,
+) -> Result<PageVideo> {
+    let html = download_with_retry(fetcher, page.as_str(), retry)
+        .await
+        .context("Failed to download lesson page")?;
+    let playlist = page_playlist(fetcher, page, &html, retry, is_player)
+        .await?
+        .ok_or_else(|| anyhow!("No video player found on {} (authentication required?)", page))?;
+    Ok(PageVideo { playlist, title: page_title(&html) })
+}
+
+/// Like [`find_page_playlist`] for a page already fetched as `html`; `None`
+/// when the page has no player at all, such as a text-only lesson.
+pub async fn page_playlist(
+    fetcher: &dyn HttpFetcher,
+    page: &Url,
+    html: &str,
+    retry: Retry,
+    is_player: fn(&str) -> bool,
+) -> Result<Option<String>> {
+    if let Some(url) = playlist_in(html, page) {
+        return Ok(Some(url));
+    }
+
+    let players: Vec<Url> = tags(html, "iframe")
+        .into_iter()
+        .filter_map(|tag| attr(tag, "src"))
+        .filter(|src| is_player(src))
+        .filter_map(|src| page.join(&src).ok())
+        .collect();
+    if players.is_empty() {
+        return Ok(None);
+    }
+    for player in &players {
+        if let Some(url) = known_embed(player) {
+            return Ok(Some(url));
+        }
+        let player_html = download_with_retry(fetcher, player.as_str(), retry)
+            .await
+            .context("Failed to download player page")?;
+        if let Some(url) = playlist_in(&player_html, player) {
+            return Ok(Some(url));
+        }
+    }
+    Err(anyhow!("Player page {} has no playlist link", players[0]))
+}
+
+/// The `data-master` attribute of the player element, else the first quoted
+/// `.m3u8` URL anywhere in the page (player configs are often inline JSON).
+pub fn playlist_in(html: &str, base: &Url) -> Option<String> {
+    if let Some(url) = attr(html, "data-master").filter(|url| !url.is_empty()) {
+        return Some(url);
+    }
+    html.split(['"', '\''])
+        .skip(1)
+        .step_by(2)
+        .map(|s| decode_entities(&s.replace("\\/", "/")))
+        .find(|s| s.contains(".m3u8") && !s.contains(char::is_whitespace))
+        .and_then(|s| base.join(&s).ok())
+        .map(String::from)
+}
+
+/// Players whose playlist URL follows from the embed URL alone.
+fn known_embed(player: &Url) -> Option<String> {
+    // Kinescope (used by many ZenClass schools): /embed/<id> -> /<id>/master.m3u8
+    if player.host_str()?.ends_with("kinescope.io") {
+        let id = player.path().strip_prefix("/embed/")?.trim_end_matches('/');
+        return Some(format!("https://kinescope.io/{}/master.m3u8", id));
+    }
+    None
+}
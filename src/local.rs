@@ -0,0 +1,141 @@
+//! Playlists read from a file or stdin instead of the network, for manifests
+//! captured by hand (from the browser's developer tools, say) and for
+//! debugging. The playlist is served to the rest of the pipeline under a URL
+//! of its own by `Preloaded`, so nothing downstream needs to know.
+
+use crate::{
+    exit::{Categorize, Failure},
+    http::{HttpFetcher, Response},
+};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream};
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    sync::Arc,
+};
+use url::{ParseError, Url};
+
+/// Whether the first argument names a local playlist: `-` (stdin), a
+/// `file://` URL or anything without a scheme.
+pub fn is_local(source: &str) -> bool {
+    source == "-" || source.starts_with("file://") || !source.contains("://")
+}
+
+/// Reads the playlist named by `source` and makes its URIs absolute against
+/// `base_url`. Returns the URL the playlist is served under (`base_url`, or
+/// the file's own `file://` URL) and a fetcher that serves it there.
+pub fn load(
+    fetcher: &Arc<dyn HttpFetcher>,
+    source: &str,
+    base_url: Option<&str>,
+) -> Result<(String, Arc<dyn HttpFetcher>)> {
+    let text = match source {
+        "-" => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).context("Failed to read the playlist from stdin")?;
+            text
+        }
+        source => {
+            let path = match Url::parse(source) {
+                Ok(url) => url.to_file_path().map_err(|_| anyhow!("Invalid file URL: {}", source))?,
+                Err(_) => Path::new(source).to_path_buf(),
+            };
+            fs::read_to_string(&path).with_context(|| format!("Cannot read playlist {}", path.display()))?
+        }
+    };
+    if !text.trim_start().starts_with("#EXTM3U") {
+        let source = if source == "-" { "stdin" } else { source };
+        return Err(Failure::Playlist.of(anyhow!("{} is not an M3U8 playlist", source)));
+    }
+    let base =
+        base_url.map(|url| Url::parse(url).with_context(|| format!("Invalid --base-url: {}", url))).transpose()?;
+    let text = absolutize(&text, base.as_ref()).category(Failure::Playlist)?;
+    let url = match (base, source) {
+        (Some(base), _) => base.to_string(),
+        (None, "-") => "file:///dev/stdin".to_string(),
+        (None, source) => match Url::parse(source) {
+            Ok(url) => url.to_string(),
+            Err(_) => {
+                let path = fs::canonicalize(source)?;
+                Url::from_file_path(&path).map_err(|_| anyhow!("Cannot make a URL of {}", path.display()))?.to_string()
+            }
+        },
+    };
+    let preloaded = Preloaded { inner: Arc::clone(fetcher), url: url.clone(), body: Bytes::from(text) };
+    Ok((url, Arc::new(preloaded)))
+}
+
+/// Resolves relative URI lines and `URI="..."` attributes against `base`.
+fn absolutize(text: &str, base: Option<&Url>) -> Result<String> {
+    let resolve = |uri: &str| -> Result<String> {
+        match Url::parse(uri) {
+            Ok(_) => Ok(uri.to_string()),
+            Err(ParseError::RelativeUrlWithoutBase) => match base {
+                Some(base) => Ok(base.join(uri)?.to_string()),
+                None => {
+                    Err(anyhow!("The playlist has relative URIs such as {:?}; give --base-url to resolve them", uri))
+                }
+            },
+            Err(e) => Err(anyhow!("Invalid URI {:?} in the playlist: {}", uri, e)),
+        }
+    };
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        let line = line.trim_end();
+        if !line.is_empty() && !line.starts_with('#') {
+            out.push_str(&resolve(line)?);
+        } else if let Some(start) = line.find("URI=\"").map(|i| i + 5)
+            && let Some(len) = line[start..].find('"')
+        {
+            out.push_str(&line[..start]);
+            out.push_str(&resolve(&line[start..start + len])?);
+            out.push_str(&line[start + len..]);
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Answers requests for `url` with `body` and passes everything else on to
+/// `inner`.
+struct Preloaded {
+    inner: Arc<dyn HttpFetcher>,
+    url: String,
+    body: Bytes,
+}
+
+impl Preloaded {
+    fn response(&self, body: bool) -> Response {
+        let chunks = if body { vec![Ok(self.body.clone())] } else { Vec::new() };
+        let length = Some(self.body.len() as u64);
+        Response {
+            status: 200,
+            length,
+            url: None,
+            content_type: None,
+            cookies: Vec::new(),
+            body: Box::pin(stream::iter(chunks)),
+        }
+    }
+}
+
+impl HttpFetcher for Preloaded {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        match url == self.url {
+            true => Box::pin(async move { Ok(self.response(true)) }),
+            false => self.inner.request(url, headers),
+        }
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        match url == self.url {
+            true => Box::pin(async move { Ok(self.response(false)) }),
+            false => self.inner.head(url, headers),
+        }
+    }
+}
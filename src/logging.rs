@@ -0,0 +1,170 @@
+//! Log output for the command line. The library reports through `tracing`:
+//! status lines at `info`, problems at `warn`, and request-level detail at
+//! `debug` and `trace`. This subscriber prints status to stdout and the rest
+//! to stderr, with the level picked by `-q`/`-v`/`-vv` or `RUST_LOG`.
+
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Instant,
+};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span, Event, Level, Metadata, Subscriber,
+};
+
+/// Target of the library's own events.
+const CRATE: &str = "getcourse_downloader";
+
+/// Whether status lines go to stderr, leaving stdout to the video.
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// One `RUST_LOG` directive: a level, for events whose target starts with
+/// `target`, or for all of them.
+struct Directive {
+    target: Option<String>,
+    level: LevelFilter,
+}
+
+struct Logger {
+    /// Most specific first.
+    directives: Vec<Directive>,
+    started: Instant,
+    next_span: AtomicU64,
+}
+
+/// Installs the logger. `verbosity` is -1 for `-q` (warnings and errors
+/// only), 0 for status lines, 1 for `-v` (debug) and 2 for `-vv` (trace);
+/// without a flag, `RUST_LOG` takes precedence when it is set.
+pub fn init(verbosity: Option<i8>) {
+    let directives = match (verbosity, std::env::var("RUST_LOG")) {
+        (None, Ok(spec)) if !spec.trim().is_empty() => parse(&spec),
+        (verbosity, _) => {
+            let level = match verbosity.unwrap_or(0) {
+                ..0 => LevelFilter::WARN,
+                0 => LevelFilter::INFO,
+                1 => LevelFilter::DEBUG,
+                _ => LevelFilter::TRACE,
+            };
+            // Other crates' detail is noise unless asked for by name.
+            vec![
+                Directive { target: Some(CRATE.into()), level },
+                Directive { target: None, level: level.min(LevelFilter::WARN) },
+            ]
+        }
+    };
+    let logger = Logger { directives, started: Instant::now(), next_span: AtomicU64::new(1) };
+    // Only fails when a subscriber is set already, which then stays.
+    let _ = tracing::subscriber::set_global_default(logger);
+}
+
+/// Sends status lines to stderr from now on, so that stdout carries nothing
+/// but its data: the video of a download to `-`, or the URL list or script
+/// of `--print-urls` and `export-plan`.
+pub fn status_to_stderr() {
+    STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// `RUST_LOG` in its common form: comma-separated `level` or
+/// `target=level`. Other parts are reported and left out. Targets not named
+/// keep the status lines.
+fn parse(spec: &str) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let (target, level) = match part.split_once('=') {
+            Some((target, level)) => (Some(target.trim().to_string()), level.trim()),
+            // A bare target enables everything for it.
+            None if part.parse::<LevelFilter>().is_err() => (Some(part.to_string()), "trace"),
+            None => (None, part),
+        };
+        match level.parse() {
+            Ok(level) => directives.push(Directive { target, level }),
+            Err(_) => eprintln!("Ignoring {:?} in RUST_LOG: unknown level {:?}", part, level),
+        }
+    }
+    if !directives.iter().any(|d| d.target.is_none()) {
+        directives.push(Directive { target: None, level: LevelFilter::INFO });
+    }
+    directives.sort_by_key(|d| std::cmp::Reverse(d.target.as_ref().map_or(0, |t| t.len() + 1)));
+    directives
+}
+
+impl Logger {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|d| d.target.as_deref().is_none_or(|prefix| target.starts_with(prefix)))
+            .map_or(LevelFilter::OFF, |d| d.level)
+    }
+}
+
+/// Collects an event's message and other fields.
+#[derive(Default)]
+struct Line {
+    message: String,
+    fields: String,
+}
+
+impl Visit for Line {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.push_str(value),
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.level(metadata.target()) >= *metadata.level()
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.directives.iter().map(|d| d.level).max()
+    }
+
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut line = Line::default();
+        event.record(&mut line);
+        let metadata = event.metadata();
+        // Status and warnings read as they always have; detail is marked.
+        match *metadata.level() {
+            Level::INFO if !STATUS_TO_STDERR.load(Ordering::Relaxed) => println!("{}{}", line.message, line.fields),
+            Level::INFO | Level::WARN | Level::ERROR => eprintln!("{}{}", line.message, line.fields),
+            level => eprintln!(
+                "[{:>8.3}s {:<5} {}] {}{}",
+                self.started.elapsed().as_secs_f64(),
+                level,
+                metadata.target(),
+                line.message,
+                line.fields
+            ),
+        }
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
@@ -0,0 +1,74 @@
+//! Segment hash manifest (`--hash-manifest`). The SHA-256 of every segment is
+//! taken from the data as it comes off the network and written next to the
+//! output in the tagged format of `sha256sum --tag`, which names the
+//! algorithm on every line, together with the digest of the finished file.
+//! SHA-256 rather than BLAKE3: this crate implements it already, and every
+//! system has a tool to check it. With `--verify-manifest` the stored segments are hashed again before
+//! they are joined, so that silent disk corruption on a long-running archive
+//! box is caught (and the damaged segments fetched again) instead of ending
+//! up in the output.
+
+use crate::sha256::{self, Sha256};
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Hex digest by segment index.
+    digests: BTreeMap<usize, String>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, index: usize, digest: String) {
+        self.digests.insert(index, digest);
+    }
+
+    /// Segments whose stored data, as returned by `read`, no longer matches
+    /// the digest recorded when they were downloaded.
+    pub fn verify(&self, read: impl Fn(usize) -> Result<Vec<u8>>) -> Result<Vec<usize>> {
+        let mut damaged = Vec::new();
+        for (&index, digest) in &self.digests {
+            if sha256::digest_hex(&read(index)?) != *digest {
+                damaged.push(index);
+            }
+        }
+        Ok(damaged)
+    }
+
+    /// Writes the manifest to `path`: a line per segment (`SHA256 (00000.ts)
+    /// = HEX`, the segment's name in the work directory), then one for
+    /// `output`.
+    pub fn write(&self, path: &Path, output: &Path) -> Result<()> {
+        let mut text = String::new();
+        for (index, digest) in &self.digests {
+            text.push_str(&format!("SHA256 ({:05}.ts) = {}\n", index, digest));
+        }
+        let name = output.file_name().unwrap_or_default().to_string_lossy();
+        text.push_str(&format!("SHA256 ({}) = {}\n", name, file_digest(output)?));
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Hex SHA-256 of the file at `path`, read in chunks.
+fn file_digest(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).with_context(|| format!("Failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(sha256::to_hex(&hasher.finalize()))
+}
@@ -0,0 +1,282 @@
+//! Stream detection from the first segment: the PAT/PMT of an MPEG-TS segment
+//! or the sample descriptions in an fMP4 init segment.
+
+use std::fmt;
+
+const TS_PACKET: usize = 188;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Mpeg2Video,
+    H264,
+    Hevc,
+    Mp3,
+    Aac,
+    Ac3,
+    Eac3,
+}
+
+impl Codec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Mpeg2Video => "MPEG-2 video",
+            Codec::H264 => "H.264",
+            Codec::Hevc => "H.265",
+            Codec::Mp3 => "MP3",
+            Codec::Aac => "AAC",
+            Codec::Ac3 => "AC-3",
+            Codec::Eac3 => "E-AC-3",
+        }
+    }
+
+    pub fn is_video(self) -> bool {
+        matches!(self, Codec::Mpeg2Video | Codec::H264 | Codec::Hevc)
+    }
+
+    /// PMT `stream_type` values used by HLS packagers.
+    fn from_stream_type(stream_type: u8) -> Option<Self> {
+        Some(match stream_type {
+            0x01 | 0x02 => Codec::Mpeg2Video,
+            0x1b => Codec::H264,
+            0x24 => Codec::Hevc,
+            0x03 | 0x04 => Codec::Mp3,
+            0x0f | 0x11 => Codec::Aac,
+            0x81 => Codec::Ac3,
+            0x87 => Codec::Eac3,
+            _ => return None,
+        })
+    }
+
+    /// `stsd` sample entry types.
+    fn from_fourcc(fourcc: &[u8]) -> Option<Self> {
+        Some(match fourcc {
+            b"avc1" | b"avc3" => Codec::H264,
+            b"hvc1" | b"hev1" => Codec::Hevc,
+            b"mp4a" => Codec::Aac,
+            b".mp3" => Codec::Mp3,
+            b"ac-3" => Codec::Ac3,
+            b"ec-3" => Codec::Eac3,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ts,
+    Fmp4,
+}
+
+/// What the first segment revealed about the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub format: Format,
+    pub codecs: Vec<Codec>,
+}
+
+impl StreamInfo {
+    pub fn has_audio(&self) -> bool {
+        self.codecs.iter().any(|c| !c.is_video())
+    }
+
+    pub fn has(&self, codec: Codec) -> bool {
+        self.codecs.contains(&codec)
+    }
+}
+
+impl fmt::Display for StreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            Format::Ts => "MPEG-TS",
+            Format::Fmp4 => "fragmented MP4",
+        };
+        let list = |video: bool| {
+            let names: Vec<_> = self.codecs.iter().filter(|c| c.is_video() == video).map(|c| c.name()).collect();
+            if names.is_empty() { "none".to_string() } else { names.join(", ") }
+        };
+        write!(f, "{}, video: {}, audio: {}", format, list(true), list(false))
+    }
+}
+
+/// Identifies the container and codecs of a segment. Returns `None` for data
+/// that is neither (e.g. still encrypted).
+pub fn probe(data: &[u8]) -> Option<StreamInfo> {
+    if data.len() >= TS_PACKET && data[0] == 0x47 {
+        probe_ts(data)
+    } else if data.get(4..8).is_some_and(|t| [b"ftyp", b"moov", b"moof", b"styp"].iter().any(|k| k[..] == *t)) {
+        Some(StreamInfo { format: Format::Fmp4, codecs: mp4_codecs(data) })
+    } else {
+        None
+    }
+}
+
+fn probe_ts(data: &[u8]) -> Option<StreamInfo> {
+    let mut pmt_pid = None;
+    for packet in data.chunks_exact(TS_PACKET) {
+        if packet[0] != 0x47 {
+            return None;
+        }
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let Some(section) = psi_section(packet) else {
+            continue;
+        };
+        if pid == 0 && section.first() == Some(&0x00) {
+            // PAT: the first program that is not the network PID.
+            pmt_pid = section
+                .get(8..section_end(section)?)?
+                .chunks_exact(4)
+                .find(|p| u16::from_be_bytes([p[0], p[1]]) != 0)
+                .map(|p| u16::from_be_bytes([p[2] & 0x1f, p[3]]));
+        } else if Some(pid) == pmt_pid && section.first() == Some(&0x02) {
+            let end = section_end(section)?;
+            let program_info = usize::from(u16::from_be_bytes([section[10] & 0x0f, section[11]]));
+            let mut rest = section.get(12 + program_info..end)?;
+            let mut codecs = Vec::new();
+            while rest.len() >= 5 {
+                let info = usize::from(u16::from_be_bytes([rest[3] & 0x0f, rest[4]]));
+                codecs.extend(Codec::from_stream_type(rest[0]));
+                rest = rest.get(5 + info..)?;
+            }
+            return Some(StreamInfo { format: Format::Ts, codecs });
+        }
+    }
+    // Valid TS, but no PAT/PMT in this segment.
+    Some(StreamInfo { format: Format::Ts, codecs: Vec::new() })
+}
+
+/// The PSI section starting in `packet`, if it has one.
+fn psi_section(packet: &[u8]) -> Option<&[u8]> {
+    if packet[1] & 0x40 == 0 {
+        return None;
+    }
+    let mut payload = 4;
+    if packet[3] & 0x20 != 0 {
+        payload += 1 + usize::from(packet[4]);
+    }
+    let pointer = usize::from(*packet.get(payload)?);
+    packet.get(payload + 1 + pointer..)
+}
+
+/// End of a section's payload, excluding the CRC.
+fn section_end(section: &[u8]) -> Option<usize> {
+    let length = usize::from(u16::from_be_bytes([*section.get(1)? & 0x0f, *section.get(2)?]));
+    let end = (3 + length).checked_sub(4)?;
+    (end <= section.len()).then_some(end)
+}
+
+/// Sample entry codecs under `moov/trak/mdia/minf/stbl/stsd`.
+fn mp4_codecs(data: &[u8]) -> Vec<Codec> {
+    let mut codecs = Vec::new();
+    for moov in boxes(data).filter(|(t, _)| t == b"moov") {
+        for trak in boxes(moov.1).filter(|(t, _)| t == b"trak") {
+            let stsd = [b"mdia", b"minf", b"stbl", b"stsd"].iter().try_fold(trak.1, |body, name| {
+                boxes(body).find(|(t, _)| t == *name).map(|(_, b)| b)
+            });
+            // Full box header (4) and entry count (4), then sample entries.
+            if let Some(entries) = stsd.and_then(|s| s.get(8..)) {
+                codecs.extend(boxes(entries).filter_map(|(t, _)| Codec::from_fourcc(&t)));
+            }
+        }
+    }
+    codecs
+}
+
+/// Iterates over the ISO BMFF boxes in `data` as (type, body).
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = data.get(4..8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, data.len()),
+            1 => (16, u64::from_be_bytes(data.get(8..16)?.try_into().ok()?) as usize),
+            size => (8, size),
+        };
+        let body = data.get(header..size)?;
+        data = &data[size..];
+        Some((kind, body))
+    })
+}
+
+/// PTS/DTS wrap around at 33 bits.
+const TIMESTAMP_WRAP: u64 = 1 << 33;
+
+/// PTS of the first PES packet in an MPEG-TS segment (90 kHz units).
+pub fn first_pts(data: &[u8]) -> Option<u64> {
+    data.chunks_exact(TS_PACKET).find_map(|packet| {
+        let (pes, flags) = pes_header(packet)?;
+        (flags & 0b10 != 0).then(|| decode_timestamp(&packet[pes + 9..pes + 14]))
+    })
+}
+
+/// Shifts every PTS, DTS and PCR in an MPEG-TS segment by `offset` (90 kHz
+/// units), wrapping at 33 bits like the timestamps themselves.
+pub fn shift_timestamps(data: &mut [u8], offset: i64) {
+    let shift = |ts: u64| (ts as i64 + offset).rem_euclid(TIMESTAMP_WRAP as i64) as u64;
+    for packet in data.chunks_exact_mut(TS_PACKET) {
+        if packet[0] != 0x47 {
+            continue;
+        }
+        // PCR: 33-bit base in 90 kHz units followed by a 9-bit extension.
+        if packet[3] & 0x20 != 0 && packet[4] >= 7 && packet[5] & 0x10 != 0 {
+            let pcr = &mut packet[6..12];
+            let base = (u64::from(pcr[0]) << 25)
+                | (u64::from(pcr[1]) << 17)
+                | (u64::from(pcr[2]) << 9)
+                | (u64::from(pcr[3]) << 1)
+                | (u64::from(pcr[4]) >> 7);
+            let base = shift(base);
+            pcr[0] = (base >> 25) as u8;
+            pcr[1] = (base >> 17) as u8;
+            pcr[2] = (base >> 9) as u8;
+            pcr[3] = (base >> 1) as u8;
+            pcr[4] = (pcr[4] & 0x7f) | (((base & 1) as u8) << 7);
+        }
+        let Some((pes, flags)) = pes_header(packet) else {
+            continue;
+        };
+        if flags & 0b10 != 0 {
+            let field = &mut packet[pes + 9..pes + 14];
+            let ts = shift(decode_timestamp(field));
+            encode_timestamp(field, ts);
+        }
+        if flags == 0b11 && pes + 19 <= TS_PACKET {
+            let field = &mut packet[pes + 14..pes + 19];
+            let ts = shift(decode_timestamp(field));
+            encode_timestamp(field, ts);
+        }
+    }
+}
+
+/// Offset of a PES header starting in `packet` and its PTS_DTS_flags.
+fn pes_header(packet: &[u8]) -> Option<(usize, u8)> {
+    if packet[0] != 0x47 || packet[1] & 0x40 == 0 || packet[3] & 0x10 == 0 {
+        return None;
+    }
+    let pes = if packet[3] & 0x20 != 0 { 5 + usize::from(packet[4]) } else { 4 };
+    let header = packet.get(pes..pes + 14)?;
+    if header[..3] != [0, 0, 1] {
+        return None;
+    }
+    // Streams without the optional PES header (padding, private_stream_2, ...).
+    if matches!(header[3], 0xbc | 0xbe | 0xbf | 0xf0 | 0xf1 | 0xf2 | 0xf8 | 0xff) {
+        return None;
+    }
+    Some((pes, header[7] >> 6))
+}
+
+fn decode_timestamp(field: &[u8]) -> u64 {
+    (u64::from(field[0] >> 1) & 0x07) << 30
+        | u64::from(field[1]) << 22
+        | u64::from(field[2] >> 1) << 15
+        | u64::from(field[3]) << 7
+        | u64::from(field[4] >> 1)
+}
+
+/// Re-encodes `ts` in place, keeping the field's 4-bit prefix.
+fn encode_timestamp(field: &mut [u8], ts: u64) {
+    field[0] = (field[0] & 0xf0) | ((ts >> 29) as u8 & 0x0e) | 1;
+    field[1] = (ts >> 22) as u8;
+    field[2] = ((ts >> 14) as u8 & 0xfe) | 1;
+    field[3] = (ts >> 7) as u8;
+    field[4] = ((ts << 1) as u8 & 0xfe) | 1;
+}
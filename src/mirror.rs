@@ -0,0 +1,114 @@
+//! `--mirror URL`: other hosts that serve the same segments. A segment that
+//! keeps failing on its own host (an error status, a timeout, a body that
+//! breaks off) is requested at the same path on the mirrors in turn, and on
+//! its own host again between rounds, for as long as its retries last.
+
+use crate::http::{HttpFetcher, Response};
+use anyhow::{anyhow, Result};
+use futures::{future::BoxFuture, StreamExt};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use tracing::{debug, info};
+use url::Url;
+
+/// Failed attempts of a segment on its own host before the mirrors are tried.
+const OWN_HOST_ATTEMPTS: usize = 2;
+
+/// `fetcher`, trying `mirrors` for requests that keep failing; `fetcher`
+/// itself when there are none.
+pub fn with_mirrors(fetcher: &Arc<dyn HttpFetcher>, mirrors: &[String]) -> Result<Arc<dyn HttpFetcher>> {
+    if mirrors.is_empty() {
+        return Ok(Arc::clone(fetcher));
+    }
+    let mirrors = mirrors.iter().map(|m| parse(m)).collect::<Result<_>>()?;
+    Ok(Arc::new(Mirrored {
+        inner: Arc::clone(fetcher),
+        mirrors,
+        failures: Arc::default(),
+        announced: AtomicBool::new(false),
+    }))
+}
+
+/// A mirror base URL: `http(s)://host[:port][/prefix]`.
+pub fn parse(mirror: &str) -> Result<Url> {
+    let url = Url::parse(mirror).map_err(|_| anyhow!("Invalid mirror URL {:?}", mirror))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err(anyhow!("Invalid mirror URL {:?}; expected http(s)://host", mirror));
+    }
+    Ok(url)
+}
+
+struct Mirrored {
+    inner: Arc<dyn HttpFetcher>,
+    mirrors: Vec<Url>,
+    /// Failed attempts by requested URL.
+    failures: Arc<Mutex<HashMap<String, usize>>>,
+    /// Whether the first move to a mirror was reported.
+    announced: AtomicBool,
+}
+
+impl Mirrored {
+    /// Where attempt number `failed` (0 for the first) of `url` goes.
+    fn target(&self, url: &str, failed: usize) -> Option<String> {
+        let turn = failed.checked_sub(OWN_HOST_ATTEMPTS)? % (self.mirrors.len() + 1);
+        on_mirror(url, self.mirrors.get(turn)?)
+    }
+}
+
+/// `url` with its scheme, host and port taken from `mirror`, and the path of
+/// `mirror` put in front of its own.
+fn on_mirror(url: &str, mirror: &Url) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let mut moved = mirror.clone();
+    moved.set_path(&format!("{}{}", mirror.path().trim_end_matches('/'), url.path()));
+    moved.set_query(url.query());
+    moved.set_fragment(url.fragment());
+    Some(moved.into())
+}
+
+fn count(failures: &Mutex<HashMap<String, usize>>, url: &str) {
+    *failures.lock().unwrap().entry(url.to_string()).or_default() += 1;
+}
+
+impl HttpFetcher for Mirrored {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let failed = self.failures.lock().unwrap().get(url).copied().unwrap_or(0);
+            let target = self.target(url, failed);
+            if let Some(target) = &target {
+                if !self.announced.swap(true, Ordering::Relaxed) {
+                    info!("{} keeps failing; trying the mirrors for it and any others that do", url);
+                }
+                debug!("Requesting {} from a mirror: {}", url, target);
+            }
+            let result = self.inner.request(target.as_deref().unwrap_or(url), headers).await;
+            match result {
+                Ok(mut resp) if resp.is_success() => {
+                    let (failures, url) = (Arc::clone(&self.failures), url.to_string());
+                    resp.body = resp
+                        .body
+                        .inspect(move |chunk| {
+                            if chunk.is_err() {
+                                count(&failures, &url);
+                            }
+                        })
+                        .boxed();
+                    Ok(resp)
+                }
+                other => {
+                    count(&self.failures, url);
+                    other
+                }
+            }
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        self.inner.head(url, headers)
+    }
+}
@@ -0,0 +1,161 @@
+//! GetCourse `.bin` segments: MPEG-TS under a light disguise, so that the
+//! files do not pass for video. The start of each body is sniffed for it:
+//! bytes in front of the first TS packet (a small image header, say) are cut
+//! off, and a body whose every byte is XORed with one key byte is XORed back.
+//! A body that already is MPEG-TS or MP4, or that cannot be made out, is
+//! passed through as it is.
+
+use crate::http::{HttpFetcher, Response};
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use std::{collections::HashSet, sync::Arc};
+use tracing::debug;
+use url::Url;
+
+const TS_PACKET: usize = 188;
+
+/// How far into a body the first TS packet is looked for.
+const SNIFF: usize = 64 * 1024;
+
+/// Consecutive packets that make it MPEG-TS rather than a stray 0x47.
+const RUN: usize = 4;
+
+/// `fetcher`, undoing the disguise of the `.bin` ones among `urls`.
+pub fn unmasking<'a>(fetcher: &Arc<dyn HttpFetcher>, urls: impl IntoIterator<Item = &'a str>) -> Arc<dyn HttpFetcher> {
+    let masked: HashSet<String> = urls.into_iter().filter(|url| is_bin(url)).map(String::from).collect();
+    if masked.is_empty() {
+        return Arc::clone(fetcher);
+    }
+    Arc::new(Unmasking { inner: Arc::clone(fetcher), masked })
+}
+
+/// Whether the path of `url` ends in `.bin`.
+fn is_bin(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.path().to_ascii_lowercase().ends_with(".bin"))
+}
+
+/// What was done to a segment, as told by its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    None,
+    /// This many bytes in front of the first packet.
+    Prefix(usize),
+    /// Every byte XORed with this one.
+    Xor(u8),
+}
+
+impl Mask {
+    fn of(head: &[u8]) -> Self {
+        if is_ts(head) || is_mp4(head) {
+            return Mask::None;
+        }
+        let key = head.first().map_or(0, |&b| b ^ 0x47);
+        if key != 0 && is_ts(&head.iter().take(RUN * TS_PACKET).map(|b| b ^ key).collect::<Vec<_>>()) {
+            return Mask::Xor(key);
+        }
+        match (1..head.len().min(SNIFF)).find(|&start| is_ts(&head[start..])) {
+            Some(start) => Mask::Prefix(start),
+            None => Mask::None,
+        }
+    }
+
+    fn unmask(self, mut data: BytesMut) -> Bytes {
+        match self {
+            Mask::None => {}
+            Mask::Prefix(len) => drop(data.split_to(len.min(data.len()))),
+            Mask::Xor(key) => data.iter_mut().for_each(|b| *b ^= key),
+        }
+        data.freeze()
+    }
+}
+
+/// Whether `data` starts with [`RUN`] TS packets, or as many as it holds.
+fn is_ts(data: &[u8]) -> bool {
+    let packets = (data.len() / TS_PACKET).min(RUN);
+    packets > 0 && (0..packets).all(|i| data[i * TS_PACKET] == 0x47)
+}
+
+/// Whether `data` starts with an MP4 box an fMP4 segment begins with.
+fn is_mp4(data: &[u8]) -> bool {
+    data.get(4..8).is_some_and(|kind| [b"ftyp", b"styp", b"moof", b"moov", b"sidx"].iter().any(|k| kind == *k))
+}
+
+/// Passes requests through, undoing the disguise of the bodies of `masked`
+/// segments. Their sizes change with it, so HEAD requests for them report no
+/// length.
+struct Unmasking {
+    inner: Arc<dyn HttpFetcher>,
+    masked: HashSet<String>,
+}
+
+impl HttpFetcher for Unmasking {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        if !self.masked.contains(url) {
+            return self.inner.request(url, headers);
+        }
+        Box::pin(async move {
+            // The disguise is told by the start; the whole segment is sent on.
+            let headers: Vec<_> =
+                headers.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("range")).cloned().collect();
+            let mut response = self.inner.request(url, &headers).await?;
+            if !response.is_success() {
+                return Ok(response);
+            }
+            let body = std::mem::replace(&mut response.body, futures::stream::empty().boxed());
+            response.body = unmasked(body, url.to_string());
+            response.length = None;
+            Ok(response)
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        let masked = self.masked.contains(url);
+        Box::pin(async move {
+            let mut response = self.inner.head(url, headers).await?;
+            if masked {
+                response.length = None;
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// `body` with its disguise undone: held back until [`SNIFF`] bytes (and a
+/// few packets past them) have arrived or it ends, then passed on unmasked.
+fn unmasked(body: BoxStream<'static, Result<Bytes>>, url: String) -> BoxStream<'static, Result<Bytes>> {
+    let state = (body, None::<Mask>, BytesMut::new());
+    futures::stream::unfold(Some(state), move |state| {
+        let url = url.clone();
+        async move {
+            let (mut body, mask, mut head) = state?;
+            if let Some(mask) = mask {
+                return match body.next().await? {
+                    Ok(chunk) if mask == Mask::None => Some((Ok(chunk), Some((body, Some(mask), head)))),
+                    Ok(chunk) => Some((Ok(mask.unmask(BytesMut::from(&chunk[..]))), Some((body, Some(mask), head)))),
+                    Err(e) => Some((Err(e), None)),
+                };
+            }
+            let ended = loop {
+                if head.len() >= SNIFF + RUN * TS_PACKET {
+                    break false;
+                }
+                match body.next().await {
+                    Some(Ok(chunk)) => head.extend_from_slice(&chunk),
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => break true,
+                }
+            };
+            let mask = Mask::of(&head);
+            if mask != Mask::None {
+                debug!("Unmasking {} ({:?})", url, mask);
+            }
+            let data = mask.unmask(std::mem::take(&mut head));
+            // Only the start has anything in front of it.
+            let rest = if let Mask::Xor(_) = mask { mask } else { Mask::None };
+            Some((Ok(data), (!ended).then_some((body, Some(rest), head))))
+        }
+    })
+    .filter(|chunk| futures::future::ready(!chunk.as_ref().is_ok_and(|chunk| chunk.is_empty())))
+    .boxed()
+}
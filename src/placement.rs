@@ -0,0 +1,195 @@
+//! Media-library placement rules: route downloads to a directory and file
+//! name chosen by matching the school (host) and course title.
+//!
+//! ```toml
+//! [rules.psychology]
+//! school = "psy-school"            # glob on the school name or host
+//! course = "*терапия*"             # glob on the course title
+//! dir = "~/Archive/{school}/{course}"
+//! name = "{index:03} {lesson}"
+//! ```
+
+use crate::sanitize_filename;
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use tracing::info;
+use url::Url;
+
+#[derive(Debug, Clone, Default)]
+pub struct Rule {
+    pub name: String,
+    pub school: Option<String>,
+    pub course: Option<String>,
+    pub dir: Option<String>,
+    pub filename: Option<String>,
+}
+
+pub const RULE_FIELDS: &[&str] = &["school", "course", "dir", "name"];
+pub const DEFAULT_NAME: &str = "{lesson}";
+
+impl Rule {
+    pub fn set(&mut self, field: &str, value: String) -> Result<()> {
+        match field {
+            "school" => self.school = Some(value),
+            "course" => self.course = Some(value),
+            "dir" => self.dir = Some(value),
+            "name" => {
+                check_template(&value)?;
+                self.filename = Some(value)
+            }
+            other => {
+                return Err(anyhow!(
+                    "unknown rule field '{}' (expected one of: {})",
+                    other,
+                    RULE_FIELDS.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, course: &CourseInfo) -> bool {
+        let school_ok = self.school.as_deref().is_none_or(|pattern| {
+            glob_match(pattern, &course.school) || glob_match(pattern, &course.host)
+        });
+        let course_ok = self
+            .course
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, &course.title));
+        school_ok && course_ok
+    }
+}
+
+/// What is known about the course a lesson belongs to.
+#[derive(Debug, Clone)]
+pub struct CourseInfo {
+    pub host: String,
+    pub school: String,
+    pub title: String,
+}
+
+impl CourseInfo {
+    pub fn new(url: &Url, title: &str) -> Self {
+        let host = url.host_str().unwrap_or_default().to_string();
+        // `myschool.getcourse.ru` -> `myschool`; custom domains stay as they are.
+        let school = host
+            .strip_suffix(".getcourse.ru")
+            .unwrap_or(&host)
+            .to_string();
+        Self { host, school, title: title.to_string() }
+    }
+}
+
+/// Where a course's lessons go and how each file is named.
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub dir: PathBuf,
+    name: String,
+    course: CourseInfo,
+}
+
+impl Placement {
+    /// Picks the first matching rule; without one, lessons go to `fallback_dir`.
+    pub fn resolve(rules: &[Rule], course: CourseInfo, fallback_dir: PathBuf) -> Self {
+        let rule = rules.iter().find(|rule| rule.matches(&course));
+        if let Some(rule) = rule {
+            info!("Using placement rule '{}'", rule.name);
+        }
+        let dir = rule
+            .and_then(|r| r.dir.as_deref())
+            .map(|template| expand_home(&render(template, &course, None, 0)))
+            .unwrap_or(fallback_dir);
+        let name = rule
+            .and_then(|r| r.filename.clone())
+            .unwrap_or_else(|| DEFAULT_NAME.to_string());
+        Self { dir, name, course }
+    }
+
+    /// File name (with extension) for the `index`-th lesson (1-based).
+    pub fn file_name(&self, lesson: &str, index: usize, extension: &str) -> String {
+        format!("{}.{}", sanitize_filename(&render(&self.name, &self.course, Some(lesson), index)), extension)
+    }
+}
+
+/// Expands `{school}`, `{host}`, `{course}`, `{lesson}` and `{index}` (with
+/// optional zero padding, e.g. `{index:03}`). Values are sanitized so they
+/// cannot introduce extra path components.
+fn render(template: &str, course: &CourseInfo, lesson: Option<&str>, index: usize) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let placeholder = &rest[start + 1..start + end];
+        let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        let value = match name {
+            "school" => sanitize_filename(&course.school),
+            "host" => sanitize_filename(&course.host),
+            "course" => sanitize_filename(&course.title),
+            "lesson" => lesson.map(sanitize_filename).unwrap_or_default(),
+            "index" => {
+                let width = spec.trim_start_matches('0').parse().unwrap_or(0);
+                format!("{:0width$}", index, width = width)
+            }
+            _ => format!("{{{}}}", placeholder),
+        };
+        out.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn check_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed '{{' in template '{}'", template))?;
+        let placeholder = &rest[start + 1..start + end];
+        let name = placeholder.split(':').next().unwrap_or_default();
+        if !["school", "host", "course", "lesson", "index"].contains(&name) {
+            return Err(anyhow!("unknown placeholder {{{}}} in template '{}'", placeholder, template));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(rest))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Case-insensitive glob with `*` and `?`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
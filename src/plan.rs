@@ -0,0 +1,283 @@
+//! The download plan (segment URLs in order, and the headers each request
+//! needs, the configured ones and the saved session included) for tools
+//! other than this one: `--print-urls` lists it, and `export-plan` renders it
+//! into a script for aria2c, curl or PowerShell, for machines where this
+//! binary cannot run but one of those can.
+
+use crate::{byterange, config::Config, extractor, http::HttpFetcher, playlist::ByteRange, resolve_segments, session};
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use tracing::warn;
+use url::Url;
+
+/// What a download would fetch.
+pub struct Plan {
+    /// Sent with every request.
+    pub headers: Vec<(String, String)>,
+    pub segments: Vec<Request>,
+}
+
+/// One segment: a URL, or a slice of one (`#EXT-X-BYTERANGE`).
+pub struct Request {
+    /// Without the fragment that names the slice inside this tool.
+    pub url: String,
+    pub range: Option<ByteRange>,
+}
+
+impl Request {
+    fn new(url: String) -> Self {
+        match byterange::split(&url) {
+            Some((base, range)) => Self { url: base.to_string(), range: Some(range) },
+            None => Self { url, range: None },
+        }
+    }
+
+    /// The `N-M` of `Range: bytes=N-M` for a slice.
+    fn bytes(&self) -> Option<String> {
+        self.range.map(|range| format!("{}-{}", range.offset, range.offset + range.length - 1))
+    }
+}
+
+impl Plan {
+    /// Resolves `url` (a playlist or a page an extractor understands) down to
+    /// the segments of its media playlist.
+    pub async fn resolve(fetcher: &Arc<dyn HttpFetcher>, config: &Config, url: &str) -> Result<Self> {
+        let extraction = extractor::extract(fetcher.as_ref(), url, config.playlist_retry(), false).await?;
+        let fetcher = extractor::with_headers(fetcher, extraction.headers.clone());
+        let segments = resolve_segments(fetcher.as_ref(), &extraction.playlist, config.playlist_retry(), config.quality).await?;
+        if let Some(key) = segments.iter().find_map(|s| s.key.as_ref()) {
+            warn!("Note: the segments are AES-128 encrypted (key: {}); they are listed as served", key.uri);
+        }
+        if config.request_hook.is_some() {
+            warn!("Note: requests also pass through the request hook, which may change URLs and headers");
+        }
+        let segments: Vec<Request> = segments.into_iter().map(|s| Request::new(s.url)).collect();
+        let headers = headers(config, extraction.headers, &segments);
+        Ok(Self { headers, segments })
+    }
+}
+
+/// The headers every segment request of a download carries: those of the
+/// extractor, replaced by `--header`, `--referer`, `--cookie` and
+/// `--user-agent` of the same name, and else the saved login session of the
+/// school, when the segments are all on its hosts (it goes nowhere else).
+fn headers(config: &Config, extracted: Vec<(String, String)>, segments: &[Request]) -> Vec<(String, String)> {
+    let mut own = config.headers.clone();
+    if let Some(cookie) = &config.cookie {
+        own.push(("Cookie".to_string(), cookie.clone()));
+    }
+    if let Some(agent) = &config.user_agent {
+        own.push(("User-Agent".to_string(), agent.clone()));
+    }
+    let mut headers: Vec<_> =
+        extracted.into_iter().filter(|(name, _)| !own.iter().any(|(set, _)| set.eq_ignore_ascii_case(name))).collect();
+    headers.extend(own);
+    let has_cookie = headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("cookie"));
+    if !has_cookie && let Some(sessions) = session::saved() {
+        let cookies: Vec<Option<&str>> = segments
+            .iter()
+            .map(|segment| Url::parse(&segment.url).ok()?.host_str().and_then(|host| sessions.cookie(host)))
+            .collect();
+        if let Some(Some(cookie)) = cookies.first()
+            && cookies.iter().all(|other| other == &Some(*cookie))
+        {
+            headers.push(("Cookie".to_string(), cookie.to_string()));
+        }
+    }
+    headers
+}
+
+/// `--print-urls`: the segment URLs in order, one per line, after the
+/// headers as `# Name: value` comment lines (which `aria2c -i` and `wget -i`
+/// skip). A slice is followed by its `Range` as an aria2c option line.
+pub async fn print_urls(fetcher: &Arc<dyn HttpFetcher>, config: &Config, url: &str) -> Result<()> {
+    let plan = Plan::resolve(fetcher, config, url).await?;
+    for (name, value) in &plan.headers {
+        println!("# {}: {}", name, value);
+    }
+    for segment in &plan.segments {
+        println!("{}", segment.url);
+        if let Some(bytes) = segment.bytes() {
+            println!("  header=Range: bytes={}", bytes);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Aria2,
+    Curl,
+    PowerShell,
+}
+
+impl Format {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "aria2" => Ok(Format::Aria2),
+            "curl" => Ok(Format::Curl),
+            "powershell" => Ok(Format::PowerShell),
+            other => Err(anyhow!("Unknown plan format {:?}; expected aria2, curl or powershell", other)),
+        }
+    }
+}
+
+struct ExportOptions {
+    url: Option<String>,
+    output: Option<String>,
+    format: Format,
+}
+
+impl ExportOptions {
+    fn from_args(args: &[String]) -> Result<Self> {
+        let mut opts = Self { url: None, output: None, format: Format::Curl };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| iter.next().ok_or_else(|| anyhow!("Missing value for {}", name)).cloned();
+            match arg.as_str() {
+                "--format" => opts.format = Format::parse(&value(arg)?)?,
+                other if !other.starts_with('-') && opts.url.is_none() => opts.url = Some(other.to_string()),
+                other if !other.starts_with('-') && opts.output.is_none() => opts.output = Some(other.to_string()),
+                other => return Err(anyhow!("Unknown export-plan option: {}", other)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// `export-plan URL [OUTPUT] [--format aria2|curl|powershell]`: prints a
+/// script that downloads the segments into `OUTPUT.parts` and joins them into
+/// `OUTPUT` (default `video.ts`).
+pub async fn run(fetcher: &Arc<dyn HttpFetcher>, config: &Config, args: &[String]) -> Result<()> {
+    let opts = ExportOptions::from_args(args)?;
+    let url = opts.url.ok_or_else(|| {
+        anyhow!("Usage: getcourse-downloader export-plan URL [OUTPUT] [--format aria2|curl|powershell]")
+    })?;
+    let output = opts.output.unwrap_or_else(|| "video.ts".to_string());
+    let plan = Plan::resolve(fetcher, config, &url).await?;
+    let script = match opts.format {
+        Format::Aria2 => aria2(&plan, &url, &output, config.concurrency),
+        Format::Curl => curl(&plan, &url, &output),
+        Format::PowerShell => powershell(&plan, &url, &output),
+    };
+    print!("{}", script);
+    Ok(())
+}
+
+/// `00000.ts`, `00001.ts`, ...: names that sort in playlist order, as in the
+/// downloader's own work directory.
+fn part_name(index: usize) -> String {
+    format!("{:05}.ts", index)
+}
+
+/// Quotes `s` for a POSIX shell.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes `s` as a PowerShell literal string.
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn header(plan: &Plan, url: &str, comment: &str) -> String {
+    format!(
+        "{c} Download plan for {} ({} segments), exported by getcourse-downloader {}.\n",
+        url,
+        plan.segments.len(),
+        env!("CARGO_PKG_VERSION"),
+        c = comment
+    )
+}
+
+/// A shell script feeding an aria2c input file (with per-download `out=` and
+/// `header=` options) on stdin, then joining the parts.
+fn aria2(plan: &Plan, url: &str, output: &str, concurrency: usize) -> String {
+    let parts = format!("{}.parts", output);
+    let mut s = format!("#!/bin/sh\n{}set -e\n", header(plan, url, "#"));
+    s.push_str(&format!("mkdir -p {}\n", sh_quote(&parts)));
+    s.push_str(&format!(
+        "aria2c --input-file=- --dir={} --max-concurrent-downloads={} --auto-file-renaming=false --allow-overwrite=true <<'PLAN'\n",
+        sh_quote(&parts),
+        concurrency
+    ));
+    for (i, segment) in plan.segments.iter().enumerate() {
+        s.push_str(&format!("{}\n  out={}\n", segment.url, part_name(i)));
+        for (name, value) in &plan.headers {
+            s.push_str(&format!("  header={}: {}\n", name, value));
+        }
+        // aria2c must not split a slice into ranges of its own.
+        if let Some(bytes) = segment.bytes() {
+            s.push_str(&format!("  header=Range: bytes={}\n  split=1\n", bytes));
+        }
+    }
+    s.push_str("PLAN\n");
+    s.push_str(&format!("cat {}/*.ts > {}\n", sh_quote(&parts), sh_quote(output)));
+    s.push_str(&format!("rm -r {}\n", sh_quote(&parts)));
+    s
+}
+
+/// A shell script with one `curl` call per segment, in order.
+fn curl(plan: &Plan, url: &str, output: &str) -> String {
+    let parts = format!("{}.parts", output);
+    let mut s = format!("#!/bin/sh\n{}set -e\n", header(plan, url, "#"));
+    s.push_str(&format!("mkdir -p {}\n", sh_quote(&parts)));
+    let headers: String = plan.headers.iter().map(|(n, v)| format!(" -H {}", sh_quote(&format!("{}: {}", n, v)))).collect();
+    for (i, segment) in plan.segments.iter().enumerate() {
+        let part = format!("{}/{}", parts, part_name(i));
+        let range = segment.bytes().map_or(String::new(), |bytes| format!(" -r {}", bytes));
+        s.push_str(&format!(
+            "curl -fsSL --retry 5{}{} -o {} {}\n",
+            headers,
+            range,
+            sh_quote(&part),
+            sh_quote(&segment.url)
+        ));
+    }
+    s.push_str(&format!("cat {}/*.ts > {}\n", sh_quote(&parts), sh_quote(output)));
+    s.push_str(&format!("rm -r {}\n", sh_quote(&parts)));
+    s
+}
+
+/// A PowerShell script using `Invoke-WebRequest`, for Windows machines
+/// without curl or aria2.
+fn powershell(plan: &Plan, url: &str, output: &str) -> String {
+    let parts = format!("{}.parts", output);
+    let mut s = header(plan, url, "#");
+    s.push_str("$ErrorActionPreference = 'Stop'\n");
+    s.push_str(&format!("$parts = [System.IO.Path]::Combine($PWD.Path, {})\n", ps_quote(&parts)));
+    s.push_str(&format!("$output = [System.IO.Path]::Combine($PWD.Path, {})\n", ps_quote(output)));
+    s.push_str("$headers = @{\n");
+    for (name, value) in &plan.headers {
+        s.push_str(&format!("    {} = {}\n", ps_quote(name), ps_quote(value)));
+    }
+    s.push_str("}\n$urls = @(\n");
+    for segment in &plan.segments {
+        s.push_str(&format!("    {}\n", ps_quote(&segment.url)));
+    }
+    // Slices of one resource carry their range; the rest an empty string.
+    s.push_str(")\n$ranges = @(\n");
+    for segment in &plan.segments {
+        let range = segment.bytes().map_or(String::new(), |bytes| format!("bytes={}", bytes));
+        s.push_str(&format!("    {}\n", ps_quote(&range)));
+    }
+    s.push_str(concat!(
+        ")\n",
+        "New-Item -ItemType Directory -Force -Path $parts | Out-Null\n",
+        "for ($i = 0; $i -lt $urls.Count; $i++) {\n",
+        "    $part = Join-Path $parts ('{0:d5}.ts' -f $i)\n",
+        "    $request = $headers.Clone()\n",
+        "    if ($ranges[$i]) { $request['Range'] = $ranges[$i] }\n",
+        "    Invoke-WebRequest -UseBasicParsing -Headers $request -Uri $urls[$i] -OutFile $part\n",
+        "}\n",
+        "$out = [System.IO.File]::Create($output)\n",
+        "Get-ChildItem $parts -Filter *.ts | Sort-Object Name | ForEach-Object {\n",
+        "    $in = [System.IO.File]::OpenRead($_.FullName)\n",
+        "    $in.CopyTo($out)\n",
+        "    $in.Close()\n",
+        "}\n",
+        "$out.Close()\n",
+        "Remove-Item -Recurse $parts\n",
+    ));
+    s
+}
@@ -0,0 +1,359 @@
+//! M3U8 playlists (RFC 8216). A master playlist lists variants of the stream
+//! and alternate renditions; a media playlist lists the segments. [`parse`]
+//! tells the two apart and reads the tags the downloader acts on, making
+//! every URI absolute against the playlist's own URL.
+
+use crate::{
+    audio::Rendition,
+    byterange,
+    decrypt::{KeyTag, SegmentKey},
+    skip::{self, attribute_list},
+    variant::Variant,
+};
+use anyhow::{anyhow, Context, Result};
+use url::Url;
+
+pub enum Playlist {
+    Master(Master),
+    Media(Media),
+}
+
+/// A master playlist.
+pub struct Master {
+    /// `#EXT-X-STREAM-INF` entries, in playlist order.
+    pub variants: Vec<Variant>,
+    /// `#EXT-X-MEDIA:TYPE=AUDIO` renditions with their own media playlist.
+    /// The same rendition listed in several groups (one per bitrate ladder,
+    /// say) appears once; renditions without a URI are muxed into the
+    /// variants and need no separate download.
+    pub renditions: Vec<Rendition>,
+    /// `#EXT-X-MEDIA:TYPE=SUBTITLES` renditions, likewise.
+    pub subtitles: Vec<Rendition>,
+}
+
+/// A media playlist.
+pub struct Media {
+    /// The URL it was read from, after redirects.
+    pub url: String,
+    /// `#EXT-X-TARGETDURATION`: an upper bound on segment durations.
+    pub target_duration: Option<f64>,
+    /// `#EXT-X-MEDIA-SEQUENCE`: the sequence number of the first segment.
+    /// Live playlists drop old segments and raise it.
+    pub sequence: u64,
+    /// `#EXT-X-ENDLIST`: no more segments will be added.
+    pub ended: bool,
+    pub segments: Vec<Segment>,
+}
+
+/// A media segment as listed in the media playlist.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// With a byte range, [`byterange::mark`]ed with it.
+    pub url: String,
+    /// `#EXTINF` duration in seconds (the target duration when missing).
+    pub duration: f64,
+    /// `#EXTINF` title, after the duration, when not empty.
+    pub title: Option<String>,
+    /// Preceded by `#EXT-X-DISCONTINUITY`: timestamps may jump here.
+    pub discontinuity: bool,
+    /// Wall-clock start as Unix time, from `#EXT-X-PROGRAM-DATE-TIME`.
+    pub program_date: Option<f64>,
+    /// `#EXT-X-DATERANGE` tags that precede this segment.
+    pub dateranges: Vec<skip::DateRange>,
+    /// Set when the segment is encrypted (`#EXT-X-KEY`).
+    pub key: Option<SegmentKey>,
+    /// `#EXT-X-BYTERANGE`: only this part of the resource at `url`.
+    pub byte_range: Option<ByteRange>,
+    /// `#EXT-X-MAP`: the init segment of a fragmented MP4 stream.
+    pub map: Option<Map>,
+}
+
+/// An `#EXT-X-MAP` init segment, which the segments after it need in front of
+/// them to be playable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Map {
+    /// With a byte range, [`byterange::mark`]ed with it.
+    pub url: String,
+    /// The key in effect at the tag, if the init segment is encrypted.
+    pub key: Option<SegmentKey>,
+    pub byte_range: Option<ByteRange>,
+}
+
+/// `length` bytes starting at `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub length: u64,
+    pub offset: u64,
+}
+
+/// One line of a playlist.
+enum Line<'a> {
+    /// `#EXT...`, split at the first colon.
+    Tag(&'a str, &'a str),
+    Uri(&'a str),
+}
+
+fn lines(text: &str) -> impl Iterator<Item = Line<'_>> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).filter_map(|line| {
+        if line.starts_with("#EXT") {
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            Some(Line::Tag(name, value))
+        } else if line.starts_with('#') {
+            None
+        } else {
+            Some(Line::Uri(line))
+        }
+    })
+}
+
+/// Whether `text`, served as `content_type`, is a web page rather than a
+/// playlist.
+pub fn is_web_page(text: &str, content_type: Option<&str>) -> bool {
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let html = content_type.is_some_and(|t| t.trim_start().to_ascii_lowercase().starts_with("text/html"));
+    !text.starts_with("#EXTM3U") && (html || text.starts_with('<'))
+}
+
+/// Whether the web page `html` asks to log in. Once the session has expired,
+/// GetCourse answers playlist requests with its login form and status 200.
+pub fn is_login_page(html: &str) -> bool {
+    let html = html.to_ascii_lowercase();
+    ["type=\"password\"", "type='password'", "/cms/system/login", "/login?"].iter().any(|marker| html.contains(marker))
+}
+
+/// Parses `text`, downloaded from `url`.
+pub fn parse(text: &str, url: &str) -> Result<Playlist> {
+    if !text.trim_start().starts_with("#EXTM3U") {
+        if text.trim_start().starts_with('<') {
+            return Err(anyhow!(
+                "{} is a web page, not an M3U8 playlist; to find the video on it, give page:{}",
+                url,
+                url
+            ));
+        }
+        return Err(anyhow!("{} is not an M3U8 playlist (no #EXTM3U header)", url));
+    }
+    let base = Url::parse(url).ok();
+    let resolve = |uri: &str| -> Result<String> {
+        match &base {
+            Some(base) => Ok(base.join(uri).with_context(|| format!("Invalid URI {:?} in {}", uri, url))?.into()),
+            None => Ok(uri.to_string()),
+        }
+    };
+    let master = lines(text).any(|line| matches!(line, Line::Tag("#EXT-X-STREAM-INF", _)));
+    match master {
+        true => parse_master(text, &resolve).map(Playlist::Master),
+        false => parse_media(text, url, &resolve).map(Playlist::Media),
+    }
+}
+
+fn parse_master(text: &str, resolve: &dyn Fn(&str) -> Result<String>) -> Result<Master> {
+    let mut master = Master { variants: Vec::new(), renditions: Vec::new(), subtitles: Vec::new() };
+    let mut pending: Option<Variant> = None;
+    for line in lines(text) {
+        match line {
+            Line::Tag("#EXT-X-STREAM-INF", attributes) => {
+                let mut variant = Variant { bandwidth: 0, resolution: None, uri: String::new() };
+                for (key, value) in attribute_list(attributes) {
+                    match key {
+                        "BANDWIDTH" => variant.bandwidth = value.parse().unwrap_or(0),
+                        "RESOLUTION" => {
+                            variant.resolution =
+                                value.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        }
+                        _ => {}
+                    }
+                }
+                pending = Some(variant);
+            }
+            Line::Tag("#EXT-X-MEDIA", attributes) => {
+                let list = match rendition(attributes, resolve)? {
+                    Some(("AUDIO", rendition)) => Some((&mut master.renditions, rendition)),
+                    Some(("SUBTITLES", rendition)) => Some((&mut master.subtitles, rendition)),
+                    _ => None,
+                };
+                if let Some((list, rendition)) = list
+                    && !list.iter().any(|r| r.name == rendition.name && r.language == rendition.language)
+                {
+                    list.push(rendition);
+                }
+            }
+            Line::Uri(uri) => {
+                if let Some(mut variant) = pending.take() {
+                    variant.uri = resolve(uri)?;
+                    master.variants.push(variant);
+                }
+            }
+            Line::Tag(..) => {}
+        }
+    }
+    Ok(master)
+}
+
+/// An audio or subtitle rendition with a URI of its own, with its TYPE.
+fn rendition(
+    attributes: &str,
+    resolve: &dyn Fn(&str) -> Result<String>,
+) -> Result<Option<(&'static str, Rendition)>> {
+    let mut rendition = Rendition { name: String::new(), language: None, default: false, uri: String::new() };
+    let mut kind = None;
+    for (key, value) in attribute_list(attributes) {
+        match key {
+            "TYPE" => kind = ["AUDIO", "SUBTITLES"].into_iter().find(|&kind| kind == value),
+            "NAME" => rendition.name = value,
+            "LANGUAGE" => rendition.language = Some(value),
+            "DEFAULT" => rendition.default = value == "YES",
+            "URI" => rendition.uri = resolve(&value)?,
+            _ => {}
+        }
+    }
+    Ok(kind.filter(|_| !rendition.uri.is_empty()).map(|kind| (kind, rendition)))
+}
+
+fn parse_media(text: &str, url: &str, resolve: &dyn Fn(&str) -> Result<String>) -> Result<Media> {
+    let mut media =
+        Media { url: url.to_string(), target_duration: None, sequence: 0, ended: false, segments: Vec::new() };
+    let (mut duration, mut title, mut discontinuity, mut byte_range) = (None, None, false, None);
+    let (mut program_date, mut dateranges) = (None, Vec::new());
+    let (mut sequence, mut key, mut map) = (0, None, None);
+    // Where the previous byte range ended, for ranges without an offset.
+    let mut range_end: Option<(String, u64)> = None;
+    for line in lines(text) {
+        match line {
+            Line::Tag("#EXT-X-TARGETDURATION", value) => {
+                media.target_duration = Some(value.trim().parse().context("Invalid #EXT-X-TARGETDURATION")?)
+            }
+            Line::Tag("#EXT-X-MEDIA-SEQUENCE", value) => {
+                sequence = value.trim().parse().context("Invalid #EXT-X-MEDIA-SEQUENCE")?;
+                media.sequence = sequence;
+            }
+            Line::Tag("#EXTINF", value) => {
+                let (seconds, name) = value.split_once(',').unwrap_or((value, ""));
+                // A duration that does not parse, or is negative or not
+                // finite, is taken as missing.
+                duration = seconds.trim().parse().ok().filter(|d: &f64| d.is_finite() && *d >= 0.0);
+                title = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+            }
+            Line::Tag("#EXT-X-DISCONTINUITY", _) => discontinuity = true,
+            Line::Tag("#EXT-X-PROGRAM-DATE-TIME", value) => program_date = skip::parse_date(value),
+            Line::Tag("#EXT-X-DATERANGE", attributes) => dateranges.push(skip::DateRange::parse(attributes)),
+            Line::Tag("#EXT-X-KEY", attributes) => {
+                key = KeyTag::parse(attributes)?.map(|key| key.resolve(resolve)).transpose()?
+            }
+            Line::Tag("#EXT-X-BYTERANGE", value) => byte_range = Some(value.trim().to_string()),
+            Line::Tag("#EXT-X-MAP", attributes) => {
+                let mut init = Map { url: String::new(), key: None, byte_range: None };
+                for (name, value) in attribute_list(attributes) {
+                    match name {
+                        "URI" => init.url = resolve(&value)?,
+                        "BYTERANGE" => init.byte_range = Some(parse_byte_range(&value, None)?),
+                        _ => {}
+                    }
+                }
+                if init.url.is_empty() {
+                    return Err(anyhow!("#EXT-X-MAP without URI"));
+                }
+                if let Some(range) = init.byte_range {
+                    init.url = byterange::mark(&init.url, range);
+                }
+                init.key = key.as_ref().map(|key: &KeyTag| key.for_segment(sequence));
+                map = Some(init);
+            }
+            Line::Tag("#EXT-X-ENDLIST", _) => media.ended = true,
+            Line::Tag(..) => {}
+            Line::Uri(uri) => {
+                let url = resolve(uri)?;
+                let byte_range = match byte_range.take() {
+                    Some(value) => {
+                        let previous = range_end.as_ref().filter(|(u, _)| *u == url).map(|(_, end)| *end);
+                        let range = parse_byte_range(&value, previous)?;
+                        range_end = Some((url.clone(), range.offset + range.length));
+                        Some(range)
+                    }
+                    None => None,
+                };
+                let segments = &media.segments;
+                let program_date = program_date
+                    .take()
+                    .or_else(|| segments.last().and_then(|s: &Segment| Some(s.program_date? + s.duration)));
+                media.segments.push(Segment {
+                    url: byte_range.map_or_else(|| url.clone(), |range| byterange::mark(&url, range)),
+                    duration: duration.take().or(media.target_duration).unwrap_or(0.0),
+                    title: title.take(),
+                    discontinuity,
+                    program_date,
+                    dateranges: std::mem::take(&mut dateranges),
+                    key: key.as_ref().map(|key: &KeyTag| key.for_segment(sequence)),
+                    byte_range,
+                    map: map.clone(),
+                });
+                discontinuity = false;
+                sequence += 1;
+            }
+        }
+    }
+    Ok(media)
+}
+
+/// `<length>[@<offset>]`; without an offset the range follows `previous`,
+/// the end of the previous range of the same resource.
+fn parse_byte_range(value: &str, previous: Option<u64>) -> Result<ByteRange> {
+    let invalid = || anyhow!("Invalid #EXT-X-BYTERANGE:{}", value);
+    let (length, offset) = match value.split_once('@') {
+        Some((length, offset)) => (length, Some(offset.parse().map_err(|_| invalid())?)),
+        None => (value, None),
+    };
+    let length = length.parse().map_err(|_| invalid())?;
+    let offset = offset.or(previous).ok_or_else(|| anyhow!("#EXT-X-BYTERANGE:{} has no offset to follow", value))?;
+    Ok(ByteRange { length, offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://cdn.example/v/index.m3u8";
+
+    fn media(body: &str) -> Result<Media> {
+        match parse(&format!("#EXTM3U\n#EXT-X-TARGETDURATION:6\n{}", body), URL)? {
+            Playlist::Media(media) => Ok(media),
+            Playlist::Master(_) => panic!("parsed as a master playlist"),
+        }
+    }
+
+    #[test]
+    fn extinf() {
+        let media = media("#EXTINF:4.5,Intro\na.ts\n#EXTINF:3\nb.ts\n").unwrap();
+        let segments: Vec<_> =
+            media.segments.iter().map(|s| (s.url.as_str(), s.duration, s.title.as_deref())).collect();
+        let expected = [("https://cdn.example/v/a.ts", 4.5, Some("Intro")), ("https://cdn.example/v/b.ts", 3.0, None)];
+        assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn malformed_extinf_falls_back_to_the_target_duration() {
+        let media = media("#EXTINF:abc,\na.ts\n#EXTINF:-4,\nb.ts\n#EXTINF:NaN,\nc.ts\n#EXTINF:\nd.ts\n").unwrap();
+        assert!(media.segments.iter().all(|s| s.duration == 6.0), "{:?}", media.segments);
+    }
+
+    #[test]
+    fn byterange_without_an_offset_follows_the_previous_range() {
+        let media = media("#EXT-X-BYTERANGE:100@0\nall.ts\n#EXT-X-BYTERANGE:50\nall.ts\n").unwrap();
+        let ranges: Vec<_> = media.segments.iter().map(|s| s.byte_range.unwrap()).collect();
+        assert_eq!(ranges, [ByteRange { length: 100, offset: 0 }, ByteRange { length: 50, offset: 100 }]);
+    }
+
+    #[test]
+    fn byterange_without_an_offset_needs_a_previous_range() {
+        let error = media("#EXT-X-BYTERANGE:100\nall.ts\n").err().unwrap();
+        assert!(error.to_string().contains("no offset to follow"), "unexpected error: {}", error);
+        // A range of another resource is no offset to follow either.
+        assert!(media("#EXT-X-BYTERANGE:100@0\na.ts\n#EXT-X-BYTERANGE:50\nb.ts\n").is_err());
+    }
+
+    #[test]
+    fn invalid_byterange_is_an_error() {
+        assert!(media("#EXT-X-BYTERANGE:abc@0\nall.ts\n").is_err());
+        assert!(media("#EXT-X-BYTERANGE:100@-1\nall.ts\n").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! `probe` subcommand: what a download would get, without downloading it.
+//! It follows the playlist chain (through a lesson page, if need be) and
+//! prints the variants, the segments of the one `quality` picks, their
+//! duration, encryption, and an estimate of the size. At most one media
+//! request is made, a `HEAD` of the first segment.
+
+use crate::{
+    config::Config,
+    extractor, fetch_playlists,
+    http::HttpFetcher,
+    playlist::{Media, Segment},
+    progress::{clock, size},
+    variant::Variant,
+};
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+/// `probe URL`.
+pub async fn run(fetcher: &Arc<dyn HttpFetcher>, config: &Config, args: &[String]) -> Result<()> {
+    let [url] = args else {
+        return Err(anyhow!("Usage: getcourse-downloader probe <playlist_url>"));
+    };
+    let extraction = extractor::extract(fetcher.as_ref(), url, config.playlist_retry(), false).await?;
+    if let Some(title) = &extraction.title {
+        println!("Title: {}", title);
+    }
+    if extraction.playlist != *url {
+        println!("Playlist: {}", extraction.playlist);
+    }
+    let fetcher = extractor::with_headers(fetcher, extraction.headers);
+    let (master, media) =
+        fetch_playlists(fetcher.as_ref(), &extraction.playlist, config.playlist_retry(), config.quality).await?;
+
+    if let Some((master, picked)) = &master {
+        println!("Variants:");
+        let mut variants: Vec<&Variant> = master.variants.iter().collect();
+        variants.sort_by_key(|v| std::cmp::Reverse(v.bandwidth));
+        for variant in variants {
+            let mark = if variant == picked { "*" } else { " " };
+            println!("  {} {}", mark, variant.label());
+        }
+        for rendition in &master.renditions {
+            let default = if rendition.default { " (default)" } else { "" };
+            println!("  audio: {}{}", rendition.label(), default);
+        }
+        for rendition in &master.subtitles {
+            println!("  subtitles: {}", rendition.label());
+        }
+    }
+    let duration: f64 = media.segments.iter().map(|s| s.duration).sum();
+    println!("Segments: {}", media.segments.len());
+    println!("Duration: {}{}", clock(duration), if media.ended { "" } else { " so far (live, no #EXT-X-ENDLIST)" });
+    println!("Encryption: {}", encryption(&media));
+    if media.segments.first().is_some_and(|s| s.map.is_some()) {
+        println!("Format: fragmented MP4 (#EXT-X-MAP)");
+    }
+    let bandwidth = master.as_ref().map(|(_, picked)| picked.bandwidth);
+    match estimate(fetcher.as_ref(), &media.segments, duration, bandwidth).await {
+        Some(estimate) => println!("Estimated size: {}", estimate),
+        None => println!("Estimated size: unknown"),
+    }
+    Ok(())
+}
+
+fn encryption(media: &Media) -> String {
+    let encrypted = media.segments.iter().filter(|s| s.key.is_some()).count();
+    match encrypted {
+        0 => "none".to_string(),
+        n if n == media.segments.len() => "AES-128".to_string(),
+        n => format!("AES-128 ({} of {} segments)", n, media.segments.len()),
+    }
+}
+
+/// The size of the segments: exact with `#EXT-X-BYTERANGE`, otherwise
+/// scaled up from the first segment's `Content-Length`, or else the variant's
+/// peak `bandwidth` over the `duration`, an upper bound.
+async fn estimate(
+    fetcher: &dyn HttpFetcher,
+    segments: &[Segment],
+    duration: f64,
+    bandwidth: Option<u64>,
+) -> Option<String> {
+    if !segments.is_empty() && segments.iter().all(|s| s.byte_range.is_some()) {
+        let total = segments.iter().filter_map(|s| s.byte_range).map(|range| range.length).sum();
+        return Some(format!("{} (from #EXT-X-BYTERANGE)", size(total)));
+    }
+    let first = segments.first()?;
+    let length = match fetcher.head(&first.url, &[]).await {
+        Ok(resp) if resp.is_success() => resp.length.filter(|&length| length > 0),
+        _ => None,
+    };
+    match (length, bandwidth) {
+        (Some(length), _) if first.duration > 0.0 && duration > 0.0 => {
+            Some(format!("~{} (from the first segment)", size((length as f64 * duration / first.duration) as u64)))
+        }
+        (Some(length), _) => Some(format!("~{} (from the first segment)", size(length * segments.len() as u64))),
+        (None, Some(bandwidth)) if duration > 0.0 => {
+            let total = (bandwidth as f64 / 8.0 * duration) as u64;
+            Some(format!("at most {} (from the variant's bandwidth)", size(total)))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,356 @@
+//! Download progress, in segments, in media time from the `#EXTINF`
+//! durations and in bytes, with an ETA ("segment 412/3120 (38:20 / 1:52:10),
+//! 1.2 GB / ~4.5 GB, 5.3 MB/s, ETA 12:30").
+//!
+//! Segment sizes are not known up front; the total is extrapolated from the
+//! completed segments, by media time when the playlist has durations and by
+//! segment count otherwise, so it firms up as the download goes on.
+//!
+//! With a template (`progress_template`), a line in that format is printed at
+//! most every `progress_interval` seconds instead of one line per segment,
+//! which suits CI logs and cron mail.
+//!
+//! With `progress = "json"`, newline-delimited JSON events go to stderr
+//! instead, for GUIs and scripts that wrap the downloader.
+//!
+//! A finished download ends with a summary: size, time, average and peak
+//! speed, and which segments needed more than one attempt.
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// Shortest stretch of time a peak speed is measured over.
+const PEAK_WINDOW: Duration = Duration::from_secs(1);
+
+/// Segments listed by number in the text summary.
+const LISTED_RETRIES: usize = 10;
+
+/// Placeholders understood in progress templates.
+pub const PLACEHOLDERS: &[&str] =
+    &["percent", "segments", "time", "bytes", "total", "speed", "eta", "elapsed"];
+
+/// How progress is reported (`progress`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Lines for people: one per segment, or per template.
+    Text,
+    /// `start`, `segment_done` and `finished` events, one JSON object per
+    /// line on stderr.
+    Json,
+}
+
+impl ProgressFormat {
+    pub const NAMES: &[&str] = &["text", "json"];
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(ProgressFormat::Text),
+            "json" => Ok(ProgressFormat::Json),
+            other => Err(anyhow!("Unknown progress format {:?}; expected one of {}", other, Self::NAMES.join(", "))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProgressFormat::Text => "text",
+            ProgressFormat::Json => "json",
+        }
+    }
+}
+
+pub struct Progress {
+    durations: Vec<f64>,
+    total_time: f64,
+    done: usize,
+    done_time: f64,
+    bytes: u64,
+    /// Part of `bytes` downloaded by an earlier run.
+    resumed_bytes: u64,
+    started: Instant,
+    template: Option<String>,
+    interval: Duration,
+    last_line: Option<Instant>,
+    format: ProgressFormat,
+    /// Tells this download's lines and events apart from those of others
+    /// running at the same time.
+    label: Option<String>,
+    /// Start of the current peak speed window, and `bytes` then.
+    window: (Instant, u64),
+    /// Fastest speed over a whole window, in bytes per second.
+    peak: f64,
+    /// Segments that needed more than one attempt.
+    retried: BTreeSet<usize>,
+    /// Attempts beyond the first, over all segments.
+    retries: usize,
+}
+
+impl Progress {
+    /// Progress over segments with the given durations in seconds.
+    pub fn new(durations: Vec<f64>) -> Self {
+        let total_time = durations.iter().sum();
+        Self {
+            durations,
+            total_time,
+            done: 0,
+            done_time: 0.0,
+            bytes: 0,
+            resumed_bytes: 0,
+            started: Instant::now(),
+            template: None,
+            interval: Duration::ZERO,
+            last_line: None,
+            format: ProgressFormat::Text,
+            label: None,
+            window: (Instant::now(), 0),
+            peak: 0.0,
+            retried: BTreeSet::new(),
+            retries: 0,
+        }
+    }
+
+    /// Reports with `template` at most every `interval` instead of the
+    /// default line per segment.
+    pub fn with_template(mut self, template: Option<String>, interval: Duration) -> Self {
+        self.template = template;
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_format(mut self, format: ProgressFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Starts text lines with `[label]` and adds it to events as `job`.
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    fn emit(&self, mut event: serde_json::Value) {
+        if let Some(label) = &self.label {
+            event["job"] = label.as_str().into();
+        }
+        eprintln!("{}", event);
+    }
+
+    fn prefix(&self) -> String {
+        self.label.as_ref().map_or(String::new(), |label| format!("[{}] ", label))
+    }
+
+    /// Announces the download, as a `start` event.
+    pub fn start(&self) {
+        if self.format == ProgressFormat::Json {
+            self.emit(json!({"event": "start", "total": self.durations.len(), "duration": self.total_time}));
+        }
+    }
+
+    /// Announces the finished `output` with a summary of the download, as a
+    /// `finished` event.
+    pub fn finish(&self, output: &Path) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        // Downloads shorter than a window have only their average.
+        let peak = self.peak.max(self.speed());
+        if self.format == ProgressFormat::Json {
+            let output = output.display().to_string();
+            self.emit(json!({
+                "event": "finished",
+                "segments": self.done,
+                "bytes": self.bytes,
+                "resumed_bytes": self.resumed_bytes,
+                "elapsed": elapsed,
+                "average_speed": self.speed(),
+                "peak_speed": peak,
+                "retries": self.retries,
+                "retried_segments": self.retried,
+                "output": output,
+            }));
+            return;
+        }
+        let mut summary = format!("{}{} in {}", self.prefix(), size(self.bytes), clock(elapsed));
+        if self.resumed_bytes > 0 {
+            summary += &format!(" ({} from an earlier run)", size(self.resumed_bytes));
+        }
+        summary += &format!(", {}/s average, {}/s peak", size(self.speed() as u64), size(peak as u64));
+        info!("{}", summary);
+        if !self.retried.is_empty() {
+            let mut listed: Vec<String> =
+                self.retried.iter().take(LISTED_RETRIES).map(|i| (i + 1).to_string()).collect();
+            if self.retried.len() > LISTED_RETRIES {
+                listed.push("...".to_string());
+            }
+            info!(
+                "{}{} segment(s) needed more than one attempt ({} retries in all): {}",
+                self.prefix(),
+                self.retried.len(),
+                self.retries,
+                listed.join(", ")
+            );
+        }
+    }
+
+    /// Prints the progress line, if one is due. Events are sent as segments
+    /// finish instead.
+    pub fn report(&mut self) {
+        if self.format == ProgressFormat::Json {
+            return;
+        }
+        let Some(template) = &self.template else {
+            info!("{}Downloaded {}", self.prefix(), self);
+            return;
+        };
+        let finished = self.done == self.durations.len();
+        if !finished && self.last_line.is_some_and(|last| last.elapsed() < self.interval) {
+            return;
+        }
+        self.last_line = Some(Instant::now());
+        info!("{}{}", self.prefix(), self.render(template));
+    }
+
+    /// Fills in the [`PLACEHOLDERS`] of `template`.
+    pub fn render(&self, template: &str) -> String {
+        let mut out = template.to_string();
+        for name in PLACEHOLDERS {
+            let pattern = format!("{{{}}}", name);
+            if out.contains(&pattern) {
+                out = out.replace(&pattern, &self.field(name));
+            }
+        }
+        out
+    }
+
+    fn field(&self, name: &str) -> String {
+        let total = self.total_bytes();
+        let speed = self.speed();
+        match name {
+            "percent" => {
+                let fraction = match self.total_time > 0.0 {
+                    true => self.done_time / self.total_time,
+                    false => self.done as f64 / self.durations.len().max(1) as f64,
+                };
+                format!("{:.1}%", fraction * 100.0)
+            }
+            "segments" => format!("{}/{}", self.done, self.durations.len()),
+            "time" => format!("{}/{}", clock(self.done_time), clock(self.total_time)),
+            "bytes" => size(self.bytes),
+            "total" => total.map_or("?".to_string(), size),
+            "speed" => format!("{}/s", size(speed as u64)),
+            "eta" => match total {
+                Some(total) if speed > 0.0 => clock(total.saturating_sub(self.bytes) as f64 / speed),
+                _ => "?".to_string(),
+            },
+            "elapsed" => clock(self.started.elapsed().as_secs_f64()),
+            _ => String::new(),
+        }
+    }
+
+    /// Records segment `index` as downloaded with `bytes` bytes, in
+    /// `attempts` attempts.
+    pub fn done(&mut self, index: usize, bytes: u64, attempts: usize) {
+        if attempts > 1 {
+            self.retried.insert(index);
+            self.retries += attempts - 1;
+        }
+        self.record(index, bytes, false);
+        let (since, before) = self.window;
+        if since.elapsed() >= PEAK_WINDOW {
+            self.peak = self.peak.max((self.bytes - before) as f64 / since.elapsed().as_secs_f64());
+            self.window = (Instant::now(), self.bytes);
+        }
+    }
+
+    /// Counts segment `index`, downloaded by an earlier run, as done without
+    /// counting it towards the speed.
+    pub fn resumed(&mut self, index: usize, bytes: u64) {
+        self.record(index, bytes, true);
+        self.resumed_bytes += bytes;
+        self.window.1 = self.bytes;
+    }
+
+    fn record(&mut self, index: usize, bytes: u64, resumed: bool) {
+        self.done += 1;
+        self.done_time += self.durations.get(index).copied().unwrap_or(0.0);
+        self.bytes += bytes;
+        if self.format == ProgressFormat::Json {
+            let event = json!({
+                "event": "segment_done",
+                "index": index,
+                "total": self.durations.len(),
+                "bytes": bytes,
+                "done": self.done,
+                "downloaded": self.bytes,
+                "resumed": resumed,
+            });
+            self.emit(event);
+        }
+    }
+
+    /// Estimated size of the whole download.
+    fn total_bytes(&self) -> Option<u64> {
+        let fraction = if self.total_time > 0.0 && self.done_time > 0.0 {
+            self.done_time / self.total_time
+        } else if self.done > 0 {
+            self.done as f64 / self.durations.len() as f64
+        } else {
+            return None;
+        };
+        Some((self.bytes as f64 / fraction) as u64)
+    }
+
+    /// Bytes per second so far.
+    fn speed(&self) -> f64 {
+        (self.bytes - self.resumed_bytes) as f64 / self.started.elapsed().as_secs_f64().max(0.001)
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "segment {}/{}", self.done, self.durations.len())?;
+        // Playlists without durations only get the segment count.
+        if self.total_time > 0.0 {
+            write!(f, " ({} / {})", clock(self.done_time), clock(self.total_time))?;
+        }
+        let Some(total) = self.total_bytes() else {
+            return Ok(());
+        };
+        let speed = self.speed();
+        write!(f, ", {} / ", size(self.bytes))?;
+        if self.done < self.durations.len() {
+            write!(f, "~")?;
+        }
+        write!(f, "{}, {}/s", size(total), size(speed as u64))?;
+        if self.done < self.durations.len() && speed > 0.0 {
+            write!(f, ", ETA {}", clock(total.saturating_sub(self.bytes) as f64 / speed))?;
+        }
+        Ok(())
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour on.
+pub fn clock(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 { format!("{}:{:02}:{:02}", h, m, s) } else { format!("{}:{:02}", m, s) }
+}
+
+/// Decimal units, as download speeds are usually given.
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
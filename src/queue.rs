@@ -0,0 +1,423 @@
+//! `queue` subcommand: downloads put aside to be run later, say overnight.
+//! `queue add URL OUTPUT` records one in `queue.json` next to the config
+//! file (or `GETCOURSE_QUEUE`), `queue list` shows them, and `queue run`
+//! downloads those not done yet, `--jobs N` at a time like `batch`. Each is
+//! marked done or failed as it ends, and entries added while the queue runs
+//! are picked up before it stops. One stopped by Ctrl+C or the deadline
+//! stays pending and resumes from its work directory on the next run, and a
+//! failed one is tried again.
+
+use crate::{
+    archive::{self, Archive},
+    config::{self, Config},
+    download_to,
+    exit::Failure,
+    fetcher,
+    output::{Fsync, OutputTarget},
+    DownloadOptions, Existing, STDOUT,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::{stream, StreamExt};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+const USAGE: &str =
+    "Usage: getcourse-downloader queue add <url> <output> | list | run [--jobs N] | remove <id>... | clear";
+
+/// `GETCOURSE_QUEUE`, or `queue.json` in the config directory.
+pub fn default_path() -> Option<PathBuf> {
+    match env::var_os("GETCOURSE_QUEUE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(config::default_path()?.with_file_name("queue.json")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Done,
+    Failed,
+}
+
+impl Status {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "pending" => Some(Status::Pending),
+            "done" => Some(Status::Done),
+            "failed" => Some(Status::Failed),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Done => "done",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+struct Entry {
+    id: u64,
+    url: String,
+    /// Absolute for local outputs, so that `queue run` may start anywhere.
+    output: String,
+    /// Seconds since the epoch.
+    added: u64,
+    status: Status,
+    /// Runs that have started it.
+    attempts: u64,
+    /// Why the last attempt failed.
+    error: Option<String>,
+}
+
+impl Entry {
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            id: value.get("id")?.as_u64()?,
+            url: value.get("url")?.as_str()?.to_string(),
+            output: value.get("output")?.as_str()?.to_string(),
+            added: value.get("added").and_then(Value::as_u64).unwrap_or_default(),
+            status: Status::parse(value.get("status")?.as_str()?)?,
+            attempts: value.get("attempts").and_then(Value::as_u64).unwrap_or_default(),
+            error: value.get("error").and_then(Value::as_str).map(String::from),
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        map.insert("id".into(), json!(self.id));
+        map.insert("url".into(), json!(self.url));
+        map.insert("output".into(), json!(self.output));
+        map.insert("added".into(), json!(self.added));
+        map.insert("status".into(), json!(self.status.name()));
+        map.insert("attempts".into(), json!(self.attempts));
+        if let Some(error) = &self.error {
+            map.insert("error".into(), json!(error));
+        }
+        Value::Object(map)
+    }
+}
+
+/// The entries of the queue file, oldest first.
+#[derive(Default)]
+struct Queue(Vec<Entry>);
+
+impl Queue {
+    /// Reads `path`; a missing file is an empty queue.
+    fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read the queue {}", path.display())),
+        };
+        let entries = match serde_json::from_str(&text) {
+            Ok(Value::Array(items)) => items.iter().map(Entry::from_json).collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        entries.map(Self).ok_or_else(|| anyhow!("{} is not a queue of downloads", path.display()))
+    }
+
+    /// Writes the queue to `path` through a temporary file, so that a crash
+    /// leaves the old one rather than half of the new. It is readable by the
+    /// owner only, since the URLs may carry access tokens.
+    fn save(&self, path: &Path) -> Result<()> {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        let saved = (|| {
+            let mut file = tempfile::Builder::new().prefix(".queue").tempfile_in(dir)?;
+            let items: Vec<Value> = self.0.iter().map(Entry::to_json).collect();
+            serde_json::to_writer_pretty(&mut file, &items)?;
+            writeln!(file)?;
+            file.persist(path)?;
+            anyhow::Ok(())
+        })();
+        saved.with_context(|| format!("Cannot write the queue {}", path.display()))
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut Entry> {
+        self.0.iter_mut().find(|entry| entry.id == id)
+    }
+}
+
+/// Applies `change` to the queue at `path` as it is on disk now, so that
+/// entries added or removed by another `queue` command meanwhile are kept.
+/// The [`Lock`] keeps other changes, of this process or another, out until
+/// it is written.
+async fn modify(path: &Path, change: impl FnOnce(&mut Queue)) -> Result<()> {
+    let _lock = Lock::acquire(path).await?;
+    let mut queue = Queue::load(path)?;
+    change(&mut queue);
+    queue.save(path)
+}
+
+/// A lock file left this long is taken to be from a command that crashed
+/// holding it; holding one takes milliseconds.
+const STALE_LOCK: Duration = Duration::from_secs(10);
+
+/// `queue.json.lock` next to the queue, created exclusively by the command
+/// that is changing the queue and removed when it is done.
+struct Lock(PathBuf);
+
+impl Lock {
+    /// Waits until the lock file of the queue at `path` can be created.
+    async fn acquire(path: &Path) -> Result<Self> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        let lock = path.with_file_name(name);
+        if let Some(dir) = lock.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        }
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(Self(lock)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if !(is_stale(&lock) && take_over(&lock)) {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                    }
+                }
+                Err(e) => return Err(e).with_context(|| format!("Cannot lock the queue with {}", lock.display())),
+            }
+        }
+    }
+}
+
+fn is_stale(lock: &Path) -> bool {
+    let age = fs::metadata(lock).and_then(|meta| meta.modified()).ok().and_then(|t| t.elapsed().ok());
+    age.is_some_and(|age| age > STALE_LOCK)
+}
+
+/// Removes the stale lock file `lock`. It is first renamed to a name of this
+/// process's own, which only one of several processes doing the same can do,
+/// and checked again there: a fresh lock, created by another process since
+/// `lock` was found stale, is put back instead.
+fn take_over(lock: &Path) -> bool {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.subsec_nanos());
+    let mut name = lock.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".stale-{}-{}", std::process::id(), nanos));
+    let taken = lock.with_file_name(name);
+    if fs::rename(lock, &taken).is_err() {
+        return false;
+    }
+    if is_stale(&taken) {
+        warn!("Warning: removed the stale lock {}", lock.display());
+    } else {
+        // Unless yet another process has locked the queue meanwhile.
+        let _ = fs::hard_link(&taken, lock);
+    }
+    let _ = fs::remove_file(&taken);
+    true
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// `exec` is the global `--exec` hook, run after every download; `existing`
+/// says what to do about outputs that exist already.
+pub async fn run(config: &Config, exec: Option<&str>, existing: Existing, args: &[String]) -> Result<()> {
+    let path = default_path().ok_or_else(|| anyhow!("No config directory to keep the queue in"))?;
+    match args.first().map(String::as_str) {
+        Some("add") => add(config, &path, &args[1..]).await,
+        Some("list") if args.len() == 1 => list(&path),
+        Some("run") => process(config, &path, exec, existing, &args[1..]).await,
+        Some("remove") => remove(&path, &args[1..]).await,
+        Some("clear") if args.len() == 1 => clear(&path).await,
+        _ => Err(Failure::Usage.of(anyhow!(USAGE))),
+    }
+}
+
+async fn add(config: &Config, path: &Path, args: &[String]) -> Result<()> {
+    let [url, output] = args else {
+        return Err(Failure::Usage.of(anyhow!(USAGE)));
+    };
+    if output == STDOUT {
+        return Err(Failure::Usage.of(anyhow!("A queued download needs an output file, not - (stdout)")));
+    }
+    // Placed as a download started here and now would be.
+    let output = match OutputTarget::parse(output)? {
+        OutputTarget::Local(file) if file.is_relative() => {
+            let dir = env::current_dir().context("Cannot tell the current directory")?;
+            let dir = match &config.output_dir {
+                Some(output_dir) => {
+                    let dir = dir.join(output_dir);
+                    fs::create_dir_all(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+                    dir
+                }
+                None => dir,
+            };
+            dir.join(file).display().to_string()
+        }
+        _ => output.clone(),
+    };
+    let added = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+    let mut id = 0;
+    modify(path, |queue| {
+        id = queue.0.iter().map(|entry| entry.id).max().unwrap_or_default() + 1;
+        let (url, output) = (url.clone(), output.clone());
+        queue.0.push(Entry { id, url, output, added, status: Status::Pending, attempts: 0, error: None });
+    }).await?;
+    info!("Queued #{}: {} -> {}", id, url, output);
+    Ok(())
+}
+
+fn list(path: &Path) -> Result<()> {
+    let queue = Queue::load(path)?;
+    if queue.0.is_empty() {
+        info!("The queue is empty ({})", path.display());
+        return Ok(());
+    }
+    for entry in &queue.0 {
+        println!("#{:<4} {:<8} {} -> {}", entry.id, entry.status.name(), entry.url, entry.output);
+        if let (Status::Failed, Some(error)) = (entry.status, &entry.error) {
+            println!("      after {} attempt(s): {}", entry.attempts, error);
+        }
+    }
+    let left = queue.0.iter().filter(|entry| entry.status != Status::Done).count();
+    info!("{} of {} not done yet", left, queue.0.len());
+    Ok(())
+}
+
+async fn remove(path: &Path, args: &[String]) -> Result<()> {
+    if args.is_empty() {
+        return Err(Failure::Usage.of(anyhow!(USAGE)));
+    }
+    let ids = args
+        .iter()
+        .map(|id| id.trim_start_matches('#').parse().map_err(|_| Failure::Usage.of(anyhow!("Invalid queue ID {}", id))))
+        .collect::<Result<HashSet<u64>>>()?;
+    let mut removed = 0;
+    modify(path, |queue| {
+        let before = queue.0.len();
+        queue.0.retain(|entry| !ids.contains(&entry.id));
+        removed = before - queue.0.len();
+    }).await?;
+    info!("Removed {} of {} queued download(s)", removed, ids.len());
+    Ok(())
+}
+
+/// Drops the entries that are done.
+async fn clear(path: &Path) -> Result<()> {
+    let mut cleared = 0;
+    modify(path, |queue| {
+        let before = queue.0.len();
+        queue.0.retain(|entry| entry.status != Status::Done);
+        cleared = before - queue.0.len();
+    }).await?;
+    info!("Cleared {} finished download(s) from the queue", cleared);
+    Ok(())
+}
+
+fn parallel(args: &[String]) -> Result<usize> {
+    match args {
+        [] => Ok(1),
+        // `--parallel` is the spelling `batch` also takes.
+        [name, value] if name == "--jobs" || name == "--parallel" => value
+            .parse()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| Failure::Usage.of(anyhow!("Invalid {} '{}'; expected a positive number", name, value))),
+        [other, ..] => Err(Failure::Usage.of(anyhow!("Unknown queue run option: {}", other))),
+    }
+}
+
+/// `queue run`: rounds of the entries not done yet, until a round finds none
+/// it has not tried.
+async fn process(config: &Config, path: &Path, exec: Option<&str>, existing: Existing, args: &[String]) -> Result<()> {
+    let parallel = parallel(args)?;
+    let archive = match &config.download_archive {
+        Some(path) => Some(Archive::open(path, config.fsync != Fsync::Off)?),
+        None => None,
+    };
+    let fetcher = fetcher(config)?;
+    let mut tried = HashSet::new();
+    let mut results = Vec::new();
+    loop {
+        let round: Vec<(u64, String, String)> = Queue::load(path)?
+            .0
+            .into_iter()
+            .filter(|entry| entry.status != Status::Done && !tried.contains(&entry.id))
+            .map(|entry| (entry.id, entry.url, entry.output))
+            .collect();
+        if round.is_empty() {
+            break;
+        }
+        info!("{} queued download(s) to run", round.len());
+        tried.extend(round.iter().map(|(id, _, _)| *id));
+        let done: Vec<_> = stream::iter(round)
+            .map(|(id, url, output)| {
+                let (fetcher, archive) = (&fetcher, &archive);
+                let label = (parallel > 1).then(|| format!("#{}", id));
+                let download_opts = DownloadOptions { label, existing, ..Default::default() };
+                async move {
+                    let archived = archive.as_ref().is_some_and(|archive| archive.contains(&archive::url_key(&url)));
+                    let result = match archived {
+                        true => {
+                            info!("#{} {} is in the download archive; skipping it", id, url);
+                            Ok(())
+                        }
+                        false => {
+                            info!("#{} {} -> {}", id, url, output);
+                            let started = |queue: &mut Queue| {
+                                queue.get_mut(id).into_iter().for_each(|entry| entry.attempts += 1)
+                            };
+                            modify(path, started).await?;
+                            let mut result = download_to(fetcher, config, &download_opts, &url, &output, exec).await;
+                            if let (Ok(()), Some(archive)) = (&result, archive) {
+                                result = archive.add(&archive::url_key(&url));
+                            }
+                            result
+                        }
+                    };
+                    if let Err(e) = &result {
+                        warn!("#{} {} failed: {:#}", id, output, e);
+                    }
+                    let error = result.as_ref().err().map(|e| format!("{:#}", e));
+                    modify(path, |queue| {
+                        if let Some(entry) = queue.get_mut(id) {
+                            entry.status = if error.is_none() { Status::Done } else { Status::Failed };
+                            entry.error = error;
+                        }
+                    }).await?;
+                    anyhow::Ok((id, output, result))
+                }
+            })
+            .buffer_unordered(parallel)
+            .collect()
+            .await;
+        for item in done {
+            results.push(item?);
+        }
+    }
+    if results.is_empty() {
+        info!("Nothing to do: every queued download is done");
+        return Ok(());
+    }
+
+    results.sort_by_key(|(id, _, _)| *id);
+    info!("\nQueue summary:");
+    let failed = results.iter().filter(|(_, _, result)| result.is_err()).count();
+    for (id, output, result) in &results {
+        match result {
+            Ok(()) => info!("  ok      #{} {}", id, output),
+            Err(e) => info!("  FAILED  #{} {}: {:#}", id, output, e),
+        }
+    }
+    match failed {
+        0 => {
+            info!("All {} queued downloads finished.", results.len());
+            Ok(())
+        }
+        n => Err(anyhow!("{} of {} queued downloads failed; `queue run` tries them again", n, results.len())),
+    }
+}
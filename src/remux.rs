@@ -0,0 +1,442 @@
+//! Output containers. HLS segments are MPEG-TS, so `.ts` output is a plain
+//! concatenation; other containers are produced by remuxing with ffmpeg
+//! (stream copy, no re-encoding unless a [`Recode`] profile is chosen).
+
+use crate::media::{Codec, StreamInfo};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Ts,
+    Mp4,
+    Mkv,
+    /// Audio only, AAC in MP4.
+    M4a,
+}
+
+impl Container {
+    /// Picks the container from the output file extension. Unknown or missing
+    /// extensions get the raw stream, as before containers were supported
+    /// (see [`check_output`] for the warning).
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("mp4" | "m4v" | "mov") => Container::Mp4,
+            Some("mkv") => Container::Mkv,
+            Some("m4a") => Container::M4a,
+            _ => Container::Ts,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Container::Ts => "MPEG-TS",
+            Container::Mp4 => "MP4",
+            Container::Mkv => "Matroska",
+            Container::M4a => "M4A",
+        }
+    }
+
+    pub fn needs_remux(self) -> bool {
+        self != Container::Ts
+    }
+
+    /// The usual file extension.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Ts => "ts",
+            Container::Mp4 => "mp4",
+            Container::Mkv => "mkv",
+            Container::M4a => "m4a",
+        }
+    }
+
+    /// A `remux` value: `mp4`, `mkv`, or empty for none.
+    pub fn parse_remux(name: &str) -> Result<Option<Self>> {
+        match name {
+            "" => Ok(None),
+            "mp4" => Ok(Some(Container::Mp4)),
+            "mkv" => Ok(Some(Container::Mkv)),
+            other => Err(anyhow!("Unknown remux container {:?}; expected mp4 or mkv", other)),
+        }
+    }
+}
+
+/// Re-encoding presets for archives where the source bitrate is more than
+/// needed; lecture video compresses well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recode {
+    /// H.264 at up to 1080p, AAC audio; plays everywhere.
+    H264,
+    /// H.265 at up to 720p, lower-rate AAC audio; roughly a quarter of the size.
+    H265,
+    /// Audio only, Opus at speech-friendly bitrate.
+    Opus,
+}
+
+impl Recode {
+    pub const NAMES: &[&str] = &["h264-1080p", "h265-compact", "audio-opus"];
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "h264-1080p" => Ok(Recode::H264),
+            "h265-compact" => Ok(Recode::H265),
+            "audio-opus" => Ok(Recode::Opus),
+            other => Err(anyhow!("Unknown recode profile {:?}; expected one of {}", other, Self::NAMES.join(", "))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Recode::H264 => "h264-1080p",
+            Recode::H265 => "h265-compact",
+            Recode::Opus => "audio-opus",
+        }
+    }
+
+    /// ffmpeg options for the video stream; `None` drops video.
+    fn video_args(self) -> Option<&'static [&'static str]> {
+        match self {
+            // Never upscale; -2 keeps the width even as the encoders require.
+            Recode::H264 => Some(&[
+                "-c:v", "libx264", "-preset", "medium", "-crf", "23", "-pix_fmt", "yuv420p",
+                "-vf", "scale=-2:'min(1080,ih)'",
+            ]),
+            Recode::H265 => Some(&[
+                "-c:v", "libx265", "-preset", "medium", "-crf", "28", "-pix_fmt", "yuv420p",
+                "-vf", "scale=-2:'min(720,ih)'",
+            ]),
+            Recode::Opus => None,
+        }
+    }
+
+    fn audio_args(self) -> &'static [&'static str] {
+        match self {
+            Recode::H264 => &["-c:a", "aac", "-b:a", "128k"],
+            Recode::H265 => &["-c:a", "aac", "-b:a", "96k"],
+            Recode::Opus => &["-c:a", "libopus", "-b:a", "64k"],
+        }
+    }
+}
+
+/// Extensions that are fine for raw MPEG-TS data.
+const TS_EXTENSIONS: &[&str] = &["ts", "m2ts", "mts"];
+
+/// Decides before downloading what actually gets written to `path`, so that a
+/// missing ffmpeg does not surface only after an hour-long download, and never
+/// silently produces e.g. an `.mp4` file that is really MPEG-TS. With
+/// `allow_raw`, such files are written anyway with a warning. Re-encoding
+/// always needs ffmpeg.
+pub fn check_output(path: &Path, allow_raw: bool, recode: Option<Recode>) -> Result<Container> {
+    let container = Container::from_path(path);
+    if let Some(recode) = recode {
+        if container == Container::M4a && recode == Recode::Opus {
+            return Err(anyhow!("M4A cannot hold Opus audio; use .mkv, .mp4 or .ts with --recode audio-opus"));
+        }
+        require_ffmpeg(&format!("--recode {}", recode.name()))?;
+        return Ok(container);
+    }
+    if container.needs_remux() && !ffmpeg_available() {
+        if !allow_raw {
+            return Err(anyhow!(
+                "{} needs ffmpeg to be written as {}, but ffmpeg was not found in PATH. \
+                 Install ffmpeg, use a .ts file name, or pass --allow-raw to write MPEG-TS data anyway",
+                path.display(),
+                container.name()
+            ));
+        }
+        warn_raw(path);
+        return Ok(Container::Ts);
+    }
+    let ext = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    if container == Container::Ts && ext.is_some_and(|ext| !TS_EXTENSIONS.contains(&ext.as_str())) {
+        warn_raw(path);
+    }
+    Ok(container)
+}
+
+/// Whether `path` names an MPEG-TS file, or has no extension at all.
+pub fn ts_name(path: &Path) -> bool {
+    path.extension().is_none_or(|ext| TS_EXTENSIONS.contains(&ext.to_string_lossy().to_ascii_lowercase().as_str()))
+}
+
+fn warn_raw(path: &Path) {
+    warn!("WARNING: {} will contain raw MPEG-TS data regardless of its extension;", path.display());
+    warn!("WARNING: some players and editors will refuse it. Rename it to .ts to avoid confusion.");
+}
+
+/// Writes the CEA-608/708 closed captions carried in the video stream of
+/// `input` (in H.264/H.265 SEI or MPEG-2 user data) to `srt`, cut like the
+/// video with `trim`. Returns `false`, leaving no file, if there were none.
+pub fn extract_captions(input: &Path, srt: &Path, trim: Option<Trim>) -> Result<bool> {
+    // The lavfi `movie` source exposes the captions as a subtitle stream.
+    let input = input.canonicalize()?;
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y", "-f", "lavfi", "-i"]);
+    cmd.arg(format!("movie={}[out0+subcc]", lavfi_escape(&input.to_string_lossy())));
+    if let Some(trim) = trim.filter(|t| t.start > 0.0) {
+        cmd.arg("-ss").arg(format!("{:.3}", trim.start));
+    }
+    if let Some(duration) = trim.and_then(|t| t.duration) {
+        cmd.arg("-t").arg(format!("{:.3}", duration));
+    }
+    cmd.args(["-map", "0:s?"]).arg(srt);
+    let status = cmd.status().context("Failed to run ffmpeg")?;
+    if !status.success() {
+        return Err(anyhow!("ffmpeg exited with {}", status));
+    }
+    if std::fs::metadata(srt).is_ok_and(|m| m.len() == 0) {
+        std::fs::remove_file(srt)?;
+        return Ok(false);
+    }
+    Ok(srt.exists())
+}
+
+/// Escapes a filter option value and then the filter description, the two
+/// levels a path inside a `-f lavfi` graph goes through.
+fn lavfi_escape(value: &str) -> String {
+    let option = escape(value, &['\\', '\'', ':']);
+    escape(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+/// `value` with a backslash before each of the `special` characters.
+fn escape(value: &str, special: &[char]) -> String {
+    value.chars().fold(String::new(), |mut out, c| {
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+        out
+    })
+}
+
+/// Fails early if `what` cannot work because ffmpeg is missing.
+pub fn require_ffmpeg(what: &str) -> Result<()> {
+    if !ffmpeg_available() {
+        return Err(anyhow!("{} needs ffmpeg, but it was not found in PATH", what));
+    }
+    Ok(())
+}
+
+fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Checks the streams found in the first segment against the container.
+/// Without stream information (e.g. encrypted segments) everything passes.
+pub fn check_streams(container: Container, recode: Option<Recode>, info: Option<&StreamInfo>) -> Result<()> {
+    let Some(info) = info else {
+        return Ok(());
+    };
+    if recode == Some(Recode::Opus) && !info.has_audio() {
+        return Err(anyhow!("The stream has no audio track to encode with --recode audio-opus"));
+    }
+    if container == Container::M4a {
+        if !info.has_audio() {
+            return Err(anyhow!("The stream has no audio track, so it cannot be saved as M4A"));
+        }
+        // Re-encoding produces AAC anyway.
+        if recode.is_none() && !info.has(Codec::Aac) {
+            return Err(anyhow!("M4A needs AAC audio, but the stream has {}; use .mkv instead", info));
+        }
+    }
+    Ok(())
+}
+
+/// A cut within the remuxed input, in seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Trim {
+    pub start: f64,
+    pub duration: Option<f64>,
+}
+
+/// An extra audio input, muxed in as a selectable track.
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    pub path: PathBuf,
+    /// Cut of this input; its segments do not line up with the video's.
+    pub trim: Option<Trim>,
+    pub title: String,
+    pub language: Option<String>,
+    pub default: bool,
+}
+
+/// What the output says about itself: global tags and chapter markers, which
+/// media libraries and players show.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// The lesson's title.
+    pub title: Option<String>,
+    /// The course it belongs to, tagged as the album.
+    pub album: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+/// A chapter, in seconds of the output.
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: String,
+}
+
+impl Metadata {
+    fn is_empty(&self) -> bool {
+        self.title.is_none() && self.album.is_none() && self.chapters.is_empty()
+    }
+
+    /// The tags and chapters as an ffmpeg metadata file (`FFMETADATA1`).
+    fn ffmetadata(&self) -> String {
+        let escape = |value: &str| escape(value, &['=', ';', '#', '\\', '\n']);
+        let mut text = String::from(";FFMETADATA1\n");
+        for (key, value) in [("title", &self.title), ("album", &self.album)] {
+            if let Some(value) = value {
+                text.push_str(&format!("{}={}\n", key, escape(value)));
+            }
+        }
+        for chapter in &self.chapters {
+            text.push_str(&format!(
+                "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                (chapter.start * 1000.0).round() as u64,
+                (chapter.end * 1000.0).round() as u64,
+                escape(&chapter.title)
+            ));
+        }
+        text
+    }
+}
+
+/// Remuxes the concatenated transport stream `input` into `output`, adjusting
+/// the ffmpeg options to the streams in `info` when known.
+///
+/// With `trim`, the input is seeked before decoding; in stream-copy mode ffmpeg
+/// then starts at the preceding keyframe and the MP4 muxer hides the frames
+/// before the cut point with an edit list, so the result starts exactly there.
+/// When re-encoding, the cut is exact in every container.
+///
+/// With `recode`, video and audio are re-encoded with the profile's settings;
+/// any other streams are still copied.
+///
+/// With `audio` tracks, only the video of `input` is kept and the tracks
+/// replace its audio.
+///
+/// `metadata` is written into every container but MPEG-TS.
+#[allow(clippy::too_many_arguments)]
+pub fn remux(
+    input: &Path,
+    output: &Path,
+    container: Container,
+    info: Option<&StreamInfo>,
+    trim: Option<Trim>,
+    recode: Option<Recode>,
+    audio: &[AudioTrack],
+    metadata: &Metadata,
+) -> Result<()> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"]);
+    for (path, trim) in [(input, trim)].into_iter().chain(audio.iter().map(|a| (a.path.as_path(), a.trim))) {
+        if let Some(trim) = trim.filter(|t| t.start > 0.0) {
+            cmd.arg("-ss").arg(format!("{:.3}", trim.start));
+        }
+        cmd.arg("-i").arg(path);
+    }
+    // Removed when the command is done with it.
+    let mut tags = None;
+    if container != Container::Ts && !metadata.is_empty() {
+        let dir = input.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut file = tempfile::Builder::new().suffix(".ffmeta").tempfile_in(dir)?;
+        file.write_all(metadata.ffmetadata().as_bytes()).context("Failed to write the metadata for ffmpeg")?;
+        cmd.args(["-f", "ffmetadata", "-i"]).arg(file.path());
+        let index = (1 + audio.len()).to_string();
+        cmd.args(["-map_metadata", &index, "-map_chapters", &index]);
+        tags = Some(file);
+    }
+    if let Some(duration) = trim.and_then(|t| t.duration) {
+        cmd.arg("-t").arg(format!("{:.3}", duration));
+    }
+    match container {
+        _ if !audio.is_empty() => {
+            if container != Container::M4a {
+                cmd.args(["-map", "0:v?"]);
+            }
+            for input in 1..=audio.len() {
+                cmd.arg("-map").arg(format!("{}:a", input));
+            }
+            if container == Container::Ts {
+                cmd.args(["-f", "mpegts"]);
+            }
+        }
+        Container::Ts => {
+            cmd.args(["-map", "0", "-f", "mpegts"]);
+        }
+        Container::Mp4 => {
+            cmd.args(["-map", "0:v?", "-map", "0:a?"]);
+        }
+        Container::Mkv => {
+            cmd.args(["-map", "0"]);
+        }
+        Container::M4a => {
+            cmd.args(["-map", "0:a", "-vn"]);
+        }
+    }
+    cmd.args(["-c", "copy"]);
+    match recode {
+        Some(recode) => {
+            match recode.video_args() {
+                Some(args) if container != Container::M4a => cmd.args(args),
+                _ => cmd.arg("-vn"),
+            };
+            cmd.args(recode.audio_args());
+        }
+        // ADTS AAC from TS must be converted to raw AAC for the MP4 family.
+        None if matches!(container, Container::Mp4 | Container::M4a) => {
+            cmd.args(["-bsf:a", "aac_adtstoasc"]);
+        }
+        None => {}
+    }
+    // QuickTime and Apple devices only play HEVC in MP4 when tagged hvc1.
+    let hevc = match recode {
+        Some(recode) => recode == Recode::H265,
+        None => info.is_some_and(|i| i.has(Codec::Hevc)),
+    };
+    if container == Container::Mp4 && hevc {
+        cmd.args(["-tag:v", "hvc1"]);
+    }
+    // Index (moov) before the media data, so playback from a NAS or cloud
+    // drive starts without fetching the end of the file first.
+    if matches!(container, Container::Mp4 | Container::M4a) {
+        cmd.args(["-movflags", "+faststart"]);
+    }
+    for (i, track) in audio.iter().enumerate() {
+        cmd.arg(format!("-metadata:s:a:{}", i)).arg(format!("title={}", track.title));
+        if let Some(language) = &track.language {
+            cmd.arg(format!("-metadata:s:a:{}", i)).arg(format!("language={}", language));
+        }
+        cmd.arg(format!("-disposition:a:{}", i)).arg(if track.default { "default" } else { "0" });
+    }
+    cmd.arg(output);
+    match recode {
+        Some(recode) => info!("Re-encoding to {} ({}) with ffmpeg", container.name(), recode.name()),
+        None => info!("Remuxing to {} with ffmpeg", container.name()),
+    }
+    let status = cmd.status().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => anyhow!("ffmpeg not found in PATH; it is needed to write {} files", container.name()),
+        _ => anyhow!(e).context("Failed to run ffmpeg"),
+    })?;
+    drop(tags);
+    if !status.success() {
+        return Err(anyhow!("ffmpeg exited with {}", status));
+    }
+    Ok(())
+}
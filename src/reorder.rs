@@ -0,0 +1,196 @@
+//! In-order output from out-of-order segment downloads. A completed segment
+//! is appended as soon as every segment before it has been; those that finish
+//! early wait in memory up to a limit, and in a spill directory beyond it.
+//! The writing happens on a thread of its own (see [`Reorder::spawn`]), so
+//! slow disks never hold up the downloads feeding it.
+
+use crate::Rebase;
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+use tempfile::{tempdir_in, TempDir};
+use tokio::sync::watch;
+
+/// A segment that arrived before its turn.
+enum Pending {
+    Memory(Bytes),
+    Disk(PathBuf),
+}
+
+pub struct Reorder<W> {
+    out: W,
+    /// Index of the next segment to append.
+    next: usize,
+    pending: BTreeMap<usize, Pending>,
+    /// Bytes of `pending` held in memory.
+    memory: u64,
+    max_memory: u64,
+    /// Where the spill directory is created, on the first spill.
+    spill_in: PathBuf,
+    spill: Option<TempDir>,
+    /// Applied to each segment as it is appended.
+    rebase: Option<Rebase>,
+}
+
+impl<W: Write> Reorder<W> {
+    /// Segments beyond `max_memory` bytes are spilled to a directory in
+    /// `spill_in`.
+    pub fn new(out: W, max_memory: u64, spill_in: PathBuf) -> Self {
+        Self { out, next: 0, pending: BTreeMap::new(), memory: 0, max_memory, spill_in, spill: None, rebase: None }
+    }
+
+    /// Rebases timestamps across discontinuities on the way out, which needs
+    /// the segments in order and so can only happen here.
+    pub fn rebasing(mut self, rebase: Rebase) -> Self {
+        self.rebase = Some(rebase);
+        self
+    }
+
+    /// Takes segment `index`, appending it and any waiting successors if its
+    /// turn has come, and keeping it until then otherwise.
+    fn push(&mut self, index: usize, data: Bytes) -> Result<()> {
+        if index != self.next {
+            let len = data.len() as u64;
+            let pending = if self.memory + len <= self.max_memory {
+                self.memory += len;
+                Pending::Memory(data)
+            } else {
+                let dir = match &self.spill {
+                    Some(dir) => dir,
+                    None => {
+                        let spill = fs::create_dir_all(&self.spill_in)
+                            .and_then(|_| tempdir_in(&self.spill_in))
+                            .context("Failed to create a spill directory")?;
+                        self.spill.insert(spill)
+                    }
+                };
+                let path = dir.path().join(format!("{:05}.ts", index));
+                fs::write(&path, &data).context("Failed to spill a segment to disk")?;
+                Pending::Disk(path)
+            };
+            self.pending.insert(index, pending);
+            return Ok(());
+        }
+        self.append(data)?;
+        while let Some(pending) = self.pending.remove(&self.next) {
+            let data = match pending {
+                Pending::Memory(data) => {
+                    self.memory -= data.len() as u64;
+                    data
+                }
+                Pending::Disk(path) => {
+                    let data = fs::read(&path).context("Failed to read a spilled segment")?;
+                    let _ = fs::remove_file(&path);
+                    Bytes::from(data)
+                }
+            };
+            self.append(data)?;
+        }
+        Ok(())
+    }
+
+    /// Writes segment `self.next`.
+    fn append(&mut self, data: Bytes) -> Result<()> {
+        match &mut self.rebase {
+            Some(rebase) => {
+                let mut data = data.to_vec();
+                rebase.apply(self.next, &mut data);
+                self.out.write_all(&data)
+            }
+            None => self.out.write_all(&data),
+        }
+        .context("Failed to write the output")?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Returns the writer once every segment has been appended.
+    fn finish(self) -> Result<W> {
+        match self.pending.keys().next() {
+            Some(_) => Err(anyhow!("Segment {} never arrived; the output is incomplete", self.next + 1)),
+            None => Ok(self.out),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Reorder<W> {
+    /// Moves the writer to its own thread.
+    pub fn spawn(mut self) -> ReorderThread<W> {
+        let (sender, receiver) = mpsc::channel::<(usize, Bytes)>();
+        let (progress, next) = watch::channel(self.next);
+        let thread = thread::spawn(move || {
+            for (index, data) in receiver {
+                self.push(index, data)?;
+                progress.send_replace(self.next);
+            }
+            self.finish()
+        });
+        ReorderThread { sender: Some(sender), next, thread: Some(thread) }
+    }
+}
+
+/// Handle to a [`Reorder`] writing on its own thread.
+pub struct ReorderThread<W> {
+    sender: Option<mpsc::Sender<(usize, Bytes)>>,
+    next: watch::Receiver<usize>,
+    thread: Option<thread::JoinHandle<Result<W>>>,
+}
+
+impl<W> ReorderThread<W> {
+    /// Index of the next segment to be appended; everything before it has
+    /// been written.
+    pub fn next(&self) -> usize {
+        *self.next.borrow()
+    }
+
+    /// Hands segment `index` over to be written.
+    pub fn push(&mut self, index: usize, data: Bytes) -> Result<()> {
+        let sent = self.sender.as_ref().is_some_and(|sender| sender.send((index, data)).is_ok());
+        if sent { Ok(()) } else { Err(self.failure()) }
+    }
+
+    /// Waits until more of the output has been written.
+    pub async fn written(&mut self) -> Result<()> {
+        match self.next.changed().await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(self.failure()),
+        }
+    }
+
+    /// Waits for everything handed over to be written and returns the writer.
+    pub async fn finish(mut self) -> Result<W>
+    where
+        W: Send + 'static,
+    {
+        self.sender = None;
+        let thread = self.thread.take().ok_or_else(|| anyhow!("The output writer stopped early"))?;
+        // The writer may still have a lot to write; wait for it off the async threads.
+        match tokio::task::spawn_blocking(move || thread.join()).await {
+            Ok(Ok(result)) => result,
+            _ => Err(anyhow!("The output writer panicked")),
+        }
+    }
+
+    /// Why the writer thread stopped early.
+    fn failure(&mut self) -> anyhow::Error {
+        self.sender = None;
+        match self.join() {
+            Ok(_) => anyhow!("The output writer stopped early"),
+            Err(e) => e,
+        }
+    }
+
+    /// Only called once the thread has stopped (its channel is gone), so the
+    /// join does not wait.
+    fn join(&mut self) -> Result<W> {
+        let thread = self.thread.take().ok_or_else(|| anyhow!("The output writer stopped early"))?;
+        thread.join().map_err(|_| anyhow!("The output writer panicked"))?
+    }
+}
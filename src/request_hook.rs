@@ -0,0 +1,116 @@
+//! Pre-request hook (`--request-hook` / `request_hook`): a user script run
+//! before every playlist and segment request, for schools that sign URLs or
+//! require computed headers the downloader does not know about yet.
+//!
+//! The script gets the URL as its first argument and in
+//! `$GETCOURSE_REQUEST_URL`. Each line it prints is either a replacement URL
+//! (`https://...`) or a header to set (`Name: value`, replacing one of the
+//! same name); blank lines and lines starting with `#` are ignored. No output leaves the request unchanged, and
+//! a non-zero exit fails the request (which is then retried as usual).
+
+use crate::http::{HttpFetcher, Response};
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use std::{process::Command, sync::Arc};
+
+/// Wraps `inner` so every request first passes through `command`.
+pub struct HookedFetcher {
+    inner: Arc<dyn HttpFetcher>,
+    command: Arc<str>,
+}
+
+impl HookedFetcher {
+    pub fn new(inner: Arc<dyn HttpFetcher>, command: &str) -> Self {
+        Self { inner, command: command.into() }
+    }
+}
+
+impl HookedFetcher {
+    /// Runs the hook for `url`; returns the URL and headers to use instead.
+    async fn rewrite(&self, url: &str, headers: &[(String, String)]) -> Result<(String, Vec<(String, String)>)> {
+        let command = Arc::clone(&self.command);
+        let original = url.to_string();
+        let rewrite = tokio::task::spawn_blocking(move || run(&command, &original))
+            .await
+            .context("Request hook panicked")??;
+        // The hook's headers win over configured ones of the same name.
+        let mut all: Vec<_> = headers
+            .iter()
+            .filter(|(name, _)| !rewrite.headers.iter().any(|(set, _)| set.eq_ignore_ascii_case(name)))
+            .cloned()
+            .collect();
+        all.extend(rewrite.headers);
+        Ok((rewrite.url.unwrap_or_else(|| url.to_string()), all))
+    }
+}
+
+impl HttpFetcher for HookedFetcher {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let (url, headers) = self.rewrite(url, headers).await?;
+            self.inner.request(&url, &headers).await
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let (url, headers) = self.rewrite(url, headers).await?;
+            self.inner.head(&url, &headers).await
+        })
+    }
+}
+
+/// A replacement URL and extra headers, as printed by a hook or plugin.
+#[derive(Debug, Default)]
+pub struct Rewrite {
+    pub url: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+fn run(command: &str, url: &str) -> Result<Rewrite> {
+    // The URL never becomes part of the command line, so quotes, `%` or `&`
+    // in it cannot change what runs.
+    #[cfg(windows)]
+    let mut shell = {
+        use std::os::windows::process::CommandExt;
+        // Delayed `!VAR!` expansion happens after cmd has parsed the line.
+        let mut shell = Command::new("cmd");
+        shell.args(["/V:ON", "/C"]).raw_arg(format!("{} \"!GETCOURSE_REQUEST_URL!\"", command));
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        // "$0" is the script name slot of `sh -c`, so the URL lands in "$1".
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(format!("{} \"$1\"", command)).arg("sh").arg(url);
+        shell
+    };
+    let output = shell
+        .env("GETCOURSE_REQUEST_URL", url)
+        .output()
+        .context("Failed to start request hook")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => anyhow!("Request hook exited with {}", output.status),
+            stderr => anyhow!("Request hook exited with {}: {}", output.status, stderr),
+        });
+    }
+    parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses script output: `http(s)://` lines set the URL, `Name: value` lines
+/// add headers.
+pub fn parse(stdout: &str) -> Result<Rewrite> {
+    let mut rewrite = Rewrite::default();
+    for line in stdout.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        if line.starts_with("http://") || line.starts_with("https://") {
+            rewrite.url = Some(line.to_string());
+        } else if let Some((name, value)) = line.split_once(':') {
+            rewrite.headers.push((name.trim().to_string(), value.trim().to_string()));
+        } else {
+            return Err(anyhow!("Unrecognized line in script output: {}", line));
+        }
+    }
+    Ok(rewrite)
+}
@@ -0,0 +1,212 @@
+//! Resumable downloads. Segments are stored in `<output>.parts` next to the
+//! output, or in `tmp_dir`, together with `state.json`: the playlist, its
+//! segments and the ones already finished. Running the same download again
+//! finds them there and fetches only the rest. The directory is removed once
+//! the output is complete, and kept when the download fails.
+
+use crate::{segment_file, sha256};
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+const STATE_FILE: &str = "state.json";
+
+/// Minimum time between two writes of the state file. Segments finished in
+/// between are saved with the next write; after a crash they are simply
+/// fetched again.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The work directory of one download.
+pub struct WorkDir {
+    path: PathBuf,
+    playlist: String,
+    segments: Vec<String>,
+    done: BTreeSet<usize>,
+    saved: Option<Instant>,
+    /// Set once the output is complete; the directory is removed on drop.
+    finished: bool,
+}
+
+impl WorkDir {
+    /// Opens the work directory for downloading `segments` of `playlist` into
+    /// `output`, picking up what an earlier run of the same download left.
+    /// The leftovers of a different download are cleared, and with `fresh`
+    /// those of the same one too. It is created in `tmp_dir` when given, with
+    /// a name that tells outputs of the same name in different directories
+    /// apart.
+    pub fn open(
+        output: &Path,
+        tmp_dir: Option<&Path>,
+        playlist: &str,
+        segments: &[String],
+        fresh: bool,
+    ) -> Result<Self> {
+        let mut name = output.file_name().unwrap_or_default().to_os_string();
+        let path = match tmp_dir {
+            Some(dir) => {
+                let full = std::path::absolute(output).unwrap_or_else(|_| output.to_path_buf());
+                let digest = sha256::digest_hex(full.as_os_str().as_encoded_bytes());
+                name.push(format!("-{}.parts", &digest[..8]));
+                dir.join(name)
+            }
+            None => {
+                name.push(".parts");
+                output.with_file_name(name)
+            }
+        };
+        let mut work = Self {
+            path,
+            playlist: playlist.to_string(),
+            segments: segments.to_vec(),
+            done: BTreeSet::new(),
+            saved: None,
+            finished: false,
+        };
+        match work.load() {
+            Some(_) if fresh => {
+                info!("Discarding the earlier progress in {} (--force)", work.path.display());
+                fs::remove_dir_all(&work.path).with_context(|| format!("Failed to clear {}", work.path.display()))?;
+            }
+            Some(done) => work.done = done,
+            None if work.path.exists() => {
+                info!("Discarding {}, which belongs to a different download", work.path.display());
+                fs::remove_dir_all(&work.path).with_context(|| format!("Failed to clear {}", work.path.display()))?;
+            }
+            None => {}
+        }
+        fs::create_dir_all(&work.path).with_context(|| format!("Failed to create {}", work.path.display()))?;
+        if work.done.is_empty() {
+            info!("Using work directory: {}", work.path.display());
+            work.save()?;
+        } else {
+            info!(
+                "Resuming from {}: {} of {} segments already downloaded",
+                work.path.display(),
+                work.done.len(),
+                segments.len()
+            );
+        }
+        Ok(work)
+    }
+
+    /// The finished segments recorded in the state file, if it describes
+    /// this download. Segments whose file has gone missing are not counted.
+    fn load(&self) -> Option<BTreeSet<usize>> {
+        let text = fs::read_to_string(self.path.join(STATE_FILE)).ok()?;
+        let state: serde_json::Value = serde_json::from_str(&text).ok()?;
+        let segments: Vec<&str> = state["segments"].as_array()?.iter().filter_map(|s| s.as_str()).collect();
+        // Signed URLs change between runs; the paths do not.
+        let same = segments.len() == self.segments.len()
+            && segments.iter().zip(&self.segments).all(|(a, b)| without_query(a) == without_query(b));
+        if !same {
+            return None;
+        }
+        let done = state["done"].as_array()?.iter().filter_map(|i| i.as_u64()).map(|i| i as usize);
+        Some(done.filter(|&i| i < self.segments.len() && segment_file(&self.path, i).exists()).collect())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Segments finished by an earlier run.
+    pub fn done(&self) -> impl Iterator<Item = usize> + '_ {
+        self.done.iter().copied()
+    }
+
+    /// Records segment `index` as finished.
+    pub fn record(&mut self, index: usize) {
+        self.done.insert(index);
+        if self.saved.is_none_or(|saved| saved.elapsed() >= SAVE_INTERVAL)
+            && let Err(e) = self.save()
+        {
+            warn!("Warning: {:#}", e);
+        }
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let state = json!({
+            "playlist": self.playlist,
+            "segments": self.segments,
+            "done": self.done,
+        });
+        let tmp = self.path.join(format!("{}.tmp", STATE_FILE));
+        fs::write(&tmp, state.to_string())
+            .and_then(|_| fs::rename(&tmp, self.path.join(STATE_FILE)))
+            .context("Failed to save the download state")?;
+        self.saved = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Marks the download complete, so the directory is removed.
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+
+    /// Marks the download complete and moves the segments (and the tracks of
+    /// alternate audio, in their subdirectories) to `dir`, which must be
+    /// missing or empty, instead of removing them. When they cannot be moved
+    /// they stay where they are.
+    pub fn keep(mut self, dir: &Path) -> Result<()> {
+        for name in [STATE_FILE, "joined.tmp"] {
+            let _ = fs::remove_file(self.path.join(name));
+        }
+        let moved = fs::rename(&self.path, dir).or_else(|_| {
+            // Another file system, or a platform that does not rename over an empty directory.
+            copy_tree(&self.path, dir)?;
+            fs::remove_dir_all(&self.path)
+        });
+        match moved {
+            Ok(()) => {
+                self.finished = true;
+                Ok(())
+            }
+            Err(e) => {
+                // Left alone on drop: neither removed nor saved as a partial download.
+                let path = std::mem::take(&mut self.path);
+                self.done.clear();
+                Err(e).with_context(|| {
+                    format!("Failed to move the segments to {}; they are in {}", dir.display(), path.display())
+                })
+            }
+        }
+    }
+}
+
+/// Copies the files in `from`, and those of its subdirectories, to `to`.
+fn copy_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_tree(&entry.path(), &target)?,
+            false => fs::copy(entry.path(), target).map(drop)?,
+        }
+    }
+    Ok(())
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if self.finished || self.done.is_empty() {
+            let _ = fs::remove_dir_all(&self.path);
+        } else if self.save().is_ok() {
+            warn!("Partial download kept in {}; run the same command again to resume", self.path.display());
+        }
+    }
+}
+
+/// `url` without its query, keeping the fragment that names a byte range.
+fn without_query(url: &str) -> String {
+    match url.split_once('?') {
+        Some((path, rest)) => format!("{}{}", path, rest.find('#').map_or("", |i| &rest[i..])),
+        None => url.to_string(),
+    }
+}
@@ -0,0 +1,44 @@
+//! Retry policy for requests: how many times to try again, and how long to
+//! wait in between. The wait doubles with every attempt, up to a cap, and is
+//! jittered so that many failed segments do not all come back at once.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    time::Duration,
+};
+
+/// Longest wait between two attempts, however many have failed.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Wait before the first retry when none is configured.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Attempts after the first one.
+    pub retries: usize,
+    /// Wait before the first retry; 0 retries at once.
+    pub backoff: Duration,
+}
+
+impl Retry {
+    /// `retries` more attempts with the default backoff.
+    pub fn times(retries: usize) -> Self {
+        Self { retries, backoff: DEFAULT_BACKOFF }
+    }
+
+    /// The wait before retry number `attempt` (0 for the first): `backoff`
+    /// doubled `attempt` times and capped, of which a random part between
+    /// nothing and half is left out.
+    pub fn delay(self, attempt: usize) -> Duration {
+        let full = self.backoff.saturating_mul(1 << attempt.min(20)).min(MAX_DELAY);
+        let jitter = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+        full.mul_f64(1.0 - jitter / 2.0)
+    }
+
+    /// Sleeps before retry number `attempt`.
+    pub async fn wait(self, attempt: usize) {
+        tokio::time::sleep(self.delay(attempt)).await;
+    }
+}
@@ -0,0 +1,255 @@
+//! `--serve ADDR`: watching a video while it downloads. A small HTTP server
+//! on `ADDR` offers an event playlist of the segments downloaded so far, in
+//! order, which grows as more arrive, so a player pointed at it starts from
+//! the beginning and keeps up. `--stream` downloads keep no segments and
+//! offer the growing output file instead. The server stays up after the
+//! download is complete, until Ctrl+C, so a player that is behind can finish.
+
+use crate::{interrupt, playlist::Segment, Destination};
+use anyhow::{Context, Result};
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, info};
+
+/// How often a reader of the growing output looks for more.
+const POLL: Duration = Duration::from_millis(250);
+
+/// Where the served video is read from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Segment files in the work directory.
+    Dir(PathBuf),
+    /// Segments at their offsets in the output (`--in-place`).
+    Output { path: PathBuf, offsets: Vec<u64> },
+    /// The output itself, as it grows (`--stream`).
+    Growing(PathBuf),
+}
+
+impl Source {
+    fn destination(&self) -> Option<Destination<'_>> {
+        match self {
+            Source::Dir(dir) => Some(Destination::Dir(dir)),
+            Source::Output { path, offsets } => Some(Destination::Output { path, offsets }),
+            Source::Growing(_) => None,
+        }
+    }
+}
+
+struct Shared {
+    source: Source,
+    /// Duration of each segment, and whether it follows a discontinuity.
+    segments: Vec<(f64, bool)>,
+    /// Segment 0 is the init segment of a fragmented MP4 stream.
+    init: bool,
+    ready: Vec<bool>,
+    complete: bool,
+}
+
+impl Shared {
+    /// The segments downloaded so far, up to the first one that is not.
+    fn playlist(&self) -> String {
+        let target = self.segments.iter().map(|(duration, _)| duration.ceil() as u64).max().unwrap_or(0).max(1);
+        let mut text = String::from("#EXTM3U\n");
+        let _ = write!(
+            text,
+            "#EXT-X-VERSION:{}\n#EXT-X-TARGETDURATION:{}\n#EXT-X-PLAYLIST-TYPE:EVENT\n#EXT-X-MEDIA-SEQUENCE:0\n",
+            if self.init { 7 } else { 3 },
+            target
+        );
+        let first = self.init as usize;
+        if self.init && self.ready.first() == Some(&true) {
+            text.push_str("#EXT-X-MAP:URI=\"segment/0.mp4\"\n");
+        }
+        if !self.init || self.ready.first() == Some(&true) {
+            for index in (first..self.segments.len()).take_while(|&index| self.ready[index]) {
+                let (duration, discontinuity) = self.segments[index];
+                if discontinuity && index > first {
+                    text.push_str("#EXT-X-DISCONTINUITY\n");
+                }
+                let _ = write!(text, "#EXTINF:{:.3},\nsegment/{}.{}\n", duration, index, self.extension());
+            }
+        }
+        if self.complete {
+            text.push_str("#EXT-X-ENDLIST\n");
+        }
+        text
+    }
+
+    fn extension(&self) -> &'static str {
+        if self.init { "mp4" } else { "ts" }
+    }
+
+    fn content_type(&self) -> &'static str {
+        if self.init { "video/mp4" } else { "video/mp2t" }
+    }
+}
+
+/// The running server; dropping it stops it.
+pub struct Server {
+    shared: Arc<Mutex<Shared>>,
+    url: String,
+    task: JoinHandle<()>,
+}
+
+impl Server {
+    /// Starts serving `segments` from `source` on `addr`. `init` says that the
+    /// first segment is the init segment of a fragmented MP4 stream.
+    pub async fn start(addr: SocketAddr, segments: &[Segment], init: bool, source: Source) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await.with_context(|| format!("Cannot listen on {} (--serve)", addr))?;
+        let path = match source {
+            Source::Growing(_) => "video",
+            _ => "playlist.m3u8",
+        };
+        let shared = Arc::new(Mutex::new(Shared {
+            source,
+            segments: segments.iter().map(|s| (s.duration, s.discontinuity)).collect(),
+            init,
+            ready: vec![false; segments.len()],
+            complete: false,
+        }));
+        let url = format!("http://{}/{}", listener.local_addr()?, path);
+        info!("Watch while it downloads: {}", url);
+        let task = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let shared = Arc::clone(&shared);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, &shared).await {
+                            debug!("Serving a request failed: {:#}", e);
+                        }
+                    });
+                }
+            }
+        });
+        Ok(Self { shared, url, task })
+    }
+
+    /// Adds segment `index` to the playlist, along with any after it that
+    /// were waiting for it.
+    pub fn ready(&self, index: usize) {
+        if let Some(ready) = self.shared.lock().unwrap().ready.get_mut(index) {
+            *ready = true;
+        }
+    }
+
+    /// Ends the playlist. A file the video is read from has moved to `output`.
+    pub fn finish(&self, output: &Path) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.complete = true;
+        match &mut shared.source {
+            Source::Output { path, .. } | Source::Growing(path) => *path = output.to_path_buf(),
+            Source::Dir(_) => {}
+        }
+    }
+
+    /// Keeps serving until Ctrl+C (or SIGTERM).
+    pub async fn linger(self) {
+        info!("Still serving {} until Ctrl+C", self.url);
+        let _handling = interrupt::handle();
+        interrupt::signalled().await;
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers one request and closes the connection.
+async fn handle(stream: TcpStream, shared: &Mutex<Shared>) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request = String::new();
+    stream.read_line(&mut request).await?;
+    // The headers say nothing the answer depends on.
+    let mut line = String::new();
+    while stream.read_line(&mut line).await? > 2 {
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let head = parts.next() == Some("HEAD");
+    let path = parts.next().unwrap_or("/");
+    let mut stream = stream.into_inner();
+
+    let growing = matches!(shared.lock().unwrap().source, Source::Growing(_));
+    if path == "/video" || (path == "/" && growing) {
+        return grow(&mut stream, shared, head).await;
+    }
+    if !growing && (path == "/" || path == "/playlist.m3u8") {
+        let playlist = shared.lock().unwrap().playlist();
+        return reply(&mut stream, "200 OK", "application/vnd.apple.mpegurl", playlist.as_bytes(), head).await;
+    }
+    let segment = path
+        .strip_prefix("/segment/")
+        .and_then(|name| name.split_once('.'))
+        .and_then(|(index, _)| index.parse::<usize>().ok());
+    let (source, content_type) = {
+        let shared = shared.lock().unwrap();
+        let source = segment.filter(|&index| shared.ready.get(index) == Some(&true)).map(|_| shared.source.clone());
+        (source, shared.content_type())
+    };
+    match (segment, source) {
+        (Some(index), Some(source)) if source.destination().is_some() => {
+            let data = crate::blocking(move || source.destination().expect("checked").read(index)).await?;
+            reply(&mut stream, "200 OK", content_type, &data, head).await
+        }
+        _ => reply(&mut stream, "404 Not Found", "text/plain", b"Not downloaded (yet)\n", head).await,
+    }
+}
+
+async fn reply(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8], head: bool) -> Result<()> {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    if !head {
+        stream.write_all(body).await?;
+    }
+    Ok(())
+}
+
+/// Sends the growing output from its start, and more as it is written, until
+/// the download is complete.
+async fn grow(stream: &mut TcpStream, shared: &Mutex<Shared>, head: bool) -> Result<()> {
+    let (path, content_type) = {
+        let shared = shared.lock().unwrap();
+        let path = match &shared.source {
+            Source::Growing(path) => Some(path.clone()),
+            _ => None,
+        };
+        (path, shared.content_type())
+    };
+    let Some(path) = path else {
+        return reply(stream, "404 Not Found", "text/plain", b"Not a --stream download\n", head).await;
+    };
+    let header = format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n\r\n", content_type);
+    stream.write_all(header.as_bytes()).await?;
+    if head {
+        return Ok(());
+    }
+    let mut file = tokio::fs::File::open(&path).await?;
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        // Whether it was complete before this read, which then got the rest.
+        let complete = shared.lock().unwrap().complete;
+        match file.read(&mut buf).await? {
+            0 if complete => return Ok(()),
+            0 => tokio::time::sleep(POLL).await,
+            n => stream.write_all(&buf[..n]).await?,
+        }
+    }
+}
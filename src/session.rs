@@ -0,0 +1,309 @@
+//! Logged-in sessions for schools whose lessons are not public. The `login`
+//! subcommand signs in with the account's email and password the way the
+//! school's login form does, and keeps the session cookie in `sessions.json`
+//! next to the config file. Later requests to that school (its pages,
+//! playlists and segments) carry the cookie; other hosts never see it.
+
+use crate::{
+    config,
+    http::{HttpFetcher, Response},
+};
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use serde_json::{Map, Value};
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+use url::Url;
+
+/// Path of the school's login page and form endpoint.
+const LOGIN_PATH: &str = "/cms/system/login";
+
+/// `sessions.json` in the config directory.
+pub fn default_path() -> Option<PathBuf> {
+    Some(config::default_path()?.with_file_name("sessions.json"))
+}
+
+/// Saved sessions: the `Cookie` header value for each school host.
+#[derive(Debug, Default)]
+pub struct Sessions(Map<String, Value>);
+
+impl Sessions {
+    /// Reads `path`; a missing file holds no sessions.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read {}", path.display())),
+        };
+        match serde_json::from_str(&text) {
+            Ok(Value::Object(map)) => Ok(Self(map)),
+            _ => Err(anyhow!("{} is not a JSON object of host: cookie pairs", path.display())),
+        }
+    }
+
+    /// Writes the sessions to `path`, readable by the owner only.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path).with_context(|| format!("Cannot write {}", path.display()))?;
+        serde_json::to_writer_pretty(&mut file, &self.0)?;
+        writeln!(file)?;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, host: &str, cookie: String) {
+        self.0.insert(host.to_ascii_lowercase(), Value::String(cookie));
+    }
+
+    /// The cookie for `host`: the session of the host itself, or of the
+    /// nearest parent domain that has one.
+    pub fn cookie(&self, host: &str) -> Option<&str> {
+        let host = host.to_ascii_lowercase();
+        let mut domain = host.as_str();
+        loop {
+            if let Some(cookie) = self.0.get(domain).and_then(Value::as_str) {
+                return Some(cookie);
+            }
+            domain = domain.split_once('.')?.1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Sends the saved session cookie with requests of `inner` to the school it
+/// belongs to, unless the request has a `Cookie` header already.
+pub struct WithSession {
+    inner: Arc<dyn HttpFetcher>,
+    sessions: Sessions,
+}
+
+impl WithSession {
+    pub fn new(inner: Arc<dyn HttpFetcher>, sessions: Sessions) -> Self {
+        Self { inner, sessions }
+    }
+
+    fn headers(&self, url: &str, headers: &[(String, String)]) -> Vec<(String, String)> {
+        let mut all = headers.to_vec();
+        let host = Url::parse(url).ok().and_then(|url| url.host_str().map(String::from));
+        if let Some(cookie) = host.and_then(|host| self.sessions.cookie(&host))
+            && !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+        {
+            all.push(("Cookie".to_string(), cookie.to_string()));
+        }
+        all
+    }
+}
+
+impl HttpFetcher for WithSession {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let all = self.headers(url, headers);
+            self.inner.request(url, &all).await
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let all = self.headers(url, headers);
+            self.inner.head(url, &all).await
+        })
+    }
+}
+
+/// `inner` with the saved sessions, if there are any.
+pub fn with_saved(inner: Arc<dyn HttpFetcher>) -> Arc<dyn HttpFetcher> {
+    match saved() {
+        Some(sessions) => Arc::new(WithSession::new(inner, sessions)),
+        None => inner,
+    }
+}
+
+/// The saved sessions, unless there are none.
+pub fn saved() -> Option<Sessions> {
+    match Sessions::load(&default_path()?) {
+        Ok(sessions) if !sessions.is_empty() => Some(sessions),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Warning: ignoring saved sessions: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Cookies as a `Cookie` header value; later ones replace earlier ones of the
+/// same name.
+fn cookie_header(cookies: &[String]) -> String {
+    let mut pairs: Vec<(&str, &str)> = Vec::new();
+    for (name, value) in cookies.iter().filter_map(|c| c.split_once('=')) {
+        pairs.retain(|(other, _)| *other != name);
+        pairs.push((name, value));
+    }
+    pairs.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("; ")
+}
+
+/// A JavaScript assignment `window.<name> = <value>;` on the login page.
+fn page_variable(html: &str, name: &str) -> Option<String> {
+    let rest = &html[html.find(&format!("window.{}", name))? + "window.".len() + name.len()..];
+    let value = rest.trim_start().strip_prefix('=')?.trim_start();
+    let end = value.find([';', '\n']).unwrap_or(value.len());
+    Some(value[..end].trim().trim_matches(['"', '\'']).to_string()).filter(|v| !v.is_empty())
+}
+
+/// The `data-xdget-id` of the login form: the last one before the email
+/// field, as forms nest inside other page blocks.
+fn form_id(html: &str) -> Option<String> {
+    let end = html.find("name=\"email\"").unwrap_or(html.len());
+    let head = &html[..end];
+    let start = head.rfind("data-xdget-id=\"")? + "data-xdget-id=\"".len();
+    let id = &head[start..start + head[start..].find('"')?];
+    Some(id.to_string())
+}
+
+/// Signs in to the school at `school` and returns the session's `Cookie`
+/// header value.
+pub async fn login(fetcher: &dyn HttpFetcher, school: &Url, email: &str, password: &str) -> Result<String> {
+    let page_url = school.join(LOGIN_PATH)?;
+    let page = fetcher.get(page_url.as_str()).await.with_context(|| format!("Cannot open {}", page_url))?;
+    if !page.is_success() {
+        return Err(anyhow!("{} answered {}", page_url, page.status));
+    }
+    let mut cookies = page.cookies.clone();
+    let html = page.text().await?;
+    let xdget_id =
+        form_id(&html).ok_or_else(|| anyhow!("No login form on {}; is this a GetCourse school?", page_url))?;
+    let request_time = page_variable(&html, "requestTime").unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs()).to_string()
+    });
+    let sign = page_variable(&html, "requestSimpleSign").unwrap_or_default();
+
+    let form: Vec<(String, String)> = [
+        ("action", "processXdget"),
+        ("xdgetId", &xdget_id),
+        ("params[action]", "login"),
+        ("params[url]", page_url.as_str()),
+        ("params[email]", email),
+        ("params[password]", password),
+        ("params[object_type]", "cms_page"),
+        ("params[object_id]", "-1"),
+        ("requestTime", &request_time),
+        ("requestSimpleSign", &sign),
+    ]
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    let mut headers = vec![
+        ("X-Requested-With".to_string(), "XMLHttpRequest".to_string()),
+        ("Referer".to_string(), page_url.to_string()),
+    ];
+    if !cookies.is_empty() {
+        headers.push(("Cookie".to_string(), cookie_header(&cookies)));
+    }
+    let post_url = format!("{}?required=true", page_url);
+    let resp = fetcher.post_form(&post_url, &headers, &form).await.context("Login request failed")?;
+    let status = resp.status;
+    cookies.extend(resp.cookies.iter().cloned());
+    let answer: Value = serde_json::from_str(&resp.text().await?)
+        .map_err(|_| anyhow!("The login endpoint answered {} with something other than JSON", status))?;
+    if answer["success"] != Value::Bool(true) {
+        let reason = ["errorMessage", "message"]
+            .iter()
+            .find_map(|key| answer[key].as_str().filter(|m| !m.is_empty()))
+            .unwrap_or("no reason given");
+        return Err(anyhow!("Login failed: {}", reason));
+    }
+    if cookies.is_empty() {
+        return Err(anyhow!("Login succeeded, but the school set no session cookie"));
+    }
+    Ok(cookie_header(&cookies))
+}
+
+/// The password from `GETCOURSE_PASSWORD`, or else a line typed at a prompt.
+fn read_password() -> Result<String> {
+    if let Ok(password) = env::var("GETCOURSE_PASSWORD") {
+        return Ok(password);
+    }
+    eprint!("Password: ");
+    io::stderr().flush()?;
+    #[cfg(unix)]
+    let echo = Echo::off();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    #[cfg(unix)]
+    if echo.is_some() {
+        eprintln!();
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Terminal echo switched off for as long as this lives.
+#[cfg(unix)]
+struct Echo(libc::termios);
+
+#[cfg(unix)]
+impl Echo {
+    /// `None` when stdin is not a terminal.
+    fn off() -> Option<Self> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return None;
+        }
+        let mut silent = termios;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent) };
+        Some(Self(termios))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Echo {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+/// `login URL --email EMAIL [--password PASSWORD]`.
+pub async fn run(fetcher: &dyn HttpFetcher, args: &[String]) -> Result<()> {
+    let mut school = None;
+    let mut email = None;
+    let mut password = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter.next().cloned().ok_or_else(|| anyhow!("Missing value for {}", name));
+        match arg.as_str() {
+            "--email" => email = Some(value(arg)?),
+            "--password" => password = Some(value(arg)?),
+            other if other.starts_with("--") => return Err(anyhow!("Unknown login option: {}", other)),
+            url if school.is_none() => school = Some(Url::parse(url).with_context(|| format!("Invalid URL {}", url))?),
+            extra => return Err(anyhow!("Unexpected argument: {}", extra)),
+        }
+    }
+    let school = school.ok_or_else(|| anyhow!("Usage: getcourse-downloader login SCHOOL_URL --email EMAIL"))?;
+    let host = school.host_str().ok_or_else(|| anyhow!("{} has no host", school))?.to_string();
+    let email = email.ok_or_else(|| anyhow!("Give the account's email with --email"))?;
+    let password = match password {
+        Some(password) => password,
+        None => read_password()?,
+    };
+
+    let cookie = login(fetcher, &school, &email, &password).await?;
+    let path = default_path().ok_or_else(|| anyhow!("No config directory to keep the session in"))?;
+    let mut sessions = Sessions::load(&path)?;
+    sessions.insert(&host, cookie);
+    sessions.save(&path)?;
+    info!("Logged in to {} as {}; the session is saved in {}", host, email, path.display());
+    Ok(())
+}
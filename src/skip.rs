@@ -0,0 +1,183 @@
+//! Skipping of spliced-in segments: ranges marked with `#EXT-X-DATERANGE`
+//! of a given CLASS, and (heuristically) short intro/outro bumpers delimited
+//! by `#EXT-X-DISCONTINUITY`.
+
+use crate::Segment;
+use anyhow::{anyhow, Result};
+use tracing::info;
+
+/// Longest leading or trailing discontinuity-delimited part that
+/// `skip_bumpers` treats as a bumper.
+const BUMPER_MAX_SECS: f64 = 30.0;
+
+/// An `#EXT-X-DATERANGE` tag, attached to the segment that follows it.
+#[derive(Debug, Clone, Default)]
+pub struct DateRange {
+    pub class: Option<String>,
+    /// START-DATE as Unix time.
+    pub start: Option<f64>,
+    /// DURATION (or PLANNED-DURATION, or END-DATE minus START-DATE).
+    pub duration: Option<f64>,
+}
+
+impl DateRange {
+    pub fn parse(attributes: &str) -> Self {
+        let mut range = Self::default();
+        let mut end = None;
+        for (key, value) in attribute_list(attributes) {
+            match key {
+                "CLASS" => range.class = Some(value),
+                "START-DATE" => range.start = parse_date(&value),
+                "END-DATE" => end = parse_date(&value),
+                "DURATION" => range.duration = value.parse().ok(),
+                "PLANNED-DURATION" if range.duration.is_none() => range.duration = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if let (None, Some(start), Some(end)) = (range.duration, range.start, end) {
+            range.duration = Some(end - start);
+        }
+        range
+    }
+}
+
+/// Drops segments covered by a DATERANGE whose CLASS is in `classes`, and
+/// with `bumpers`, short parts at the very start and end of the stream. The
+/// segment after a removed stretch is marked as a discontinuity.
+pub fn apply(segments: Vec<Segment>, classes: &[String], bumpers: bool) -> Result<Vec<Segment>> {
+    let mut skip = vec![false; segments.len()];
+    if !classes.is_empty() {
+        mark_dateranges(&segments, classes, &mut skip);
+    }
+    if bumpers {
+        mark_bumpers(&segments, &mut skip);
+    }
+    let skipped: Vec<_> = segments.iter().zip(&skip).filter(|(_, s)| **s).map(|(seg, _)| seg).collect();
+    if skipped.is_empty() {
+        return Ok(segments);
+    }
+    if skipped.len() == segments.len() {
+        return Err(anyhow!("Every segment of the playlist is marked for skipping"));
+    }
+    let seconds: f64 = skipped.iter().map(|s| s.duration).sum();
+    info!("Skipping {} segment(s) ({:.1}s) of intros/inserts", skipped.len(), seconds);
+
+    let mut kept = Vec::with_capacity(segments.len() - skipped.len());
+    let mut gap = false;
+    for (mut segment, skip) in segments.into_iter().zip(skip) {
+        if skip {
+            gap = true;
+            continue;
+        }
+        segment.discontinuity |= gap && !kept.is_empty();
+        gap = false;
+        kept.push(segment);
+    }
+    Ok(kept)
+}
+
+fn mark_dateranges(segments: &[Segment], classes: &[String], skip: &mut [bool]) {
+    for (i, segment) in segments.iter().enumerate() {
+        let ranges = segment
+            .dateranges
+            .iter()
+            .filter(|r| r.class.as_ref().is_some_and(|c| classes.contains(c)));
+        for range in ranges {
+            match (range.start, segment.program_date) {
+                // With program dates the range can be placed exactly.
+                (Some(start), Some(_)) => {
+                    let end = start + range.duration.unwrap_or(0.0);
+                    for (j, other) in segments.iter().enumerate() {
+                        if other.program_date.is_some_and(|t| t >= start && t < end) {
+                            skip[j] = true;
+                        }
+                    }
+                }
+                // Otherwise it starts at the next segment and runs for DURATION.
+                _ => {
+                    let mut left = range.duration.unwrap_or(segment.duration);
+                    for (j, other) in segments.iter().enumerate().skip(i) {
+                        if left <= 0.0 {
+                            break;
+                        }
+                        skip[j] = true;
+                        left -= other.duration.max(0.001);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn mark_bumpers(segments: &[Segment], skip: &mut [bool]) {
+    let starts: Vec<usize> = (0..segments.len()).filter(|&i| i == 0 || segments[i].discontinuity).collect();
+    if starts.len() < 2 {
+        return;
+    }
+    let part = |k: usize| starts[k]..starts.get(k + 1).copied().unwrap_or(segments.len());
+    let duration = |range: std::ops::Range<usize>| segments[range].iter().map(|s| s.duration).sum::<f64>();
+    // Only when there is a main part to keep.
+    if (0..starts.len()).all(|k| duration(part(k)) <= BUMPER_MAX_SECS) {
+        return;
+    }
+    for k in [0, starts.len() - 1] {
+        if duration(part(k)) <= BUMPER_MAX_SECS {
+            skip[part(k)].iter_mut().for_each(|s| *s = true);
+        }
+    }
+}
+
+/// `KEY=VALUE,KEY="quoted, value"` as used by playlist tags.
+pub fn attribute_list(list: &str) -> Vec<(&str, String)> {
+    let mut out = Vec::new();
+    let mut rest = list.trim();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let after = &rest[eq + 1..];
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (quoted[..end].to_string(), quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        out.push((key, value));
+        rest = next.trim_start_matches(',').trim_start();
+    }
+    out
+}
+
+/// ISO 8601 date-time (`2024-05-01T10:00:00.000Z`, `...+03:00`) as Unix time.
+pub fn parse_date(s: &str) -> Option<f64> {
+    let (date, time) = s.trim().split_once(['T', ' '])?;
+    let mut ymd = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => (time, "Z"),
+    };
+    let mut hms = clock.splitn(3, ':');
+    let hours: f64 = hms.next()?.parse().ok()?;
+    let minutes: f64 = hms.next()?.parse().ok()?;
+    let seconds: f64 = hms.next().unwrap_or("0").parse().ok()?;
+    let offset = match offset.as_bytes().first() {
+        Some(b'+' | b'-') => {
+            let sign = if offset.starts_with('-') { -1.0 } else { 1.0 };
+            let digits: String = offset[1..].chars().filter(char::is_ascii_digit).collect();
+            let (h, m) = digits.split_at(digits.len().min(2));
+            sign * (h.parse::<f64>().ok()? * 3600.0 + m.parse::<f64>().unwrap_or(0.0) * 60.0)
+        }
+        _ => 0.0,
+    };
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil).
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days as f64 * 86_400.0 + hours * 3600.0 + minutes * 60.0 + seconds - offset)
+}
@@ -0,0 +1,175 @@
+//! Free-space monitoring while downloading. Before the first segment, the
+//! size of the download is estimated and a download that cannot fit is
+//! refused. When the work directory or the output's filesystem runs low
+//! later on, the download pauses with a message instead of running into "No
+//! space left on device" halfway through a write, and picks up again by
+//! itself once space has been freed.
+
+use crate::{exit::Failure, http::HttpFetcher, playlist::Segment, progress};
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// How often a paused download looks at the free space again.
+const POLL: Duration = Duration::from_secs(10);
+
+pub struct SpaceGuard {
+    /// Directories to watch, with a description for messages.
+    dirs: Vec<(PathBuf, &'static str)>,
+    /// Free space to keep at all times, in bytes.
+    reserve: u64,
+}
+
+impl SpaceGuard {
+    pub fn new(reserve: u64) -> Self {
+        Self { dirs: Vec::new(), reserve }
+    }
+
+    pub fn watch(mut self, dir: &Path, what: &'static str) -> Self {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        self.dirs.push((dir.to_path_buf(), what));
+        self
+    }
+
+    /// Returns once every watched filesystem has room for `needed` more bytes
+    /// on top of the reserve, pausing until then if necessary.
+    pub async fn wait(&self, needed: u64) {
+        let mut paused = false;
+        loop {
+            let short = self.dirs.iter().find_map(|(dir, what)| {
+                let free = available(dir)?;
+                (free < self.reserve.saturating_add(needed)).then_some((dir, what, free))
+            });
+            let Some((dir, what, free)) = short else {
+                if paused {
+                    info!("Enough free space again; resuming");
+                }
+                return;
+            };
+            if !paused {
+                warn!(
+                    "WARNING: only {} free on {} ({}), {} needed; pausing before the disk fills up.",
+                    progress::size(free),
+                    dir.display(),
+                    what,
+                    progress::size(self.reserve.saturating_add(needed))
+                );
+                warn!(
+                    "WARNING: free some space and the download resumes by itself, or stop it with Ctrl+C and run the same command again later."
+                );
+                paused = true;
+            }
+            tokio::time::sleep(POLL).await;
+        }
+    }
+}
+
+/// Segments whose size is asked for when estimating a download.
+const SAMPLE: usize = 8;
+
+/// The expected size of each of `segments`: the length of its byte range
+/// when it has one, or else from HEAD requests on a sample of the others,
+/// scaled by duration. `None` when the server reports no sizes.
+pub async fn estimate(fetcher: &dyn HttpFetcher, segments: &[Segment], concurrency: usize) -> Option<Vec<u64>> {
+    let probed: Vec<usize> = (0..segments.len()).filter(|&i| segments[i].byte_range.is_none()).collect();
+    // Spread over the stream, so that a different opening or ending weighs little.
+    let step = probed.len().div_ceil(SAMPLE).max(1);
+    let sample: Vec<(usize, Option<u64>)> = futures::stream::iter(probed.iter().step_by(step))
+        .map(|&i| async move {
+            let resp = fetcher.head(&segments[i].url, &[]).await.ok();
+            (i, resp.and_then(|resp| resp.length.filter(|_| resp.is_success())))
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+    let measured: Vec<(usize, u64)> = sample.into_iter().filter_map(|(i, size)| Some((i, size?))).collect();
+    let rate = match measured.is_empty() {
+        true if !probed.is_empty() => return None,
+        true => 0.0,
+        false => {
+            let bytes: u64 = measured.iter().map(|&(_, size)| size).sum();
+            let seconds: f64 = measured.iter().map(|&(i, _)| segments[i].duration).sum();
+            bytes as f64 / seconds.max(0.001)
+        }
+    };
+    let sizes = segments.iter().enumerate().map(|(i, segment)| {
+        match (&segment.byte_range, measured.iter().find(|&&(at, _)| at == i)) {
+            (Some(range), _) => range.length,
+            (None, Some(&(_, size))) => size,
+            (None, None) => (rate * segment.duration) as u64,
+        }
+    });
+    Some(sizes.collect())
+}
+
+/// Fails when a filesystem lacks room for what is to be written to the
+/// directories in `needs` (with a description and a size each), on top of
+/// `reserve`. Directories on the same filesystem add up.
+pub fn check(needs: &[(&Path, &'static str, u64)], reserve: u64) -> Result<()> {
+    let needs: Vec<(&Path, &str, u64)> = needs
+        .iter()
+        .map(|&(dir, what, bytes)| (if dir.as_os_str().is_empty() { Path::new(".") } else { dir }, what, bytes))
+        .collect();
+    for (i, &(dir, ..)) in needs.iter().enumerate() {
+        let shared: Vec<_> = needs.iter().filter(|(other, ..)| same_filesystem(dir, other)).collect();
+        // Each filesystem is looked at once, with the first directory on it.
+        if needs[..i].iter().any(|(other, ..)| same_filesystem(dir, other)) {
+            continue;
+        }
+        let Some(free) = available(dir) else {
+            continue;
+        };
+        let needed: u64 = shared.iter().map(|&&(_, _, bytes)| bytes).sum();
+        let what = shared.iter().map(|&&(_, what, _)| what).collect::<Vec<_>>().join(" and ");
+        if free < needed.saturating_add(reserve) {
+            return Err(Failure::Disk.of(anyhow!(
+                "Not enough free space on {} ({}): the download needs about {} and min_free_space keeps {} free, \
+                 but only {} is free; free some space, or pass --no-space-check to try anyway",
+                dir.display(),
+                what,
+                progress::size(needed),
+                progress::size(reserve),
+                progress::size(free)
+            )));
+        }
+        let (needed, free) = (progress::size(needed), progress::size(free));
+        debug!("About {} needed on {} ({}), {} free", needed, dir.display(), what, free);
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b` are on the same filesystem, as far as can be told.
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let (Ok(a), Ok(b)) = (std::fs::metadata(a), std::fs::metadata(b)) {
+            return a.dev() == b.dev();
+        }
+    }
+    a == b
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+#[cfg(unix)]
+pub fn available(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `path` is a valid C string and `stat` a properly sized out-parameter.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Not implemented on this platform; monitoring is skipped.
+#[cfg(not(unix))]
+pub fn available(_: &Path) -> Option<u64> {
+    None
+}
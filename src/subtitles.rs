@@ -0,0 +1,286 @@
+//! `--subs all|LANG`: subtitle tracks advertised by a master playlist with
+//! `#EXT-X-MEDIA:TYPE=SUBTITLES`, as read by [`crate::playlist`]. The media
+//! playlist of each picked track lists WebVTT segments; their cues are put on
+//! the timeline of the video and written to one file per track next to the
+//! output, `<output>.<language>.vtt` (or `.srt`).
+
+use crate::{
+    audio::Rendition, config::Config, decrypt, download_playlist, fetch_segment, http::HttpFetcher,
+    media_playlist, mirror, remux::Trim,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use std::{collections::HashSet, fmt::Write as _, fs, path::Path, sync::Arc};
+use tracing::{info, warn};
+
+/// The file format subtitle tracks are saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Vtt,
+    Srt,
+}
+
+impl Format {
+    pub const NAMES: &[&str] = &["vtt", "srt"];
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "vtt" => Ok(Format::Vtt),
+            "srt" => Ok(Format::Srt),
+            other => Err(anyhow!("Unknown subtitle format {:?}; expected one of {}", other, Self::NAMES.join(", "))),
+        }
+    }
+
+    /// Also the file extension.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Vtt => "vtt",
+            Format::Srt => "srt",
+        }
+    }
+}
+
+/// Picks the tracks asked for in `wanted`: all of them for `all`, otherwise
+/// those matching an entry by LANGUAGE or NAME, in that order. Entries that
+/// match nothing are reported and skipped; the video is downloaded anyway.
+pub fn select(tracks: &[Rendition], wanted: &[String]) -> Vec<Rendition> {
+    if tracks.is_empty() {
+        info!("The playlist has no subtitle tracks");
+        return Vec::new();
+    }
+    if wanted.iter().any(|want| want.eq_ignore_ascii_case("all")) {
+        return tracks.to_vec();
+    }
+    let mut picked: Vec<Rendition> = Vec::new();
+    for want in wanted {
+        match tracks.iter().find(|track| track.matches(want)) {
+            Some(track) if !picked.contains(track) => picked.push(track.clone()),
+            Some(_) => {}
+            None => {
+                let available: Vec<String> = tracks.iter().map(Rendition::label).collect();
+                warn!("Warning: no subtitle track matches {:?}; available: {}", want, available.join(", "));
+            }
+        }
+    }
+    picked
+}
+
+/// Saves `tracks` next to `output_file`. `origin` is the timestamp (in
+/// seconds) of the first frame of the video, and `trim` the part of it the
+/// output was cut to. A track that fails is reported and skipped.
+pub async fn save(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    tracks: &[Rendition],
+    output_file: &Path,
+    origin: Option<f64>,
+    trim: Option<Trim>,
+) {
+    let mut names = HashSet::new();
+    for (k, track) in tracks.iter().enumerate() {
+        let mut tag = sanitize_tag(track.language.as_deref().unwrap_or(&track.name));
+        if tag.is_empty() || !names.insert(tag.clone()) {
+            tag = format!("{}{}", tag, k + 1);
+            names.insert(tag.clone());
+        }
+        let path = output_file.with_extension(format!("{}.{}", tag, config.subtitle_format.name()));
+        let saved = async {
+            let cues = fetch_cues(fetcher, config, track, origin, trim).await?;
+            let text = match config.subtitle_format {
+                Format::Vtt => vtt(&cues),
+                Format::Srt => srt(&cues.cues),
+            };
+            fs::write(&path, text).with_context(|| format!("Cannot write {}", path.display()))?;
+            anyhow::Ok(cues.cues.len())
+        };
+        match saved.await {
+            Ok(count) => info!("Subtitles {} saved to {} ({} cues)", track.label(), path.display(), count),
+            Err(e) => warn!("Warning: subtitles {} could not be saved: {:#}", track.label(), e),
+        }
+    }
+}
+
+/// A language tag or name made safe for the middle of a file name.
+fn sanitize_tag(tag: &str) -> String {
+    tag.chars().filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_')).collect()
+}
+
+/// The cues of one track, on the output's timeline.
+#[derive(Default)]
+struct Cues {
+    /// `STYLE` and `REGION` blocks, kept for WebVTT output.
+    header: Vec<String>,
+    cues: Vec<Cue>,
+}
+
+struct Cue {
+    start: f64,
+    end: f64,
+    /// Cue settings after the timing (`align:start` and the like).
+    settings: String,
+    text: String,
+}
+
+async fn fetch_cues(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    track: &Rendition,
+    origin: Option<f64>,
+    trim: Option<Trim>,
+) -> Result<Cues> {
+    let (text, base) = download_playlist(fetcher.as_ref(), &track.uri, config.playlist_retry())
+        .await
+        .context("Failed to download its playlist")?;
+    let segments = media_playlist(&text, &base)?.segments;
+    let keys = segments.iter().map(|s| (s.url.as_str(), s.key.as_ref()));
+    let fetcher = &mirror::with_mirrors(fetcher, &config.mirrors)?;
+    let fetcher = &decrypt::decrypting(fetcher, keys, config.playlist_retry()).await?;
+    let retry = config.segment_retry();
+    let bodies: Vec<_> = futures::stream::iter(&segments)
+        .map(|segment| async move { fetch_segment(fetcher.as_ref(), &segment.url, retry).await })
+        .buffered(config.concurrency)
+        .try_collect()
+        .await?;
+
+    let mut cues = Cues::default();
+    let mut seen = HashSet::new();
+    let mut origin = origin;
+    for (segment, (body, _)) in segments.iter().zip(bodies) {
+        let parsed = parse_segment(&String::from_utf8_lossy(&body))
+            .with_context(|| format!("Cannot read {}", segment.url))?;
+        // Without the video's timestamp, the first segment is taken to start with it.
+        let origin = *origin.get_or_insert(parsed.offset);
+        for block in parsed.header {
+            if !cues.header.contains(&block) {
+                cues.header.push(block);
+            }
+        }
+        for mut cue in parsed.cues {
+            let shift = parsed.offset - origin + trim.map_or(0.0, |t| -t.start);
+            cue.start = (cue.start + shift).max(0.0);
+            cue.end += shift;
+            if let Some(duration) = trim.and_then(|t| t.duration) {
+                cue.end = cue.end.min(duration);
+            }
+            // Cues that span a segment boundary are repeated in both.
+            let key = (millis(cue.start), millis(cue.end), cue.text.clone());
+            if cue.end > cue.start && seen.insert(key) {
+                cues.cues.push(cue);
+            }
+        }
+    }
+    cues.cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+/// One WebVTT segment.
+struct Parsed {
+    /// Added to cue times to put them on the MPEG-TS timeline, in seconds,
+    /// from its `X-TIMESTAMP-MAP` (RFC 8216, 3.5).
+    offset: f64,
+    header: Vec<String>,
+    cues: Vec<Cue>,
+}
+
+fn parse_segment(text: &str) -> Result<Parsed> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut blocks = text.split("\n\n").map(|block| block.trim_matches('\n')).filter(|block| !block.is_empty());
+    let head = blocks.next().unwrap_or_default();
+    if !head.starts_with("WEBVTT") {
+        return Err(anyhow!("not WebVTT text (subtitles in MP4 segments are not supported yet)"));
+    }
+    let mut segment = Parsed { offset: 0.0, header: Vec::new(), cues: Vec::new() };
+    if let Some(map) = head.lines().find_map(|line| line.strip_prefix("X-TIMESTAMP-MAP=")) {
+        let (mut mpegts, mut local) = (0.0, 0.0);
+        for (key, value) in map.split(',').filter_map(|part| part.split_once(':')) {
+            match key.trim() {
+                "MPEGTS" => mpegts = value.trim().parse::<u64>().context("Invalid X-TIMESTAMP-MAP")? as f64,
+                "LOCAL" => local = timestamp(value.trim()).context("Invalid X-TIMESTAMP-MAP")?,
+                _ => {}
+            }
+        }
+        segment.offset = mpegts / 90_000.0 - local;
+    }
+    for block in blocks {
+        if block.starts_with("STYLE") || block.starts_with("REGION") {
+            segment.header.push(block.to_string());
+            continue;
+        }
+        let mut lines = block.lines();
+        // An optional identifier comes before the timing.
+        let Some(timing) = lines.by_ref().take(2).find(|line| line.contains("-->")) else {
+            continue;
+        };
+        let (start, rest) = timing.split_once("-->").expect("checked");
+        let rest = rest.trim();
+        let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let (Some(start), Some(end)) = (timestamp(start.trim()), timestamp(end)) else {
+            continue;
+        };
+        let text = lines.collect::<Vec<_>>().join("\n");
+        segment.cues.push(Cue { start, end, settings: settings.trim().to_string(), text });
+    }
+    Ok(segment)
+}
+
+/// `hh:mm:ss.ttt` or `mm:ss.ttt`, in seconds.
+fn timestamp(text: &str) -> Option<f64> {
+    let (clock, fraction) = text.split_once('.')?;
+    let mut seconds = 0;
+    for part in clock.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(seconds as f64 + format!("0.{}", fraction).parse::<f64>().ok()?)
+}
+
+fn millis(seconds: f64) -> u64 {
+    (seconds * 1000.0).round() as u64
+}
+
+/// `hh:mm:ss` and the milliseconds joined by `separator`.
+fn clock(seconds: f64, separator: char) -> String {
+    let ms = millis(seconds);
+    format!("{:02}:{:02}:{:02}{}{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, separator, ms % 1000)
+}
+
+fn vtt(cues: &Cues) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for block in &cues.header {
+        let _ = write!(out, "{}\n\n", block);
+    }
+    for cue in &cues.cues {
+        let settings = if cue.settings.is_empty() { String::new() } else { format!(" {}", cue.settings) };
+        let _ = write!(out, "{} --> {}{}\n{}\n\n", clock(cue.start, '.'), clock(cue.end, '.'), settings, cue.text);
+    }
+    out
+}
+
+fn srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        let (start, end) = (clock(cue.start, ','), clock(cue.end, ','));
+        let _ = write!(out, "{}\n{} --> {}\n{}\n\n", i + 1, start, end, plain(&cue.text));
+    }
+    out
+}
+
+/// WebVTT cue text as SubRip has it: only the `<i>`, `<b>` and `<u>` tags
+/// kept, and the character references WebVTT needs replaced.
+fn plain(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+        let tag = &rest[open..open + close + 1];
+        if matches!(tag, "<i>" | "</i>" | "<b>" | "</b>" | "<u>" | "</u>") {
+            out.push_str(tag);
+        }
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    out.replace("&lt;", "<").replace("&gt;", ">").replace("&nbsp;", " ").replace("&amp;", "&")
+}
@@ -0,0 +1,278 @@
+//! Parser for the subset of TOML used by the config file: `[table]` headers,
+//! `key = value` pairs with strings, integers, floats, booleans and arrays
+//! that fit on one line, and `#` comments. Anything beyond that, such as
+//! multi-line arrays or `[[arrays.of.tables]]`, is an error rather than
+//! being read as something else.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+        }
+    }
+
+    /// Interprets a value given on the command line or in the environment:
+    /// anything that parses as a TOML literal is taken as such, everything
+    /// else is a plain string.
+    pub fn from_cli(raw: &str) -> Value {
+        parse_value(raw.trim())
+            .ok()
+            .filter(|(_, rest)| rest.trim().is_empty())
+            .map(|(value, _)| value)
+            .unwrap_or_else(|| Value::String(raw.to_string()))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// A `key = value` pair with its fully qualified (dotted) key and line number.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+pub fn parse(input: &str) -> Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut table = String::new();
+    let mut tables = Vec::new();
+
+    for (i, raw) in input.lines().enumerate() {
+        let line_no = i + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix('[') {
+            if rest.trim_start().starts_with('[') {
+                return Err(anyhow!("line {}: arrays of tables are not supported", line_no));
+            }
+            let (name, tail) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow!("line {}: unterminated table header", line_no))?;
+            check_trailing(tail, line_no)?;
+            let name = name.trim();
+            if name.is_empty() || !name.split('.').all(is_bare_key) {
+                return Err(anyhow!("line {}: invalid table name '{}'", line_no, name));
+            }
+            if tables.iter().any(|t| t == name) {
+                return Err(anyhow!("line {}: table [{}] defined twice", line_no, name));
+            }
+            tables.push(name.to_string());
+            table = name.to_string();
+            continue;
+        }
+
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected `key = value`", line_no))?;
+        let key = key.trim().trim_matches('"');
+        if !key.split('.').all(is_bare_key) {
+            return Err(anyhow!("line {}: invalid key '{}'", line_no, key));
+        }
+        let (value, tail) =
+            parse_value(rest.trim()).map_err(|e| anyhow!("line {}: {}", line_no, e))?;
+        check_trailing(tail, line_no)?;
+
+        let key = if table.is_empty() { key.to_string() } else { format!("{}.{}", table, key) };
+        if entries.iter().any(|e| e.key == key) {
+            return Err(anyhow!("line {}: duplicate key '{}'", line_no, key));
+        }
+        entries.push(Entry { key, value, line: line_no });
+    }
+    Ok(entries)
+}
+
+fn is_bare_key(key: &str) -> bool {
+    let key = key.trim();
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn check_trailing(tail: &str, line_no: usize) -> Result<()> {
+    let tail = tail.trim();
+    if tail.is_empty() || tail.starts_with('#') {
+        Ok(())
+    } else {
+        Err(anyhow!("line {}: unexpected trailing characters '{}'", line_no, tail))
+    }
+}
+
+fn parse_value(s: &str) -> Result<(Value, &str)> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(out), &rest[i + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('\\') => out.push('\\'),
+                    Some('"') => out.push('"'),
+                    other => return Err(anyhow!("unsupported escape sequence \\{}", other.unwrap_or(' '))),
+                },
+                c => out.push(c),
+            }
+        }
+        return Err(anyhow!("unterminated string"));
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or_else(|| anyhow!("unterminated string"))?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                return Err(anyhow!("unterminated array (arrays must fit on one line)"));
+            }
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.is_empty() && !rest.starts_with(['#', ']']) {
+                return Err(anyhow!("expected ',' or ']' in array"));
+            }
+        }
+    }
+
+    let end = s.find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace()).unwrap_or(s.len());
+    let (token, rest) = s.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => {
+            let digits = token.replace('_', "");
+            if let Ok(i) = digits.parse::<i64>() {
+                Value::Integer(i)
+            } else if let Some(x) = digits
+                .parse::<f64>()
+                .ok()
+                .filter(|_| token.chars().any(|c| c.is_ascii_digit()))
+            {
+                Value::Float(x)
+            } else if token.is_empty() {
+                return Err(anyhow!("missing value"));
+            } else {
+                return Err(anyhow!("invalid value '{}' (strings must be quoted)", token));
+            }
+        }
+    };
+    Ok((value, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(input: &str) -> Value {
+        parse(&format!("key = {}", input)).unwrap().remove(0).value
+    }
+
+    fn error(input: &str) -> String {
+        parse(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(value(r#""plain""#), Value::String("plain".into()));
+        assert_eq!(value(r#""a # b""#), Value::String("a # b".into()));
+        assert_eq!(value(r"'C:\dir\'"), Value::String(r"C:\dir\".into()));
+        let escaped = value(r#""tab\t, quote \", backslash \\, newline\n""#);
+        assert_eq!(escaped, Value::String("tab\t, quote \", backslash \\, newline\n".into()));
+    }
+
+    #[test]
+    fn unsupported_escapes_are_errors() {
+        assert!(error(r#"key = "\u00e9""#).contains("unsupported escape"));
+    }
+
+    #[test]
+    fn numbers_and_booleans() {
+        assert_eq!(value("1_000"), Value::Integer(1000));
+        assert_eq!(value("-3"), Value::Integer(-3));
+        assert_eq!(value("0.5"), Value::Float(0.5));
+        assert_eq!(value("true"), Value::Boolean(true));
+        assert!(error("key = yes").contains("strings must be quoted"));
+    }
+
+    #[test]
+    fn arrays() {
+        let strings = Value::Array(vec![Value::String("a".into()), Value::String("b".into())]);
+        assert_eq!(value(r#"["a", "b"]"#), strings);
+        assert_eq!(value(r#"[ "a" , "b", ]"#), strings);
+        assert_eq!(value("[]"), Value::Array(Vec::new()));
+        assert_eq!(value("[[1], [2, 3]]").to_string(), "[[1], [2, 3]]");
+    }
+
+    #[test]
+    fn comments_and_tables() {
+        let entries = parse("# top\nconcurrency = 4 # trailing\n\n[rules.a]\n  dir = \"x\"\n").unwrap();
+        let keys: Vec<_> = entries.iter().map(|e| (e.key.as_str(), e.line)).collect();
+        assert_eq!(keys, [("concurrency", 2), ("rules.a.dir", 5)]);
+        assert_eq!(entries[0].value, Value::Integer(4));
+    }
+
+    #[test]
+    fn multi_line_arrays_are_errors() {
+        assert!(error("headers = [\n  \"a\",\n]").contains("line 1: unterminated array"));
+        assert!(error("headers = [\"a\", # first\n  \"b\"]").contains("unterminated array"));
+        assert!(error("headers = [\"a\"").contains("unterminated array"));
+    }
+
+    #[test]
+    fn arrays_of_tables_are_errors() {
+        assert!(error("[[rules]]\nschool = \"x\"").contains("arrays of tables are not supported"));
+        assert!(error("[ [rules]]").contains("arrays of tables are not supported"));
+    }
+
+    #[test]
+    fn other_errors() {
+        assert!(error("key = \"open").contains("unterminated string"));
+        assert!(error("key = 1 2").contains("trailing characters"));
+        assert!(error("a = 1\na = 2").contains("duplicate key"));
+        assert!(error("key = { a = 1 }").contains("invalid value"));
+    }
+}
@@ -0,0 +1,203 @@
+//! `--concurrency auto`: the number of segment requests in flight follows
+//! the server instead of staying fixed. It starts at `concurrency` and is
+//! weighed again each time that many segments have finished. When throttled
+//! (429, 503) or failed requests (timeouts, broken connections) make up more
+//! than a tenth of the requests since, it is halved. Otherwise it grows by a
+//! quarter for as long as the combined throughput keeps rising with it, and
+//! steps back once more requests no longer pay off.
+
+use crate::{
+    config::Config,
+    http::{HttpFetcher, Response},
+};
+use anyhow::Result;
+use futures::{future::BoxFuture, StreamExt};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tracing::{debug, info};
+
+/// The most requests in flight at once that tuning goes up to.
+pub const MAX: usize = 32;
+
+/// Share of pushed-back requests above which the level is halved.
+const PUSHBACK_SHARE: f64 = 0.1;
+
+/// Throughput gain that makes a step up worth keeping.
+const GAIN: f64 = 1.05;
+
+/// Rounds to stay at a level after backing off or stepping back, before
+/// trying more requests again.
+const PATIENCE: usize = 8;
+
+/// Segments to finish before a round is weighed, at the least.
+const MIN_ROUND: usize = 4;
+
+/// How many segment requests may be in flight at once.
+pub struct Concurrency {
+    limit: usize,
+    tuning: Option<Tuning>,
+}
+
+struct Tuning {
+    signals: Arc<Signals>,
+    round: Round,
+    /// The level before the last step up and its throughput, until the step
+    /// has been weighed.
+    previous: Option<(usize, f64)>,
+    /// Rounds left before the next step up.
+    hold: usize,
+}
+
+/// Requests seen by [`Observed`] fetchers.
+#[derive(Default)]
+struct Signals {
+    requests: AtomicU64,
+    /// Throttled or failed ones.
+    pushback: AtomicU64,
+}
+
+/// What happened since the level was last weighed.
+struct Round {
+    started: Instant,
+    segments: usize,
+    bytes: u64,
+    requests: u64,
+    pushback: u64,
+}
+
+impl Round {
+    fn start(signals: &Signals) -> Self {
+        Self {
+            started: Instant::now(),
+            segments: 0,
+            bytes: 0,
+            requests: signals.requests.load(Ordering::Relaxed),
+            pushback: signals.pushback.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Concurrency {
+    /// The level `config` asks for.
+    pub fn of(config: &Config) -> Self {
+        match config.auto_concurrency {
+            true => Self::auto(config.concurrency),
+            false => Self::fixed(config.concurrency),
+        }
+    }
+
+    /// Always `limit`.
+    pub fn fixed(limit: usize) -> Self {
+        Self { limit: limit.max(1), tuning: None }
+    }
+
+    /// Starting at `start`, tuned between 1 and [`MAX`].
+    pub fn auto(start: usize) -> Self {
+        let signals = Arc::new(Signals::default());
+        let round = Round::start(&signals);
+        Self { limit: start.clamp(1, MAX), tuning: Some(Tuning { signals, round, previous: None, hold: 0 }) }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// `fetcher`, reporting its requests to the tuning; `fetcher` itself for
+    /// a fixed level.
+    pub fn observe(&self, fetcher: &Arc<dyn HttpFetcher>) -> Arc<dyn HttpFetcher> {
+        match &self.tuning {
+            Some(tuning) => Arc::new(Observed { inner: Arc::clone(fetcher), signals: Arc::clone(&tuning.signals) }),
+            None => Arc::clone(fetcher),
+        }
+    }
+
+    /// Counts a finished segment of `bytes`, weighing the level at the end
+    /// of a round.
+    pub fn done(&mut self, bytes: u64) {
+        let Some(tuning) = &mut self.tuning else {
+            return;
+        };
+        let round = &mut tuning.round;
+        round.segments += 1;
+        round.bytes += bytes;
+        if round.segments < self.limit.max(MIN_ROUND) {
+            return;
+        }
+        let rate = round.bytes as f64 / round.started.elapsed().as_secs_f64().max(0.001);
+        // Requests started in the round before may finish in this one.
+        let requests = (tuning.signals.requests.load(Ordering::Relaxed) - round.requests).max(round.segments as u64);
+        let pushback = tuning.signals.pushback.load(Ordering::Relaxed) - round.pushback;
+        let limit = self.limit;
+        self.limit = if pushback as f64 > requests as f64 * PUSHBACK_SHARE {
+            (tuning.previous, tuning.hold) = (None, PATIENCE);
+            let next = (limit / 2).max(1);
+            if next < limit {
+                info!("{} of {} requests were throttled or failed; {} at a time now", pushback, requests, next);
+            }
+            next
+        } else {
+            match tuning.previous.take() {
+                Some((level, before)) if rate < before * GAIN => {
+                    tuning.hold = PATIENCE;
+                    debug!("{} requests at a time were no faster than {}; back to {}", limit, level, level);
+                    level
+                }
+                _ if tuning.hold > 0 => {
+                    tuning.hold -= 1;
+                    limit
+                }
+                _ => {
+                    let next = (limit + (limit / 4).max(1)).min(MAX);
+                    if next > limit {
+                        tuning.previous = Some((limit, rate));
+                        debug!("Trying {} requests at a time", next);
+                    }
+                    next
+                }
+            }
+        };
+        tuning.round = Round::start(&tuning.signals);
+    }
+}
+
+/// Counts the requests of `inner` and those the server pushed back.
+struct Observed {
+    inner: Arc<dyn HttpFetcher>,
+    signals: Arc<Signals>,
+}
+
+impl HttpFetcher for Observed {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let signals = &self.signals;
+            signals.requests.fetch_add(1, Ordering::Relaxed);
+            let result = self.inner.request(url, headers).await;
+            match &result {
+                Ok(resp) if !matches!(resp.status, 429 | 503) => {}
+                _ => {
+                    signals.pushback.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            let mut resp = result?;
+            let signals = Arc::clone(signals);
+            resp.body = resp
+                .body
+                .inspect(move |chunk| {
+                    if chunk.is_err() {
+                        signals.pushback.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+                .boxed();
+            Ok(resp)
+        })
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        self.inner.head(url, headers)
+    }
+}
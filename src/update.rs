@@ -0,0 +1,184 @@
+//! `self-update` subcommand: fetches the latest GitHub release, verifies the
+//! platform binary against the published `SHA256SUMS` and atomically replaces
+//! the running executable. The sums come from the same release and are not
+//! signed, so this guards against damaged downloads, not tampered releases.
+//! Also hosts the startup version/advisory check.
+
+use crate::{download_with_retry, http::HttpFetcher, retry::Retry, sha256};
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::{env, fs, path::Path, time::Duration};
+use tracing::{info, warn};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/geibos/getcou-rs/releases/latest";
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+const ADVISORIES_URL: &str = "https://raw.githubusercontent.com/geibos/getcou-rs/main/advisories.json";
+const ADVISORY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Startup check against `advisories.json`: warns when a newer version exists
+/// and, more importantly, when this version is known not to work with current
+/// GetCourse playlists. Never fails the run; network problems are ignored.
+pub async fn check_advisories(fetcher: &dyn HttpFetcher) {
+    let url = env::var("GETCOURSE_ADVISORIES_URL").unwrap_or_else(|_| ADVISORIES_URL.to_string());
+    let fetch = async {
+        let resp = fetcher.get(&url).await.ok().filter(|r| r.is_success())?;
+        serde_json::from_str::<Value>(&resp.text().await.ok()?).ok()
+    };
+    let Ok(Some(doc)) = tokio::time::timeout(ADVISORY_TIMEOUT, fetch).await else {
+        return;
+    };
+
+    let current = env!("CARGO_PKG_VERSION");
+    for advisory in doc["advisories"].as_array().into_iter().flatten() {
+        let (Some(fixed_in), Some(message)) =
+            (advisory["fixed_in"].as_str(), advisory["message"].as_str())
+        else {
+            continue;
+        };
+        if is_newer(fixed_in, current) {
+            warn!("Warning: {}", message);
+            warn!("         Fixed in v{}; run `getcourse-downloader self-update`.", fixed_in.trim_start_matches('v'));
+        }
+    }
+    if let Some(latest) = doc["latest"].as_str().filter(|latest| is_newer(latest, current)) {
+        warn!(
+            "A new version is available: v{} (current: v{}). Run `getcourse-downloader self-update`.",
+            latest.trim_start_matches('v'),
+            current
+        );
+    }
+}
+
+pub async fn run(fetcher: &dyn HttpFetcher, args: &[String]) -> Result<()> {
+    let mut check_only = false;
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check_only = true,
+            other => return Err(anyhow!("Unknown self-update option: {}", other)),
+        }
+    }
+
+    let url = env::var("GETCOURSE_UPDATE_URL").unwrap_or_else(|_| RELEASES_URL.to_string());
+    let release: Value = serde_json::from_str(
+        &download_with_retry(fetcher, &url, Retry::times(3))
+            .await
+            .context("Failed to query the latest release")?,
+    )
+    .context("Release metadata is not valid JSON")?;
+
+    let tag = release["tag_name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Release metadata has no tag_name"))?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(tag, current) {
+        info!("Already up to date (v{}).", current);
+        return Ok(());
+    }
+    info!("New version available: {} (current: v{})", tag, current);
+    if check_only {
+        return Ok(());
+    }
+
+    let name = asset_name();
+    let binary_url = asset_url(&release, &name)?;
+    let sums_url = asset_url(&release, CHECKSUMS_ASSET)?;
+
+    let sums = download_with_retry(fetcher, sums_url, Retry::times(3))
+        .await
+        .context("Failed to download checksums")?;
+    let expected = sums
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(hash, _)| hash.to_ascii_lowercase())
+        .ok_or_else(|| anyhow!("{} does not list {}", CHECKSUMS_ASSET, name))?;
+
+    info!("Downloading {}", name);
+    let resp = fetcher.get(binary_url).await?;
+    if !resp.is_success() {
+        return Err(anyhow!("HTTP status: {}", resp.status));
+    }
+    let binary = resp.bytes().await.context("Failed to download release binary")?;
+    let actual = sha256::digest_hex(&binary);
+    if actual != expected {
+        return Err(anyhow!(
+            "Checksum mismatch for {} (expected {}, got {}); not updating",
+            name,
+            expected,
+            actual
+        ));
+    }
+
+    let exe = env::current_exe().context("Cannot locate the running executable")?;
+    replace_executable(&exe, &binary)?;
+    info!("Updated {} to {}", exe.display(), tag);
+    Ok(())
+}
+
+fn asset_name() -> String {
+    format!(
+        "getcourse-downloader-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+fn asset_url<'a>(release: &'a Value, name: &str) -> Result<&'a str> {
+    release["assets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|asset| asset["name"].as_str() == Some(name))
+        .and_then(|asset| asset["browser_download_url"].as_str())
+        .ok_or_else(|| anyhow!("Release has no asset named {}", name))
+}
+
+/// Compares dotted numeric versions, ignoring a leading `v` and any
+/// pre-release suffix.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parts(v: &str) -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    }
+    parts(candidate) > parts(current)
+}
+
+/// Writes the new binary next to the old one and renames it into place, so
+/// an interrupted update never leaves a half-written executable behind.
+fn replace_executable(exe: &Path, binary: &[u8]) -> Result<()> {
+    let dir = exe
+        .parent()
+        .ok_or_else(|| anyhow!("Executable has no parent directory"))?;
+    let staged = dir.join(".getcourse-downloader.update");
+    fs::write(&staged, binary)
+        .with_context(|| format!("Cannot write to {} (try running with more privileges)", dir.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    // Windows refuses to overwrite a running executable but allows renaming it.
+    #[cfg(windows)]
+    {
+        let old = exe.with_extension("old.exe");
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old).context("Failed to move the old executable aside")?;
+        if let Err(e) = fs::rename(&staged, exe) {
+            // Put the old one back rather than leave no executable at all.
+            let _ = fs::rename(&old, exe);
+            let _ = fs::remove_file(&staged);
+            return Err(e).context("Failed to replace the executable");
+        }
+    }
+
+    #[cfg(not(windows))]
+    fs::rename(&staged, exe).context("Failed to replace the executable")?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! Video variants of a master playlist (`#EXT-X-STREAM-INF`) and picking one
+//! by `quality`. They are read by [`crate::playlist`].
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{self, BufRead, IsTerminal, Write};
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    /// Peak bits per second.
+    pub bandwidth: u64,
+    /// Width and height.
+    pub resolution: Option<(u32, u32)>,
+    /// Absolute URL of the variant's media playlist.
+    pub uri: String,
+}
+
+impl Variant {
+    /// `1280x720, 2.5 Mbit/s`, for messages and the picker.
+    pub fn label(&self) -> String {
+        let rate = format!("{:.1} Mbit/s", self.bandwidth as f64 / 1e6);
+        match self.resolution {
+            Some((width, height)) => format!("{}x{}, {}", width, height, rate),
+            None => rate,
+        }
+    }
+
+    fn height(&self) -> u32 {
+        self.resolution.map_or(0, |(_, height)| height)
+    }
+}
+
+/// Which variant to download (`quality`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// Highest bandwidth.
+    Best,
+    /// Lowest bandwidth.
+    Worst,
+    /// This height (`720p`), or the tallest variant below it.
+    Height(u32),
+    /// This bandwidth in bits per second, or the fastest variant below it.
+    Bandwidth(u64),
+    /// Ask on the terminal.
+    Ask,
+}
+
+impl Quality {
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid =
+            || anyhow!("Unknown quality {:?}; expected best, worst, ask, a height like 720p or a bandwidth", s);
+        match s {
+            "best" => Ok(Quality::Best),
+            "worst" => Ok(Quality::Worst),
+            "ask" => Ok(Quality::Ask),
+            s => match s.strip_suffix('p') {
+                Some(height) => height.parse().map(Quality::Height).map_err(|_| invalid()),
+                None => s.parse().map(Quality::Bandwidth).map_err(|_| invalid()),
+            },
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Quality::Best => "best".to_string(),
+            Quality::Worst => "worst".to_string(),
+            Quality::Height(height) => format!("{}p", height),
+            Quality::Bandwidth(bandwidth) => bandwidth.to_string(),
+            Quality::Ask => "ask".to_string(),
+        }
+    }
+}
+
+/// Picks the variant `quality` asks for and says which one it is.
+pub fn select(variants: &[Variant], quality: Quality) -> Result<&Variant> {
+    let by_bandwidth = || variants.iter().max_by_key(|v| v.bandwidth);
+    let lowest = || variants.iter().min_by_key(|v| v.bandwidth);
+    let picked = match quality {
+        _ if variants.len() == 1 => variants.first(),
+        Quality::Best => by_bandwidth(),
+        Quality::Worst => lowest(),
+        Quality::Height(height) => {
+            variants.iter().filter(|v| v.height() <= height).max_by_key(|v| (v.height(), v.bandwidth)).or_else(lowest)
+        }
+        Quality::Bandwidth(bandwidth) => {
+            variants.iter().filter(|v| v.bandwidth <= bandwidth).max_by_key(|v| v.bandwidth).or_else(lowest)
+        }
+        Quality::Ask => Some(ask(variants)?),
+    };
+    let picked = picked.ok_or_else(|| anyhow!("The master playlist lists no variants"))?;
+    if variants.len() > 1 {
+        info!("Selected variant {} of {} available", picked.label(), variants.len());
+    }
+    Ok(picked)
+}
+
+/// The interactive picker for `quality = "ask"`; Enter takes the best.
+fn ask(variants: &[Variant]) -> Result<&Variant> {
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!("quality \"ask\" needs a terminal to ask on"));
+    }
+    let mut order: Vec<&Variant> = variants.iter().collect();
+    order.sort_by_key(|v| std::cmp::Reverse(v.bandwidth));
+    info!("Available variants:");
+    for (i, variant) in order.iter().enumerate() {
+        info!("  {}) {}", i + 1, variant.label());
+    }
+    loop {
+        print!("Pick one [1]: ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer).context("Failed to read the answer")? == 0 {
+            return Err(anyhow!("No variant picked"));
+        }
+        match answer.trim() {
+            "" => return Ok(order[0]),
+            answer => match answer.parse::<usize>() {
+                Ok(n) if (1..=order.len()).contains(&n) => return Ok(order[n - 1]),
+                _ => info!("Enter a number from 1 to {}", order.len()),
+            },
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! `watch` subcommand: periodically re-checks a training page and downloads
+//! lessons that were published since the last check.
+
+use crate::{
+    config::Config,
+    download, download_with_retry,
+    exit::Failure,
+    fetcher,
+    getcourse::{self, Lesson},
+    hook,
+    http::HttpFetcher,
+    output::Fsync,
+    parse_duration,
+    placement::{CourseInfo, Placement},
+    remux::Container,
+    DownloadOptions, Report,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+use url::Url;
+
+/// Lesson IDs already downloaded into the output directory, one per line.
+const STATE_FILE: &str = ".getcou-rs-watch";
+
+struct WatchOptions {
+    training: Url,
+    interval: Duration,
+    /// Overrides the directory chosen by placement rules.
+    output_dir: Option<PathBuf>,
+    once: bool,
+}
+
+impl WatchOptions {
+    fn from_args(args: &[String]) -> Result<Self> {
+        let mut training = None;
+        let mut interval = Duration::from_secs(6 * 3600);
+        let mut output_dir = None;
+        let mut once = false;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| {
+                iter.next()
+                    .ok_or_else(|| anyhow!("Missing value for {}", name))
+                    .cloned()
+            };
+            match arg.as_str() {
+                "--interval" => interval = parse_duration(&value(arg)?)?,
+                "--output-dir" => output_dir = Some(PathBuf::from(value(arg)?)),
+                "--once" => once = true,
+                other if !other.starts_with('-') && training.is_none() => {
+                    training = Some(Url::parse(other).context("Invalid training URL")?)
+                }
+                other => return Err(Failure::Usage.of(anyhow!("Unknown watch option: {}", other))),
+            }
+        }
+        let training = training.ok_or_else(|| {
+            anyhow!("Usage: getcourse-downloader watch <training-url> [--interval 6h] [--output-dir DIR] [--once]")
+        })?;
+        Ok(Self { training, interval, output_dir, once })
+    }
+}
+
+/// `exec` is the global `--exec` hook, run after every lesson.
+pub async fn run(config: &Config, exec: Option<&str>, args: &[String]) -> Result<()> {
+    let opts = WatchOptions::from_args(args)?;
+    let fetcher = fetcher(config)?;
+
+    loop {
+        match check(&fetcher, config, exec, &opts).await {
+            Ok(0) => info!("No new lessons."),
+            Ok(n) => info!("Downloaded {} new lesson(s).", n),
+            Err(e) => warn!("Check failed: {:#}", e),
+        }
+        if opts.once {
+            return Ok(());
+        }
+        info!("Next check in {}s.", opts.interval.as_secs());
+        tokio::time::sleep(opts.interval).await;
+    }
+}
+
+/// One pass over the training page. Lessons that fail are reported and
+/// retried on the next pass.
+async fn check(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    exec: Option<&str>,
+    opts: &WatchOptions,
+) -> Result<usize> {
+    let html = download_with_retry(fetcher.as_ref(), opts.training.as_str(), config.playlist_retry())
+        .await
+        .context("Failed to download training page")?;
+    let lessons = getcourse::list_lessons(&html, &opts.training);
+    if lessons.is_empty() {
+        return Err(anyhow!("No lessons found on {} (authentication required?)", opts.training));
+    }
+
+    let title = getcourse::page_title(&html).unwrap_or_else(|| "course".to_string());
+    let mut placement = Placement::resolve(
+        &config.rules,
+        CourseInfo::new(&opts.training, &title),
+        config.output_dir.clone().unwrap_or_else(|| PathBuf::from(".")),
+    );
+    if let Some(dir) = &opts.output_dir {
+        placement.dir = dir.clone();
+    }
+    fs::create_dir_all(&placement.dir)
+        .with_context(|| format!("Cannot create {}", placement.dir.display()))?;
+
+    let state_path = placement.dir.join(STATE_FILE);
+    let seen = read_state(&state_path)?;
+    let extension = config.remux.map_or("ts", Container::extension);
+    let mut downloaded = 0;
+    for (index, lesson) in lessons.iter().enumerate().filter(|(_, l)| !seen.contains(&l.id)) {
+        info!("New lesson: {}", lesson.title);
+        let output = placement.dir.join(placement.file_name(&lesson.title, index + 1, extension));
+        let started = Instant::now();
+        let result = fetch_lesson(fetcher, config, lesson, &title, &output).await;
+        let output = result.as_ref().map_or(output.display(), |report| report.output.display()).to_string();
+        match hook::finish(exec, lesson.url.as_str(), output, started, result).await {
+            Ok(()) => {
+                append_state(&state_path, &lesson.id, config.fsync != Fsync::Off)?;
+                downloaded += 1;
+            }
+            Err(e) => warn!("Failed to download \"{}\": {:#}", lesson.title, e),
+        }
+    }
+    Ok(downloaded)
+}
+
+async fn fetch_lesson(
+    fetcher: &Arc<dyn HttpFetcher>,
+    config: &Config,
+    lesson: &Lesson,
+    course: &str,
+    output: &Path,
+) -> Result<Report> {
+    let video = getcourse::resolve_lesson_playlist(fetcher.as_ref(), &lesson.url, config.playlist_retry()).await?;
+    let opts = DownloadOptions {
+        title: Some(lesson.title.clone()),
+        course: Some(course.to_string()),
+        ..Default::default()
+    };
+    download(fetcher, config, &opts, &video.playlist, output).await
+}
+
+fn read_state(path: &Path) -> Result<HashSet<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    }
+}
+
+fn append_state(path: &Path, id: &str, sync: bool) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", id)?;
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! Downloads from the in-process fixture origin, checked byte for byte
+//! against the segments it generates.
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use getcourse_downloader::{
+    config::Config,
+    fixture::{self, Disguise, Fixture, FixtureOptions},
+    http::{HttpFetcher, Response},
+    Downloader,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+const SEGMENTS: usize = 10;
+const SEGMENT_SIZE: usize = 188 * 50;
+/// The variant a download picks by default: the best one.
+const BEST: usize = 1;
+
+async fn start(opts: FixtureOptions) -> Fixture {
+    let opts = FixtureOptions {
+        addr: "127.0.0.1:0".parse().unwrap(),
+        segments: SEGMENTS,
+        segment_size: SEGMENT_SIZE,
+        ..opts
+    };
+    Fixture::start(opts).await.unwrap()
+}
+
+/// What the fixture's best variant decodes to, segment after segment.
+fn expected() -> Vec<u8> {
+    (0..SEGMENTS)
+        .flat_map(|index| fixture::segment_bytes(BEST, index, SEGMENT_SIZE, 900_000 + index as u64 * 4 * 90_000))
+        .collect()
+}
+
+/// The default configuration, changed by `tune`, without the long waits
+/// between retries.
+fn config(tune: impl FnOnce(&mut Config)) -> Config {
+    let mut config = Config::default();
+    config.retry_backoff = Duration::from_millis(10);
+    tune(&mut config);
+    config
+}
+
+async fn check(opts: FixtureOptions, config: Config) {
+    let fixture = start(opts).await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("video.ts");
+    Downloader::new(fixture.master_url()).config(config).output(&output).run().await.unwrap();
+    assert!(std::fs::read(&output).unwrap() == expected(), "the download differs from the fixture's segments");
+}
+
+#[tokio::test]
+async fn plain() {
+    check(FixtureOptions::default(), config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn aes() {
+    check(FixtureOptions { aes: true, ..Default::default() }, config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn bin_image() {
+    check(FixtureOptions { bin: Some(Disguise::Image), ..Default::default() }, config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn bin_xor() {
+    check(FixtureOptions { bin: Some(Disguise::Xor), ..Default::default() }, config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn byterange() {
+    check(FixtureOptions { byterange: true, ..Default::default() }, config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn damaged_segments_are_fetched_again() {
+    check(FixtureOptions { damage_every: Some(3), ..Default::default() }, config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn cut_segments_are_fetched_again() {
+    check(FixtureOptions { cut_every: Some(3), ..Default::default() }, config(|_| ())).await;
+}
+
+#[tokio::test]
+async fn stream() {
+    check(FixtureOptions::default(), config(|config| config.stream_output = true)).await;
+}
+
+#[tokio::test]
+async fn in_place() {
+    check(FixtureOptions::default(), config(|config| config.in_place = true)).await;
+}
+
+/// Counts the segment requests it passes on, and raises `interrupt` at
+/// number `interrupt_at`.
+struct Counting {
+    inner: Arc<dyn HttpFetcher>,
+    segments: AtomicUsize,
+    interrupt_at: usize,
+    interrupt: Notify,
+}
+
+impl Counting {
+    fn new(config: &Config, interrupt_at: usize) -> Arc<Self> {
+        let inner = getcourse_downloader::fetcher(config).unwrap();
+        Arc::new(Self { inner, segments: AtomicUsize::new(0), interrupt_at, interrupt: Notify::new() })
+    }
+}
+
+impl HttpFetcher for Counting {
+    fn request<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        if url.contains("/seg") && self.segments.fetch_add(1, Ordering::SeqCst) + 1 == self.interrupt_at {
+            self.interrupt.notify_one();
+        }
+        self.inner.request(url, headers)
+    }
+
+    fn head<'a>(&'a self, url: &'a str, headers: &'a [(String, String)]) -> BoxFuture<'a, Result<Response>> {
+        self.inner.head(url, headers)
+    }
+}
+
+#[tokio::test]
+async fn resumes_after_an_interrupt() {
+    let fixture = start(FixtureOptions::default()).await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("video.ts");
+    let config = config(|config| config.concurrency = 1);
+
+    let first = Counting::new(&config, SEGMENTS / 2);
+    let download = Downloader::new(fixture.master_url()).config(config.clone()).fetcher(first.clone()).output(&output);
+    tokio::select! {
+        result = download.run() => panic!("the download was not interrupted: {:?}", result.map(|r| r.segments)),
+        _ = first.interrupt.notified() => {}
+    }
+    assert!(!output.exists(), "an interrupted download left its output behind");
+
+    let second = Counting::new(&config, 0);
+    Downloader::new(fixture.master_url()).config(config).fetcher(second.clone()).output(&output).run().await.unwrap();
+    let fetched = second.segments.load(Ordering::SeqCst);
+    assert!(fetched < SEGMENTS, "the second run fetched all {} segments again", fetched);
+    assert!(std::fs::read(&output).unwrap() == expected(), "the resumed download differs from the fixture's segments");
+}
+
+#[tokio::test]
+async fn zero_preview_is_an_error() {
+    let fixture = start(FixtureOptions::default()).await;
+    let dir = tempfile::tempdir().unwrap();
+    let download = Downloader::new(fixture.master_url()).config(config(|_| ())).preview(Duration::ZERO);
+    let error = download.output(dir.path().join("video.ts")).run().await.unwrap_err();
+    assert!(error.to_string().contains("No segments"), "unexpected error: {:#}", error);
+}